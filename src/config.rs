@@ -0,0 +1,174 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::ServerConfig;
+
+/// Settings read from `--config <file>`; any flag given on the command line wins
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub workers: Option<usize>,
+    /// Same `path` or `name=path` specs as `--model`, used when no `--model` is given
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub thresholds: Thresholds,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub max_request_size_mb: Option<u32>,
+    pub max_text_length: Option<usize>,
+    pub max_batch_size: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub rate_limit: Option<f64>,
+    pub predict_timeout_ms: Option<u64>,
+    pub shutdown_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Thresholds {
+    pub default_threshold: Option<f32>,
+    pub default_k: Option<u32>,
+    pub uncertainty_threshold: Option<f32>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        let config: Config = toml::from_str(&content).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+        config.validate().map_err(|e| format!("{}: {}", path, e))?;
+        Ok(config)
+    }
+
+    /// The same checks main applies to the matching flags
+    fn validate(&self) -> Result<(), String> {
+        if self.limits.max_batch_size == Some(0) {
+            return Err("max_batch_size must be at least 1".to_string());
+        }
+        if self.limits.max_connections_per_ip == Some(0) {
+            return Err("max_connections_per_ip must be at least 1".to_string());
+        }
+        if self.limits.predict_timeout_ms == Some(0) {
+            return Err("predict_timeout_ms must be at least 1".to_string());
+        }
+        if let Some(rate) = self.limits.rate_limit {
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(format!("invalid rate_limit: {}", rate));
+            }
+        }
+        if let Some(threshold) = self.thresholds.default_threshold {
+            crate::check_default_threshold(threshold)?;
+        }
+        if let Some(k) = self.thresholds.default_k {
+            crate::check_default_k(k)?;
+        }
+        if let Some(threshold) = self.thresholds.uncertainty_threshold {
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(format!("invalid uncertainty_threshold: {}", threshold));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy file values into `config`, skipping those whose flag `explicit` reports as
+    /// given on the command line
+    pub fn apply(&self, config: &mut ServerConfig, explicit: impl Fn(&str) -> bool) {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>, explicit: bool) {
+            if let (Some(value), false) = (value, explicit) {
+                *target = value.clone();
+            }
+        }
+        let (limits, target) = (&self.limits, &mut config.limits);
+        set(&mut target.max_request_size_mb, &limits.max_request_size_mb, explicit("max-request-size"));
+        set(&mut target.max_text_length, &limits.max_text_length, explicit("max-text-length"));
+        set(&mut target.max_batch_size, &limits.max_batch_size, explicit("max-batch-size"));
+        set(&mut target.shutdown_timeout_secs, &limits.shutdown_timeout_secs, explicit("shutdown-timeout"));
+        let max_connections_per_ip = limits.max_connections_per_ip.map(Some);
+        set(&mut target.max_connections_per_ip, &max_connections_per_ip, explicit("max-connections-per-ip"));
+        set(&mut target.rate_limit, &limits.rate_limit.map(Some), explicit("rate-limit"));
+        set(&mut target.predict_timeout_ms, &limits.predict_timeout_ms.map(Some), explicit("predict-timeout-ms"));
+        let thresholds = &self.thresholds;
+        set(&mut config.default_threshold, &thresholds.default_threshold, explicit("default-threshold"));
+        set(&mut config.default_k, &thresholds.default_k, explicit("default-k"));
+        set(
+            &mut config.uncertainty_threshold,
+            &thresholds.uncertainty_threshold,
+            explicit("uncertainty-threshold"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use crate::ServerConfig;
+
+    const SAMPLE: &str = r#"
+address = "0.0.0.0"
+port = 9000
+workers = 4
+models = ["default=models/cooking.model.bin"]
+
+[limits]
+max_request_size_mb = 16
+max_text_length = 4096
+max_batch_size = 256
+rate_limit = 50.0
+
+[thresholds]
+default_threshold = 0.25
+default_k = 3
+"#;
+
+    #[test]
+    fn test_config_file_applies_to_server_config() {
+        let file: Config = toml::from_str(SAMPLE).unwrap();
+        assert!(file.validate().is_ok());
+        assert_eq!(file.address.as_deref(), Some("0.0.0.0"));
+        assert_eq!(file.port, Some(9000));
+        assert_eq!(file.workers, Some(4));
+        assert_eq!(file.models, vec!["default=models/cooking.model.bin"]);
+
+        let mut config = ServerConfig::default();
+        file.apply(&mut config, |_| false);
+        assert_eq!(config.limits.max_request_size_mb, 16);
+        assert_eq!(config.limits.max_text_length, 4096);
+        assert_eq!(config.limits.max_batch_size, 256);
+        assert_eq!(config.limits.rate_limit, Some(50.0));
+        assert_eq!(config.default_threshold, 0.25);
+        assert_eq!(config.default_k, 3);
+        // 文件里没有的字段保持默认值
+        assert_eq!(config.uncertainty_threshold, ServerConfig::default().uncertainty_threshold);
+        assert_eq!(config.limits.predict_timeout_ms, None);
+    }
+
+    #[test]
+    fn test_command_line_overrides_config_file() {
+        let file: Config = toml::from_str(SAMPLE).unwrap();
+        let mut config = ServerConfig {
+            default_k: 7,
+            ..Default::default()
+        };
+        file.apply(&mut config, |flag| flag == "default-k");
+        assert_eq!(config.default_k, 7);
+        assert_eq!(config.default_threshold, 0.25);
+    }
+
+    #[test]
+    fn test_config_file_rejects_unknown_fields() {
+        assert!(toml::from_str::<Config>("[limits]\nmax_batch = 1\n").is_err());
+        let file: Config = toml::from_str("[limits]\nmax_batch_size = 0\n").unwrap();
+        assert!(file.validate().is_err());
+        // 与--default-k、PATCH /admin/config使用同一套校验
+        let file: Config = toml::from_str("[thresholds]\ndefault_k = 0\n").unwrap();
+        assert!(file.validate().is_err());
+    }
+}
@@ -1,65 +1,323 @@
+// 参数校验直接返回要回给客户端的Status
+#![allow(clippy::result_large_err)]
+
+use std::fs;
+use std::future::Future;
 use std::net::ToSocketAddrs;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Code, Request, Response, Status, Streaming};
+use tonic_health::proto::health_server::{Health, HealthServer};
 
-use fasttext::FastText;
-use futures::StreamExt;
-use tonic::transport::Server;
-use tonic::{Request, Response, Status, Streaming};
 
 
+/// `--address` prefix selecting a unix domain socket, as for the HTTP server
+const UNIX_PREFIX: &str = "unix:";
+/// Request metadata asking for the results computed before the deadline instead of an error
+const PARTIAL_RESULTS: &str = "x-partial-results";
+/// Request metadata setting k for the messages of a stream that don't set their own
+const STREAM_K: &str = "k";
+/// Request metadata setting the threshold for the messages of a stream that don't set their own
+const STREAM_THRESHOLD: &str = "threshold";
+/// Handlers stop this long before the client deadline, so tonic does not cancel them first
+const DEADLINE_MARGIN: Duration = Duration::from_millis(10);
 
 #[allow(non_camel_case_types)]
 mod proto {
     tonic::include_proto!("fasttext_serving");
 
-    pub(crate) const FILE_DESCRIPTOR_SET: &'static [u8] =
+    pub(crate) const FILE_DESCRIPTOR_SET: &[u8] =
         tonic::include_file_descriptor_set!("fasttext_serving_descriptor");
 }
 
 use proto::{
-    fasttext_serving_server as server, PredictRequest, PredictResponse, Prediction, SentenceVector,
-    SentenceVectorRequest, SentenceVectorResponse,
+    fasttext_serving_server as server, PredictBatchRequest, PredictRequest, PredictResponse, Prediction, SentenceVector,
+    SentenceVectorBatchRequest, SentenceVectorRequest, SentenceVectorResponse,
 };
 
 #[derive(Debug, Clone)]
 struct FastTextServingService {
-    model: Arc<FastText>,
+    model: Arc<crate::ServedModel>,
     config: Arc<crate::ServerConfig>,
 }
 
+/// Reject field values that are representable on the wire but meaningless to the model,
+/// e.g. clients built against an older proto sending `k = 0`
+fn validate_predict_request(req: &PredictRequest) -> Result<(), Status> {
+    validate_predict_options(req.k, req.threshold)
+}
+
+fn validate_predict_options(k: Option<u32>, threshold: Option<f32>) -> Result<(), Status> {
+    if k == Some(0) {
+        return Err(Status::invalid_argument("k must be at least 1, got 0"));
+    }
+    if let Some(threshold) = threshold {
+        if !threshold.is_finite() || !(0.0..=1.0).contains(&threshold) {
+            return Err(Status::invalid_argument(format!(
+                "threshold must be a finite number within [0, 1], got {}",
+                threshold
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn batch_too_large(max_batch_size: usize) -> Status {
+    Status::resource_exhausted(format!("stream exceeds the maximum batch size of {} messages", max_batch_size))
+}
+
+/// Add a message's text to the bytes its stream has sent so far, failing once the total passes
+/// `--grpc-max-stream-bytes`
+fn count_stream_bytes(total: &mut usize, text: &str, config: &crate::ServerConfig) -> Result<(), Status> {
+    *total += text.len();
+    match config.limits.grpc_max_stream_bytes {
+        Some(max) if *total > max => {
+            log::warn!("Rejecting gRPC stream past {} text bytes", max);
+            Err(Status::resource_exhausted(format!("stream exceeds the maximum of {} text bytes", max)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Client deadline carried in the `grpc-timeout` header, e.g. `250m` or `5S`
+fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let value = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// When a handler must stop working on the request, from the client's `grpc-timeout`
+fn request_deadline(metadata: &MetadataMap) -> Option<Instant> {
+    grpc_timeout(metadata).map(|timeout| Instant::now() + timeout.saturating_sub(DEADLINE_MARGIN))
+}
+
+fn wants_partial_results(metadata: &MetadataMap) -> bool {
+    metadata.get(PARTIAL_RESULTS).and_then(|value| value.to_str().ok()) == Some("true")
+}
+
+/// Stream-wide k and threshold from the `k`/`threshold` request metadata
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct StreamDefaults {
+    k: Option<u32>,
+    threshold: Option<f32>,
+}
+
+impl StreamDefaults {
+    fn from_metadata(metadata: &MetadataMap) -> Result<Self, Status> {
+        fn parse<T: std::str::FromStr>(metadata: &MetadataMap, key: &str) -> Result<Option<T>, Status> {
+            let value = match metadata.get(key) {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            match value.to_str().ok().and_then(|value| value.parse().ok()) {
+                Some(parsed) => Ok(Some(parsed)),
+                None => Err(Status::invalid_argument(format!("invalid {} metadata: {:?}", key, value))),
+            }
+        }
+        let defaults = StreamDefaults {
+            k: parse(metadata, STREAM_K)?,
+            threshold: parse(metadata, STREAM_THRESHOLD)?,
+        };
+        validate_predict_options(defaults.k, defaults.threshold)?;
+        Ok(defaults)
+    }
+
+    /// Fill in the options `req` leaves unset
+    fn apply(&self, mut req: PredictRequest) -> PredictRequest {
+        req.k = req.k.or(self.k);
+        req.threshold = req.threshold.or(self.threshold);
+        req
+    }
+}
+
+fn deadline_exceeded() -> Status {
+    Status::deadline_exceeded("deadline exceeded before all texts were predicted")
+}
+
+/// Next message of a client stream, or `deadline_exceeded` if the deadline passes while waiting
+async fn next_before<S, T>(stream: &mut S, deadline: Option<Instant>) -> Result<Option<T>, Status>
+where
+    S: Stream<Item = Result<T, Status>> + Unpin,
+{
+    let next = match deadline {
+        // 消息源源不断时也要在deadline处停下
+        Some(deadline) if Instant::now() >= deadline => return Err(deadline_exceeded()),
+        Some(deadline) => tokio::time::timeout_at(deadline.into(), stream.next())
+            .await
+            .map_err(|_| deadline_exceeded())?,
+        None => stream.next().await,
+    };
+    next.transpose()
+}
+
+/// End a client stream on `status`, unless it is the deadline and the client asked for partial results
+fn stop_stream(status: Status, partial: bool, count: usize) -> Result<(), Status> {
+    if partial && status.code() == Code::DeadlineExceeded {
+        log::warn!("gRPC stream reached its deadline, returning {} partial results", count);
+        Ok(())
+    } else {
+        Err(status)
+    }
+}
+
+/// Requested k clamped to `--max-k`; gRPC has no response header for it, so the clamp is only logged
+fn capped_k(k: u32, config: &crate::ServerConfig) -> u32 {
+    if k > config.limits.max_k {
+        log::warn!("Requested k {} exceeds the maximum, capped to {}", k, config.limits.max_k);
+        return config.limits.max_k;
+    }
+    k
+}
+
+/// Time one text may take: what is left of the client deadline, else `--predict-timeout-ms`
+fn text_timeout(deadline: Option<Instant>, config: &crate::ServerConfig) -> Result<Option<Duration>, Status> {
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) => Ok(Some(remaining)),
+            None => Err(deadline_exceeded()),
+        },
+        None => Ok(config.limits.predict_timeout_ms.map(Duration::from_millis)),
+    }
+}
+
+/// `predict_one_safe` on a blocking thread when a timeout applies
+async fn predict_text(
+    model: &Arc<crate::ServedModel>,
+    config: &crate::ServerConfig,
+    settings: crate::ModelSettings<'_>,
+    text: &str,
+    k: u32,
+    threshold: f32,
+    timeout: Option<Duration>,
+) -> Result<(Vec<String>, Vec<f32>), crate::PredictError> {
+    let input = crate::prepare_input(text, config);
+    let (max_text_length, reject_nan) = (settings.max_text_length, config.reject_nan);
+    let timeout = match timeout {
+        None => {
+            let label_prefix = settings.label_prefix;
+            return crate::predict_one_safe(model, &input, k, threshold, max_text_length, label_prefix, reject_nan);
+        }
+        Some(timeout) => timeout,
+    };
+    let model = model.clone();
+    let input = input.into_owned();
+    let label_prefix = settings.label_prefix.map(str::to_string);
+    let task = tokio::task::spawn_blocking(move || {
+        crate::predict_one_safe(&model, &input, k, threshold, max_text_length, label_prefix.as_deref(), reject_nan)
+    });
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(prediction)) => prediction,
+        Ok(Err(e)) => Err(crate::PredictError::ModelError(e.to_string())),
+        Err(_) => Err(crate::PredictError::Timeout(timeout.as_millis() as u64)),
+    }
+}
+
+/// Placeholder returned in place of a failed prediction so the batch keeps its order
+fn error_marker(e: &crate::PredictError, config: &crate::ServerConfig) -> Prediction {
+    let (labels, probs) = config.error_marker(e);
+    Prediction { labels, probs }
+}
+
+/// Predict the `index`-th message of a stream, `stream_bytes` counting the text sent before it;
+/// invalid options and overlong streams end the stream with an error, a failed prediction becomes
+/// an error marker. The flag reports such a failure.
+async fn predict_message(
+    model: &Arc<crate::ServedModel>,
+    config: &crate::ServerConfig,
+    settings: crate::ModelSettings<'_>,
+    req: PredictRequest,
+    index: usize,
+    stream_bytes: &mut usize,
+    deadline: Option<Instant>,
+) -> Result<(Prediction, bool), Status> {
+    if index >= config.limits.max_batch_size {
+        log::warn!("Rejecting gRPC predict stream longer than {} messages", config.limits.max_batch_size);
+        return Err(batch_too_large(config.limits.max_batch_size));
+    }
+    count_stream_bytes(stream_bytes, &req.text, config)?;
+    if let Err(status) = validate_predict_request(&req) {
+        log::warn!("Rejecting gRPC predict request #{}: {}", index, status.message());
+        return Err(status);
+    }
+    let text = req.text;
+    let k = capped_k(req.k.unwrap_or(settings.default_k), config);
+    let threshold = req.threshold.unwrap_or(settings.default_threshold);
+
+    // 客户端设置了deadline时以剩余时间为准，否则使用--predict-timeout-ms
+    let timeout = text_timeout(deadline, config)?;
+    match predict_text(model, config, settings, &text, k, threshold, timeout).await {
+        Ok((labels, probs)) => Ok((Prediction { labels, probs }, false)),
+        // 超时是因为客户端的deadline到了，剩下的文本也不必再处理
+        Err(crate::PredictError::Timeout(_)) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+            Err(deadline_exceeded())
+        }
+        Err(e) => {
+            log::warn!("gRPC prediction failed for text (length: {}): {}", text.len(), e);
+            // 返回错误标记而不是失败整个请求
+            Ok((error_marker(&e, config), true))
+        }
+    }
+}
+
 #[tonic::async_trait]
 impl server::FasttextServing for FastTextServingService {
+    type predict_streamStream = BoxStream<'static, Result<PredictResponse, Status>>;
+    type sentence_vector_streamStream = BoxStream<'static, Result<SentenceVector, Status>>;
+
     async fn predict(
         &self,
         request: Request<Streaming<PredictRequest>>,
     ) -> Result<Response<PredictResponse>, Status> {
-        let stream = request.into_inner();
-        futures::pin_mut!(stream);
+        let deadline = request_deadline(request.metadata());
+        let partial = wants_partial_results(request.metadata());
+        let defaults = StreamDefaults::from_metadata(request.metadata())?;
+        let mut stream = request.into_inner();
         let model = self.model.clone();
+        let config = &self.config;
+        let settings = config.for_model(crate::DEFAULT_MODEL);
         let mut predictions = Vec::new();
+        let mut stream_bytes = 0;
         let mut processed_count = 0;
         let mut error_count = 0;
         
-        while let Some(req) = stream.next().await {
-            let req = req?;
-            let text = req.text;
-            let k = req.k.unwrap_or(1);
-            let threshold = req.threshold.unwrap_or(self.config.default_threshold);
-            
-            match crate::predict_one_safe(&model, &text, k, threshold, self.config.max_text_length) {
-                Ok((labels, probs)) => {
-            predictions.push(Prediction { labels, probs });
-                    processed_count += 1;
+        loop {
+            let req = match next_before(&mut stream, deadline).await {
+                Ok(Some(req)) => req,
+                Ok(None) => break,
+                Err(status) => {
+                    stop_stream(status, partial, predictions.len())?;
+                    break;
                 }
-                Err(e) => {
-                    log::warn!("gRPC prediction failed for text (length: {}): {}", text.len(), e);
-                    // 返回错误标记而不是失败整个请求
-                    predictions.push(Prediction { 
-                        labels: vec!["error".to_string()], 
-                        probs: vec![0.0] 
-                    });
-                    error_count += 1;
+            };
+            let index = predictions.len();
+            let req = defaults.apply(req);
+            let predicted = predict_message(&model, config, settings, req, index, &mut stream_bytes, deadline).await;
+            let (prediction, failed) = match predicted {
+                Ok(result) => result,
+                Err(status) => {
+                    stop_stream(status, partial, predictions.len())?;
+                    break;
                 }
+            };
+            predictions.push(prediction);
+            if failed {
+                error_count += 1;
+            } else {
+                processed_count += 1;
             }
         }
         
@@ -72,32 +330,118 @@ impl server::FasttextServing for FastTextServingService {
         Ok(Response::new(PredictResponse { predictions }))
     }
 
+    async fn predict_stream(
+        &self,
+        request: Request<Streaming<PredictRequest>>,
+    ) -> Result<Response<Self::predict_streamStream>, Status> {
+        let deadline = request_deadline(request.metadata());
+        let defaults = StreamDefaults::from_metadata(request.metadata())?;
+        let model = self.model.clone();
+        let config = self.config.clone();
+        // 逐条读取、逐条返回，出错后结束响应流
+        let responses = futures::stream::unfold(
+            (request.into_inner(), 0, 0, false),
+            move |(mut stream, index, mut stream_bytes, done)| {
+                let (model, config) = (model.clone(), config.clone());
+                async move {
+                    if done {
+                        return None;
+                    }
+                    let result = match next_before(&mut stream, deadline).await {
+                        Ok(Some(req)) => {
+                            let req = defaults.apply(req);
+                            let settings = config.for_model(crate::DEFAULT_MODEL);
+                            predict_message(&model, &config, settings, req, index, &mut stream_bytes, deadline).await
+                        }
+                        Ok(None) => return None,
+                        Err(status) => Err(status),
+                    };
+                    let done = result.is_err();
+                    let response = result.map(|(prediction, _)| PredictResponse {
+                        predictions: vec![prediction],
+                    });
+                    Some((response, (stream, index + 1, stream_bytes, done)))
+                }
+            },
+        );
+        Ok(Response::new(responses.boxed()))
+    }
+
+    async fn predict_batch(
+        &self,
+        request: Request<PredictBatchRequest>,
+    ) -> Result<Response<PredictResponse>, Status> {
+        let deadline = request_deadline(request.metadata());
+        let req = request.into_inner();
+        let config = &self.config;
+        let settings = config.for_model(crate::DEFAULT_MODEL);
+        if req.texts.len() > config.limits.max_batch_size {
+            log::warn!("Rejecting gRPC predict batch of {} texts", req.texts.len());
+            return Err(batch_too_large(config.limits.max_batch_size));
+        }
+        validate_predict_options(req.k, req.threshold)?;
+        let k = capped_k(req.k.unwrap_or(settings.default_k), config);
+        let threshold = req.threshold.unwrap_or(settings.default_threshold);
+
+        let mut predictions = Vec::with_capacity(req.texts.len());
+        let mut error_count = 0;
+        for text in &req.texts {
+            let timeout = text_timeout(deadline, config)?;
+            match predict_text(&self.model, config, settings, text, k, threshold, timeout).await {
+                Ok((labels, probs)) => predictions.push(Prediction { labels, probs }),
+                Err(e) => {
+                    log::warn!("gRPC prediction failed for text (length: {}): {}", text.len(), e);
+                    predictions.push(error_marker(&e, config));
+                    error_count += 1;
+                }
+            }
+        }
+
+        if error_count > 0 {
+            log::warn!("gRPC batch processing completed with {} errors out of {} texts", error_count, req.texts.len());
+        } else {
+            log::info!("gRPC batch processing completed successfully: {} texts", req.texts.len());
+        }
+        Ok(Response::new(PredictResponse { predictions }))
+    }
+
     async fn sentence_vector(
         &self,
         request: Request<Streaming<SentenceVectorRequest>>,
     ) -> Result<Response<SentenceVectorResponse>, Status> {
-        let stream = request.into_inner();
-        futures::pin_mut!(stream);
+        if let Some(message) = self.model.quantized_unsupported("Sentence vectors") {
+            return Err(Status::failed_precondition(message));
+        }
+        let deadline = request_deadline(request.metadata());
+        let partial = wants_partial_results(request.metadata());
+        let mut stream = request.into_inner();
         let mut vectors = Vec::new();
         let model = self.model.clone();
+        let mut stream_bytes = 0;
         let mut processed_count = 0;
         let mut error_count = 0;
         
-        while let Some(req) = stream.next().await {
-            let req = req?;
-            let text = req.text;
-            
-            match model.get_sentence_vector(&text) {
-                Ok(values) => {
-            vectors.push(SentenceVector { values });
-                    processed_count += 1;
-                }
-                Err(e) => {
-                    log::warn!("gRPC sentence vector failed for text (length: {}): {}", text.len(), e);
-                    // 返回零向量而不是失败
-                    vectors.push(SentenceVector { values: vec![0.0; self.config.default_vector_dim] });
-                    error_count += 1;
+        loop {
+            let req = match next_before(&mut stream, deadline).await {
+                Ok(Some(req)) => req,
+                Ok(None) => break,
+                Err(status) => {
+                    stop_stream(status, partial, vectors.len())?;
+                    break;
                 }
+            };
+            let max_batch_size = self.config.limits.max_batch_size;
+            if processed_count + error_count >= max_batch_size {
+                log::warn!("Rejecting gRPC sentence vector stream longer than {} messages", max_batch_size);
+                return Err(batch_too_large(max_batch_size));
+            }
+            count_stream_bytes(&mut stream_bytes, &req.text, &self.config)?;
+            let (vector, failed) = sentence_vector_message(&model, &self.config, &req.text);
+            vectors.push(vector);
+            if failed {
+                error_count += 1;
+            } else {
+                processed_count += 1;
             }
         }
         
@@ -109,31 +453,798 @@ impl server::FasttextServing for FastTextServingService {
         
         Ok(Response::new(SentenceVectorResponse { vectors }))
     }
+
+    async fn sentence_vector_stream(
+        &self,
+        request: Request<SentenceVectorBatchRequest>,
+    ) -> Result<Response<Self::sentence_vector_streamStream>, Status> {
+        if let Some(message) = self.model.quantized_unsupported("Sentence vectors") {
+            return Err(Status::failed_precondition(message));
+        }
+        let texts = request.into_inner().texts;
+        if texts.len() > self.config.limits.max_batch_size {
+            log::warn!("Rejecting gRPC sentence vector batch of {} texts", texts.len());
+            return Err(batch_too_large(self.config.limits.max_batch_size));
+        }
+        let (model, config) = (self.model.clone(), self.config.clone());
+        // 按输入顺序逐条计算，算完一条发一条
+        let vectors =
+            futures::stream::iter(texts).map(move |text| Ok(sentence_vector_message(&model, &config, &text).0));
+        Ok(Response::new(vectors.boxed()))
+    }
 }
 
-pub(crate) fn runserver(model: FastText, address: &str, port: u16, num_threads: usize, config: crate::ServerConfig) {
+/// Sentence vector for one text, the zero vector when it fails; the flag tells whether it failed
+fn sentence_vector_message(
+    model: &crate::ServedModel,
+    config: &crate::ServerConfig,
+    text: &str,
+) -> (SentenceVector, bool) {
+    let input = crate::prepare_input(text, config);
+    match crate::sentence_vector_safe(model, &input, config.reject_nan) {
+        Ok(values) => (SentenceVector { values }, false),
+        Err(e) => {
+            log::warn!("gRPC sentence vector failed for text (length: {}): {}", text.len(), e);
+            // 返回零向量而不是失败
+            (SentenceVector { values: vec![0.0; model.dimension] }, true)
+        }
+    }
+}
+
+/// Resolves on SIGINT, or SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Drive `serve` to completion, but once `draining` resolves give it at most `grace` to finish.
+/// Returns `None` if in-flight streams had to be cut off.
+async fn drain<F, D>(serve: F, draining: D, grace: Duration) -> Option<F::Output>
+where
+    F: Future,
+    D: Future<Output = ()>,
+{
+    futures::pin_mut!(serve);
+    tokio::select! {
+        output = &mut serve => return Some(output),
+        _ = draining => {}
+    }
+    tokio::time::timeout(grace, serve).await.ok()
+}
+
+/// Standard `grpc.health.v1.Health` service; the model is loaded before serving starts
+/// and gRPC has no reload, so the service is SERVING for its whole lifetime
+async fn health_service() -> HealthServer<impl Health> {
+    let (mut reporter, service) = tonic_health::server::health_reporter();
+    reporter
+        .set_serving::<server::FasttextServingServer<FastTextServingService>>()
+        .await;
+    service
+}
+
+/// Listen on the unix domain socket at `path`, replacing a stale socket file left by a previous run
+#[cfg(unix)]
+fn bind_unix(path: &str) -> std::io::Result<tokio_stream::wrappers::UnixListenerStream> {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    Ok(tokio_stream::wrappers::UnixListenerStream::new(listener))
+}
+
+/// TLS identity from PEM encoded certificate chain and private key files
+fn load_tls_config(cert_path: &str, key_path: &str) -> Result<ServerTlsConfig, String> {
+    let cert = fs::read(cert_path).map_err(|e| format!("{}: {}", cert_path, e))?;
+    let key = fs::read(key_path).map_err(|e| format!("{}: {}", key_path, e))?;
+    Ok(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
+}
+
+/// HTTP/2 and TCP keepalive from `--grpc-keepalive-interval`, `--grpc-keepalive-timeout` and `--tcp-keepalive`,
+/// so idle streams aren't dropped by proxies and load balancers
+fn with_keepalive(builder: Server, limits: &crate::Limits) -> Server {
+    let secs = |secs: Option<u64>| secs.map(Duration::from_secs);
+    builder
+        .http2_keepalive_interval(secs(limits.grpc_keepalive_interval_secs))
+        .http2_keepalive_timeout(secs(limits.grpc_keepalive_timeout_secs))
+        .tcp_keepalive(secs(limits.tcp_keepalive_secs))
+}
+
+pub(crate) fn runserver(
+    model: Arc<crate::ServedModel>,
+    address: &str,
+    port: u16,
+    num_threads: usize,
+    config: crate::ServerConfig,
+) {
+    if address.contains(',') {
+        log::error!("The gRPC server listens on a single address, got {}", address);
+        std::process::exit(1);
+    }
     let reflection_service = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
         .build()
         .unwrap();
+    let grace = Duration::from_secs(config.limits.shutdown_timeout_secs);
+    let mut builder = with_keepalive(Server::builder(), &config.limits);
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        // 证书和私钥在这里才会被真正解析
+        let tls_config = load_tls_config(cert, key).unwrap_or_else(|e| {
+            log::error!("Failed to load TLS certificate: {}", e);
+            std::process::exit(1);
+        });
+        builder = builder.tls_config(tls_config).unwrap_or_else(|e| {
+            log::error!("Failed to build TLS identity from {} and {}: {}", cert, key, e);
+            std::process::exit(1);
+        });
+        log::info!("Serving gRPC over TLS with certificate {}", cert);
+    }
+    let (stack_size, blocking_threads) = (config.worker_stack_size, config.blocking_threads);
     let instance = FastTextServingService {
-        model: Arc::new(model),
+        model,
         config: Arc::new(config),
     };
     let service = server::FasttextServingServer::new(instance);
-    let addr = (address, port).to_socket_addrs().unwrap().next().unwrap();
-    let server = Server::builder()
+    let unix_path = address.strip_prefix(UNIX_PREFIX).map(str::to_string);
+    let addr = match unix_path {
+        Some(_) => None,
+        // 方括号形式的IPv6地址与HTTP服务器一致
+        None => {
+            let host = address.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(address);
+            Some((host, port).to_socket_addrs().unwrap().next().unwrap())
+        }
+    };
+    let server = builder
         .add_service(reflection_service)
         .add_service(service);
-    log::info!("Listening on {}:{}", address, port);
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .worker_threads(num_threads)
+    match &unix_path {
+        Some(path) => log::info!("Listening on {}{}", UNIX_PREFIX, path),
+        None => log::info!("Listening on {}:{}", address, port),
+    }
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all().worker_threads(num_threads);
+    if let Some(size) = stack_size {
+        runtime.thread_stack_size(size);
+    }
+    if let Some(threads) = blocking_threads {
+        runtime.max_blocking_threads(threads);
+    }
+    runtime
         .build()
         .unwrap()
         .block_on(async {
-            server.serve(addr).await.unwrap();
+            let server = server.add_service(health_service().await);
+            let (draining_tx, draining_rx) = tokio::sync::oneshot::channel::<()>();
+            let signal = async move {
+                shutdown_signal().await;
+                log::info!("Shutdown signal received, draining in-flight streams for up to {:?}", grace);
+                let _ = draining_tx.send(());
+            };
+            let draining = async move {
+                let _ = draining_rx.await;
+            };
+            let drained = match (addr, unix_path) {
+                (Some(addr), _) => drain(server.serve_with_shutdown(addr, signal), draining, grace).await,
+                #[cfg(unix)]
+                (None, Some(path)) => {
+                    let incoming = bind_unix(&path).expect("bind failed");
+                    drain(server.serve_with_incoming_shutdown(incoming, signal), draining, grace).await
+                }
+                _ => panic!("Unix domain socket is not supported on this platform"),
+            };
+            match drained {
+                Some(result) => {
+                    result.unwrap();
+                    log::info!("gRPC server stopped");
+                }
+                None => log::warn!("Shutdown timeout reached, closing remaining gRPC streams"),
+            }
         });
 }
 
-// FIXME: add test case
+#[cfg(test)]
+mod test {
+    use super::{
+        drain, error_marker, grpc_timeout, health_service, load_tls_config, proto, server, validate_predict_request,
+        with_keepalive, FastTextServingService, PredictRequest, PARTIAL_RESULTS, STREAM_K, STREAM_THRESHOLD,
+    };
+    use fasttext::FastText;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use futures::StreamExt;
+    use tonic::transport::{Channel, Endpoint, Server, Uri};
+    use tonic::metadata::MetadataMap;
+    use tonic::Code;
+
+    use crate::{Limits, ServedModel, ServerConfig};
+
+    type Client = proto::fasttext_serving_client::FasttextServingClient<Channel>;
+
+    fn load_model() -> FastText {
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        model
+    }
+
+    /// Serve `model` on a free local port and connect a client to it
+    async fn spawn_server(model: FastText, config: ServerConfig) -> Client {
+        let addr = spawn_service(model, config).await;
+        Client::connect(format!("http://{}", addr)).await.unwrap()
+    }
+
+    async fn spawn_service(model: FastText, config: ServerConfig) -> std::net::SocketAddr {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let service = FastTextServingService {
+            model: Arc::new(ServedModel::new(model)),
+            config: Arc::new(config),
+        };
+        let health = health_service().await;
+        tokio::spawn(
+            Server::builder()
+                .add_service(health)
+                .add_service(server::FasttextServingServer::new(service))
+                .serve(addr),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_drain_completes_within_grace() {
+        let serve = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            "done"
+        };
+        let draining = async {};
+        let output = drain(serve, draining, Duration::from_secs(1)).await;
+        assert_eq!(output, Some("done"));
+    }
+
+    #[tokio::test]
+    async fn test_drain_cuts_off_after_grace() {
+        let serve = tokio::time::sleep(Duration::from_secs(30));
+        let draining = async {};
+        let output = drain(serve, draining, Duration::from_millis(50)).await;
+        assert!(output.is_none());
+    }
+
+    #[test]
+    fn test_error_marker_matches_config() {
+        let config = ServerConfig {
+            keep_label_prefix: true,
+            marker_message: true,
+            ..Default::default()
+        };
+        let e = crate::PredictError::Timeout(5);
+        let prediction = error_marker(&e, &config);
+        assert_eq!((prediction.labels, prediction.probs), config.error_marker(&e));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_serve_both_protocols() {
+        use std::io::{Read, Write};
+        let free_port = || std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (http_port, grpc_port) = (free_port(), free_port());
+        let models = vec![(crate::DEFAULT_MODEL.to_string(), Arc::new(ServedModel::new(load_model())))];
+        let config = ServerConfig::default();
+        std::thread::spawn(move || crate::serve_both(models, "127.0.0.1", http_port, grpc_port, 1, config));
+        let text = "Which baking dish is best to bake a banana bread?";
+
+        // 两个服务在各自的线程里启动，等HTTP端口可连接
+        let mut connection = None;
+        for _ in 0..50 {
+            match std::net::TcpStream::connect(("127.0.0.1", http_port)) {
+                Ok(stream) => {
+                    connection = Some(stream);
+                    break;
+                }
+                Err(_) => std::thread::sleep(Duration::from_millis(100)),
+            }
+        }
+        let mut stream = connection.expect("HTTP server did not start");
+        let body = format!("[{:?}]", text);
+        write!(
+            stream,
+            "POST /predict HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(!response.contains("error"), "{}", response);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let predictions = runtime.block_on(async {
+            let mut client = Client::connect(format!("http://127.0.0.1:{}", grpc_port)).await.unwrap();
+            let requests = vec![PredictRequest {
+                text: text.to_string(),
+                k: None,
+                threshold: None,
+            }];
+            client.predict(futures::stream::iter(requests)).await.unwrap().into_inner().predictions
+        });
+        assert_eq!(predictions[0].labels.len(), 1);
+        assert_ne!(predictions[0].labels[0], "error");
+    }
+
+    #[test]
+    fn test_validate_predict_request() {
+        let request = |k: Option<u32>, threshold: Option<f32>| PredictRequest {
+            text: "banana bread".to_string(),
+            k,
+            threshold,
+        };
+        assert!(validate_predict_request(&request(Some(3), Some(0.5))).is_ok());
+        assert!(validate_predict_request(&request(None, None)).is_ok());
+
+        let status = validate_predict_request(&request(Some(0), None)).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().contains("k must be at least 1"));
+
+        let status = validate_predict_request(&request(None, Some(f32::NAN))).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().contains("threshold"));
+        let status = validate_predict_request(&request(None, Some(1.5))).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[cfg(feature = "http")]
+    #[tokio::test]
+    async fn test_fallback_vector_matches_http() {
+        let model = load_model();
+        let dim = model.get_dimension() as usize;
+        // 故意配置一个与模型不一致的维度
+        let config = ServerConfig {
+            default_vector_dim: dim + 7,
+            ..Default::default()
+        };
+        let texts = vec!["banana bread".to_string(), "invalid\u{0}text".to_string()];
+
+        let served = ServedModel::new(load_model());
+        let http_vectors =
+            crate::http::sentence_vectors(&served, &config, &crate::metrics::Metrics::new(), &texts, "-");
+
+        let mut client = spawn_server(model, config).await;
+        let requests: Vec<_> = texts
+            .iter()
+            .map(|text| proto::SentenceVectorRequest { text: text.clone() })
+            .collect();
+        let grpc_vectors = client
+            .sentence_vector(futures::stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner()
+            .vectors;
+
+        assert_eq!(http_vectors.len(), grpc_vectors.len());
+        for (http, grpc) in http_vectors.iter().zip(&grpc_vectors) {
+            assert_eq!(http.len(), dim);
+            assert_eq!(grpc.values.len(), dim);
+        }
+        assert!(grpc_vectors[1].values.iter().all(|x| *x == 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_sentence_vector_stream() {
+        let mut client = spawn_server(load_model(), ServerConfig::default()).await;
+        let texts = vec!["banana bread".to_string(), "invalid\u{0}text".to_string(), "boil an egg".to_string()];
+        let requests: Vec<_> = texts
+            .iter()
+            .map(|text| proto::SentenceVectorRequest { text: text.clone() })
+            .collect();
+        let expected = client
+            .sentence_vector(futures::stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner()
+            .vectors;
+
+        let request = proto::SentenceVectorBatchRequest { texts };
+        let mut stream = client.sentence_vector_stream(request).await.unwrap().into_inner();
+        let mut streamed = Vec::new();
+        while let Some(vector) = stream.message().await.unwrap() {
+            streamed.push(vector);
+        }
+        // 每条输入一个向量，顺序不变，失败的一条是零向量
+        assert_eq!(streamed, expected);
+        assert!(streamed[1].values.iter().all(|x| *x == 0.0));
+        assert!(streamed[0].values.iter().any(|x| *x != 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_stream_rejected_past_max_batch_size() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_batch_size: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut client = spawn_server(load_model(), config).await;
+        let requests: Vec<_> = (0..3)
+            .map(|_| PredictRequest {
+                text: "banana bread".to_string(),
+                k: None,
+                threshold: None,
+            })
+            .collect();
+        let status = client
+            .predict(futures::stream::iter(requests))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let requests: Vec<_> = (0..3)
+            .map(|_| proto::SentenceVectorRequest {
+                text: "banana bread".to_string(),
+            })
+            .collect();
+        let status = client
+            .sentence_vector(futures::stream::iter(requests))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_stream_aborted_past_max_stream_bytes() {
+        let config = ServerConfig {
+            limits: Limits {
+                grpc_max_stream_bytes: Some(20),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut client = spawn_server(load_model(), config).await;
+        // 每条12字节，第二条时累计超过20字节
+        let requests: Vec<_> = (0..3)
+            .map(|_| PredictRequest {
+                text: "banana bread".to_string(),
+                k: None,
+                threshold: None,
+            })
+            .collect();
+        let status = client
+            .predict(futures::stream::iter(requests.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.message().contains("20 text bytes"));
+
+        let mut stream = client
+            .predict_stream(futures::stream::iter(requests.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(stream.message().await.unwrap().is_some());
+        let status = stream.message().await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let within = requests[..1].to_vec();
+        let response = client.predict(futures::stream::iter(within)).await.unwrap().into_inner();
+        assert_eq!(response.predictions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_keepalive() {
+        let limits = Limits {
+            grpc_keepalive_interval_secs: Some(1),
+            grpc_keepalive_timeout_secs: Some(1),
+            tcp_keepalive_secs: Some(1),
+            ..Default::default()
+        };
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let service = FastTextServingService {
+            model: Arc::new(ServedModel::new(load_model())),
+            config: Arc::new(ServerConfig::default()),
+        };
+        tokio::spawn(
+            with_keepalive(Server::builder(), &limits)
+                .add_service(server::FasttextServingServer::new(service))
+                .serve(addr),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut client = Client::connect(format!("http://{}", addr)).await.unwrap();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let requests = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|req| (req, rx)) });
+        let mut responses = client.predict_stream(requests).await.unwrap().into_inner();
+        let request = || PredictRequest {
+            text: "Which baking dish is best to bake a banana bread?".to_string(),
+            k: None,
+            threshold: None,
+        };
+        tx.send(request()).unwrap();
+        assert!(responses.message().await.unwrap().is_some());
+        // 空闲超过几个ping周期后，流仍然可用
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        tx.send(request()).unwrap();
+        assert!(responses.message().await.unwrap().is_some());
+        drop(tx);
+        assert!(responses.message().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_defaults_from_metadata() {
+        let mut client = spawn_server(load_model(), ServerConfig::default()).await;
+        let text = "Which baking dish is best to bake a banana bread?";
+        let requests = vec![
+            PredictRequest {
+                text: text.to_string(),
+                k: None,
+                threshold: None,
+            },
+            PredictRequest {
+                text: text.to_string(),
+                k: Some(1),
+                threshold: None,
+            },
+            PredictRequest {
+                text: text.to_string(),
+                k: None,
+                threshold: Some(0.0),
+            },
+        ];
+        let stream_request = |k: &str, threshold: &str| {
+            let mut request = tonic::Request::new(futures::stream::iter(requests.clone()));
+            request.metadata_mut().insert(STREAM_K, k.parse().unwrap());
+            request.metadata_mut().insert(STREAM_THRESHOLD, threshold.parse().unwrap());
+            request
+        };
+        // 没有标签能超过1.0，只有自带threshold的消息有结果
+        let predictions = client.predict(stream_request("3", "1.0")).await.unwrap().into_inner().predictions;
+        let counts: Vec<usize> = predictions.iter().map(|p| p.labels.len()).collect();
+        assert_eq!(counts, vec![0, 0, 3]);
+
+        let mut responses = client.predict_stream(stream_request("3", "0")).await.unwrap().into_inner();
+        let mut counts = Vec::new();
+        while let Some(response) = responses.message().await.unwrap() {
+            counts.push(response.predictions[0].labels.len());
+        }
+        assert_eq!(counts, vec![3, 1, 3]);
+
+        let status = client.predict(stream_request("abc", "0")).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status = client.predict(stream_request("3", "2")).await.unwrap_err();
+        assert!(status.message().contains("threshold"));
+    }
+
+    #[tokio::test]
+    async fn test_predict_stream_pipelined() {
+        let mut client = spawn_server(load_model(), ServerConfig::default()).await;
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let requests = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|req| (req, rx)) });
+        let mut responses = client.predict_stream(requests).await.unwrap().into_inner();
+        // 收到上一条的结果后才发送下一条，服务端缓冲整个流时这里会超时
+        for k in 1..=3 {
+            tx.send(PredictRequest {
+                text: "Which baking dish is best to bake a banana bread?".to_string(),
+                k: Some(k),
+                threshold: None,
+            })
+            .unwrap();
+            let response = tokio::time::timeout(Duration::from_secs(5), responses.message())
+                .await
+                .expect("response was not sent incrementally")
+                .unwrap()
+                .unwrap();
+            assert_eq!(response.predictions.len(), 1);
+            assert_eq!(response.predictions[0].labels.len(), k as usize);
+        }
+
+        tx.send(PredictRequest {
+            text: "banana bread".to_string(),
+            k: Some(0),
+            threshold: None,
+        })
+        .unwrap();
+        let status = responses.message().await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_stream_stops_at_deadline() {
+        let mut client = spawn_server(load_model(), ServerConfig::default()).await;
+        // 每50ms发送一条，整个流要5秒才能发完
+        let slow_stream = || {
+            futures::stream::iter(0..100).then(|_| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                PredictRequest {
+                    text: "banana bread".to_string(),
+                    k: None,
+                    threshold: None,
+                }
+            })
+        };
+
+        let mut request = tonic::Request::new(slow_stream());
+        request.set_timeout(Duration::from_millis(300));
+        let started = Instant::now();
+        let status = client.predict(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+
+        let mut request = tonic::Request::new(slow_stream());
+        request.set_timeout(Duration::from_millis(300));
+        request.metadata_mut().insert(PARTIAL_RESULTS, "true".parse().unwrap());
+        let started = Instant::now();
+        let predictions = client.predict(request).await.unwrap().into_inner().predictions;
+        assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+        assert!(!predictions.is_empty() && predictions.len() < 100, "{} predictions", predictions.len());
+
+        let mut request = tonic::Request::new(slow_stream().map(|req| proto::SentenceVectorRequest { text: req.text }));
+        request.set_timeout(Duration::from_millis(300));
+        let status = client.sentence_vector(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+    }
+
+    #[test]
+    fn test_grpc_timeout_header() {
+        let timeout = |value: &str| {
+            let mut metadata = MetadataMap::new();
+            metadata.insert("grpc-timeout", value.parse().unwrap());
+            grpc_timeout(&metadata)
+        };
+        assert_eq!(timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(timeout("5S"), Some(Duration::from_secs(5)));
+        assert_eq!(timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(timeout("10x"), None);
+        assert_eq!(timeout("m"), None);
+        assert_eq!(grpc_timeout(&MetadataMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_unary_predict_batch() {
+        let mut client = spawn_server(load_model(), ServerConfig::default()).await;
+        let request = proto::PredictBatchRequest {
+            texts: vec![
+                "Which baking dish is best to bake a banana bread?".to_string(),
+                String::new(),
+            ],
+            k: Some(2),
+            threshold: None,
+        };
+        let predictions = client.predict_batch(request).await.unwrap().into_inner().predictions;
+        assert_eq!(predictions.len(), 2);
+        assert_eq!(predictions[0].labels.len(), 2);
+        assert_eq!(predictions[0].labels.len(), predictions[0].probs.len());
+        // 空文本返回错误标记，不影响其他文本
+        assert_eq!(predictions[1].labels, vec!["error"]);
+
+        let request = proto::PredictBatchRequest {
+            texts: vec!["banana bread".to_string()],
+            k: Some(0),
+            threshold: None,
+        };
+        let status = client.predict_batch(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_health_check_serving() {
+        use tonic_health::proto::health_check_response::ServingStatus;
+        use tonic_health::proto::health_client::HealthClient;
+        use tonic_health::proto::HealthCheckRequest;
+
+        let addr = spawn_service(load_model(), ServerConfig::default()).await;
+        let channel = Channel::from_shared(format!("http://{}", addr)).unwrap().connect().await.unwrap();
+        let mut client = HealthClient::new(channel);
+        for service in &["", "fasttext_serving.FasttextServing"] {
+            let response = client
+                .check(HealthCheckRequest {
+                    service: service.to_string(),
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(response.status, ServingStatus::Serving as i32);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake() {
+        use tonic::transport::{Certificate, ClientTlsConfig};
+
+        let tls_config = load_tls_config("tests/fixtures/tls/cert.pem", "tests/fixtures/tls/key.pem").unwrap();
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let service = FastTextServingService {
+            model: Arc::new(ServedModel::new(load_model())),
+            config: Arc::new(ServerConfig::default()),
+        };
+        tokio::spawn(
+            Server::builder()
+                .tls_config(tls_config)
+                .unwrap()
+                .add_service(server::FasttextServingServer::new(service))
+                .serve(addr),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let ca = std::fs::read("tests/fixtures/tls/cert.pem").unwrap();
+        let channel = Channel::from_shared(format!("https://localhost:{}", addr.port()))
+            .unwrap()
+            .tls_config(
+                ClientTlsConfig::new()
+                    .ca_certificate(Certificate::from_pem(ca))
+                    .domain_name("localhost"),
+            )
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let request = proto::PredictBatchRequest {
+            texts: vec!["banana bread".to_string()],
+            k: None,
+            threshold: None,
+        };
+        let predictions = Client::new(channel)
+            .predict_batch(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .predictions;
+        assert_eq!(predictions.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!("fasttext-serving-grpc-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        // 上次运行残留的socket文件会被替换
+        std::fs::write(&path, b"").unwrap();
+        let incoming = super::bind_unix(&path).unwrap();
+        let service = FastTextServingService {
+            model: Arc::new(ServedModel::new(load_model())),
+            config: Arc::new(ServerConfig::default()),
+        };
+        tokio::spawn(
+            Server::builder()
+                .add_service(server::FasttextServingServer::new(service))
+                .serve_with_incoming(incoming),
+        );
+
+        // URI只是占位，连接总是走unix socket
+        let socket = path.clone();
+        let channel = Endpoint::from_static("http://localhost")
+            .connect_with_connector(tower::service_fn(move |_: Uri| tokio::net::UnixStream::connect(socket.clone())))
+            .await
+            .unwrap();
+        let request = proto::PredictBatchRequest {
+            texts: vec!["banana bread".to_string()],
+            k: None,
+            threshold: None,
+        };
+        let predictions = Client::new(channel)
+            .predict_batch(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .predictions;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(predictions.len(), 1);
+    }
+}
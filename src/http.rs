@@ -1,15 +1,63 @@
+// 参数校验直接返回要回给客户端的HttpResponse
+#![allow(clippy::result_large_err)]
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::fs;
 use std::io;
+use std::net::ToSocketAddrs;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use actix_web::rt::System;
-use actix_web::{web, App, HttpServer, HttpResponse, Result as ActixResult};
-// use fasttext::FastText;  // 不再使用Rust FastText
+use actix_cors::Cors;
+use actix_multipart::Multipart;
+use actix_web::dev::Service;
+use actix_web::http::{header, KeepAlive};
+use actix_web::middleware::{Compress, Condition};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::Payload;
+use actix_web::{web, App, FromRequest, HttpMessage, HttpRequest, HttpServer, HttpResponse, Result as ActixResult};
+use arc_swap::ArcSwap;
+use fasttext::FastText;
+use futures::{stream, StreamExt, TryStreamExt};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use socket2::{Domain, Socket, Type};
+use tokio::sync::OwnedSemaphorePermit;
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+use crate::language::LanguageRouter;
+use crate::limits::{ConcurrencyLimiter, ConnectionLimiter, MemoryGuard, RateLimiter};
+use crate::metrics::Metrics;
+use crate::neighbors::WordIndex;
+use crate::prediction_cache::PredictionCache;
+use crate::telemetry;
+use crate::vector_cache::VectorCache;
 
 
 
-const UNIX_PREFIX: &'static str = "unix:";
+const UNIX_PREFIX: &str = "unix:";
+const OCTET_STREAM: &str = "application/octet-stream";
+const NDJSON: &str = "application/x-ndjson";
+/// Form field of `/predict-upload` holding the text file
+const UPLOAD_FIELD: &str = "file";
+const MSGPACK: &str = "application/msgpack";
+const REQUEST_ID: &str = "x-request-id";
+/// Response header carrying `--max-k` when the requested k was clamped to it
+const K_CAPPED: &str = "x-k-capped";
+/// Number of `/predict` results cut short by `--max-total-predictions`
+const PREDICTIONS_TRUNCATED: &str = "x-predictions-truncated";
+/// Alternatives to `?k=` and `?threshold=` for proxies that drop query strings
+const PREDICT_K: &str = "x-predict-k";
+const PREDICT_THRESHOLD: &str = "x-predict-threshold";
+/// Longest client-supplied request ID we'll echo back
+const MAX_REQUEST_ID_LEN: usize = 128;
 
 enum Address {
     IpPort(String, u16),
@@ -30,267 +78,7862 @@ impl FromStr for Address {
     fn from_str(string: &str) -> io::Result<Self> {
         #[cfg(unix)]
         {
-            if string.starts_with(UNIX_PREFIX) {
-                let address = &string[UNIX_PREFIX.len()..];
+            if let Some(address) = string.strip_prefix(UNIX_PREFIX) {
                 return Ok(Address::Unix(address.into()));
             }
         }
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "failed to resolve TCP address",
-        ))
+        Err(io::Error::other("failed to resolve TCP address"))
     }
 }
 
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Address::IpPort(ip, port) if ip.contains(':') => write!(f, "[{}]:{}", ip, port),
             Address::IpPort(ip, port) => write!(f, "{}:{}", ip, port),
             Address::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path),
         }
     }
 }
 
-#[derive(Deserialize, Debug, Default)]
+/// Parse a comma-separated `--address` list, checking that each host resolves.
+/// IPv6 literals may be bracketed, e.g. `[::]`
+fn parse_addresses(address: &str, port: u16) -> Result<Vec<Address>, String> {
+    address
+        .split(',')
+        .map(str::trim)
+        .map(|address| {
+            if address.is_empty() {
+                return Err("empty entry in the address list".to_string());
+            }
+            match Address::from((address, port)) {
+                Address::IpPort(host, port) => {
+                    let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(&host);
+                    (host, port)
+                        .to_socket_addrs()
+                        .map_err(|e| format!("invalid address {}: {}", address, e))?;
+                    Ok(Address::IpPort(host.to_string(), port))
+                }
+                unix => Ok(unix),
+            }
+        })
+        .collect()
+}
+
+/// Apply `--tcp-nodelay` to an accepted plain TCP connection; not every platform
+/// copies the option from the listening socket
+fn set_nodelay(conn: &dyn std::any::Any, nodelay: bool) {
+    if let Some(stream) = conn.downcast_ref::<actix_web::rt::net::TcpStream>() {
+        if let Err(e) = stream.set_nodelay(nodelay) {
+            log::warn!("Failed to set TCP_NODELAY: {}", e);
+        }
+    }
+}
+
+/// Listen on every address `host` resolves to. IPv6 sockets are IPv6-only,
+/// so `0.0.0.0` and `[::]` can share a port
+fn bind_tcp(host: &str, port: u16, nodelay: bool, backlog: u32) -> io::Result<Vec<std::net::TcpListener>> {
+    let mut listeners = Vec::new();
+    for addr in (host, port).to_socket_addrs()? {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        socket.set_nodelay(nodelay)?;
+        socket.bind(&addr.into())?;
+        socket.listen(backlog as i32)?;
+        listeners.push(socket.into());
+    }
+    Ok(listeners)
+}
+
+#[derive(Deserialize, Debug, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct PredictOptions {
+    /// Number of labels per text
     k: Option<u32>,
+    /// Minimum label probability
     threshold: Option<f32>,
+    /// Report tokens missing from the model's vocabulary
+    #[serde(default)]
+    oov_report: bool,
+    /// Wrap the results with batch-level aggregates
+    #[serde(default)]
+    summary: bool,
+    /// Report the normalized entropy of each prediction
+    #[serde(default)]
+    uncertainty: bool,
+    #[serde(default)]
+    #[param(inline)]
+    format: ResponseFormat,
+    /// Stream results as newline-delimited JSON
+    #[serde(default)]
+    stream: bool,
+    /// Answer 422 listing the failed texts instead of marking them with an `error` label
+    #[serde(default)]
+    strict: bool,
+    /// Comma-separated labels to keep, overriding `--label-filter`
+    labels: Option<String>,
+    /// Rescale the kept labels' scores to sum to 1
+    #[serde(default)]
+    renormalize: bool,
+    /// Round scores to this many decimal places
+    precision: Option<u32>,
+    /// Report each input's length in bytes and characters, as received
+    #[serde(default)]
+    include_length: bool,
+    /// Collapse repeated labels to their highest score, still returning up to k labels
+    #[serde(default)]
+    unique_labels: bool,
+    /// Sum the scores of hierarchical labels sharing their first this many `--label-separator` segments
+    group_depth: Option<usize>,
+    /// Seed for randomized post-processing, i.e. ordering labels with equal scores, so repeated
+    /// requests with the same seed get the same output
+    seed: Option<u64>,
+    /// Add a stable hash of the model, text and prediction parameters to each result
+    #[serde(default)]
+    fingerprint: bool,
+    /// Return only the labels or only the scores of each result
+    #[param(inline)]
+    fields: Option<ResultFields>,
+    /// Return `{"labels": [[...]], "scores": [[...]]}` row-aligned 2D arrays when every result
+    /// has the same number of labels, the per-item pairs otherwise
+    #[serde(default)]
+    matrix: bool,
+    /// Skip, mark or fail on texts that can't be predicted, overriding `--on-error`
+    #[param(inline)]
+    on_error: Option<crate::OnError>,
 }
 
-#[derive(Serialize)]
-struct PredictResult {
-    labels: Vec<String>,
-    scores: Vec<f32>,
+/// `?precision=` and `?normalize=`, for the endpoints returning vectors
+#[derive(Deserialize, Debug, Default)]
+struct VectorOptions {
+    precision: Option<u32>,
+    /// Scale each vector to unit L2 norm, zero vectors stay zero
+    #[serde(default)]
+    normalize: bool,
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
-    message: String,
+/// Largest `?precision=`, f32 only carries about 7 significant digits
+const MAX_PRECISION: u32 = 7;
+
+fn check_precision(precision: Option<u32>) -> Result<(), HttpResponse> {
+    match precision {
+        Some(precision) if precision > MAX_PRECISION => Err(bad_request(
+            "input_error",
+            format!("precision must be at most {}, got {}", MAX_PRECISION, precision),
+        )),
+        _ => Ok(()),
+    }
 }
 
-#[derive(Serialize)]
-struct HealthResponse {
-    status: String,
-    model_loaded: bool,
+/// Reject a k or threshold that parses but can't be meant, with the same rules as gRPC
+fn check_predict_options(k: Option<u32>, threshold: Option<f32>) -> Result<(), HttpResponse> {
+    if k == Some(0) {
+        return Err(bad_request("input_error", "k must be at least 1, got 0".to_string()));
+    }
+    match threshold {
+        // NaN/inf会让阈值比较全部失效，直接拒绝
+        Some(threshold) if !threshold.is_finite() || !(0.0..=1.0).contains(&threshold) => Err(bad_request(
+            "input_error",
+            format!("threshold must be a finite number within [0, 1], got {}", threshold),
+        )),
+        _ => Ok(()),
+    }
 }
 
-async fn health_check() -> ActixResult<HttpResponse> {
-    let response = HealthResponse {
-        status: "healthy".to_string(),
-        model_loaded: true,
-    };
-    Ok(HttpResponse::Ok().json(response))
+/// Validate the query's k, threshold and precision, then resolve k and threshold against the model's
+/// defaults with k capped to `--max-k`, for the endpoints that predict with one k/threshold per request
+fn resolve_predict_options(
+    options: &PredictOptions,
+    config: &crate::ServerConfig,
+    settings: crate::ModelSettings<'_>,
+) -> Result<(u32, f32), HttpResponse> {
+    check_predict_options(options.k, options.threshold)?;
+    check_precision(options.precision)?;
+    let k = options.k.unwrap_or(settings.default_k).min(config.limits.max_k);
+    Ok((k, options.threshold.unwrap_or(settings.default_threshold)))
 }
 
-async fn predict(
-    model: web::Data<FastText>,
-    config: web::Data<crate::ServerConfig>,
-    texts: web::Json<Vec<String>>,
-    options: web::Query<PredictOptions>,
-) -> ActixResult<HttpResponse> {
-    let k = options.k.unwrap_or(1);
-    let threshold = options.threshold.unwrap_or(config.default_threshold);
-    let text_count = texts.len();
-    
-    log::info!("Processing {} texts with k={}, threshold={}", text_count, k, threshold);
-    
-    if text_count == 0 {
-        return Ok(HttpResponse::Ok().json(Vec::<PredictResult>::new()));
+/// Round `values` to `precision` decimal places so they serialize as short decimals
+fn round_scores(values: &mut [f32], precision: Option<u32>) {
+    if let Some(precision) = precision {
+        let scale = 10f64.powi(precision as i32);
+        values.iter_mut().for_each(|value| *value = ((*value as f64 * scale).round() / scale) as f32);
     }
-    
-    // 使用安全的预测函数，避免单个文本错误导致整个批次失败
-    let mut results = Vec::with_capacity(text_count);
-    let mut success_count = 0;
-    let mut error_count = 0;
-    
-    for txt in texts.iter() {
-        match crate::predict_one_safe(model.get_ref(), txt, k, threshold, config.max_text_length) {
-            Ok((labels, probs)) => {
-                results.push(PredictResult {
-                    labels,
-                    scores: probs,
-                });
-                success_count += 1;
-            }
-            Err(e) => {
-                log::warn!("Prediction failed for text (length: {}): {}", txt.len(), e);
-                // 返回默认结果而不是失败
-                results.push(PredictResult {
-                    labels: vec!["error".to_string()],
-                    scores: vec![0.0],
-                });
-                error_count += 1;
-            }
+}
+
+/// Scale a vector to unit L2 norm, leaving zero vectors as they are
+fn normalize_vector(vector: &mut [f32]) {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+/// Shape of each `/predict` item, selected with `?format=`
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ResponseFormat {
+    /// Legacy `(labels, scores)` parallel arrays
+    #[default]
+    Arrays,
+    /// `{"predictions": [{"label", "score"}, ...]}`
+    Objects,
+}
+
+/// Array kept by `?fields=`, the other one is omitted from each result
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ResultFields {
+    Labels,
+    Scores,
+}
+
+impl PredictOptions {
+    /// Extra per-item fields only fit the object response shape
+    fn wants_objects(&self) -> bool {
+        self.oov_report || self.uncertainty || self.include_length || self.fingerprint || self.fields.is_some()
+    }
+}
+
+/// One item of a `/predict` batch: a plain text, fields joined with `--field-separator`,
+/// or a text with its own `k`/`threshold` overriding the query string and an `id` echoed in its result
+#[derive(Deserialize, Debug, Clone, PartialEq, ToSchema)]
+#[serde(untagged)]
+enum PredictInput {
+    Text(String),
+    Fields { fields: Vec<String> },
+    Item {
+        text: String,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default)]
+        k: Option<u32>,
+        #[serde(default)]
+        threshold: Option<f32>,
+    },
+}
+
+impl PredictInput {
+    /// Per-item `k` and `threshold`, if the item carries them
+    fn overrides(&self) -> (Option<u32>, Option<f32>) {
+        match self {
+            PredictInput::Item { k, threshold, .. } => (*k, *threshold),
+            _ => (None, None),
         }
     }
-    
-    if error_count > 0 {
-        log::warn!("Batch processing completed with {} errors out of {} texts", error_count, text_count);
-    } else {
-        log::info!("Batch processing completed successfully: {} texts", success_count);
+
+    fn id(&self) -> Option<String> {
+        match self {
+            PredictInput::Item { id, .. } => id.clone(),
+            _ => None,
+        }
+    }
+
+    fn into_text(self, separator: &str) -> String {
+        match self {
+            PredictInput::Text(text) => text,
+            PredictInput::Fields { fields } => fields.join(separator),
+            PredictInput::Item { text, .. } => text,
+        }
     }
-    
-    // 转换为原始格式 [(labels, scores), ...]
-    let legacy_results: Vec<(Vec<String>, Vec<f32>)> = results
-        .into_iter()
-        .map(|r| (r.labels, r.scores))
-        .collect();
-    
-    Ok(HttpResponse::Ok().json(legacy_results))
 }
 
-async fn sentence_vector(
-    model: web::Data<FastText>,
-    config: web::Data<crate::ServerConfig>,
-    texts: web::Json<Vec<String>>,
-) -> ActixResult<HttpResponse> {
-    let text_count = texts.len();
-    log::info!("Processing {} texts for sentence vectors", text_count);
-    
-    if text_count == 0 {
-        return Ok(HttpResponse::Ok().json(Vec::<Vec<f32>>::new()));
+/// Request body decoded as MessagePack when sent with `Content-Type: application/msgpack`,
+/// as JSON otherwise
+struct Body<T>(T);
+
+impl<T> Body<T> {
+    fn into_inner(self) -> T {
+        self.0
     }
-    
-    let mut results = Vec::with_capacity(text_count);
-    let mut success_count = 0;
-    let mut error_count = 0;
-    
-    for txt in texts.iter() {
-        match model.get_sentence_vector(txt) {
-            Ok(vector) => {
-                results.push(vector);
-                success_count += 1;
-            }
-            Err(e) => {
-                log::warn!("Sentence vector failed for text (length: {}): {}", txt.len(), e);
-                // 返回零向量而不是失败
-                results.push(vec![0.0; config.default_vector_dim]); // 使用配置的向量维度
-                error_count += 1;
-            }
+}
+
+impl<T> std::ops::Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = actix_web::Error;
+    type Future = futures::future::LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if !is_msgpack(req) {
+            let json = web::Json::<T>::from_request(req, payload);
+            return Box::pin(async move { Ok(Body(json.await?.into_inner())) });
         }
+        // 大小限制沿用PayloadConfig
+        let bytes = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let bytes = bytes.await?;
+            rmp_serde::from_slice(&bytes).map(Body).map_err(|e| {
+                log::error!("MessagePack parsing error: {}", e);
+                let message = format!("Failed to parse MessagePack: {}", e);
+                actix_web::error::InternalError::from_response(e, bad_request("msgpack_parse_error", message)).into()
+            })
+        })
     }
-    
-    if error_count > 0 {
-        log::warn!("Sentence vector processing completed with {} errors out of {} texts", error_count, text_count);
-    } else {
-        log::info!("Sentence vector processing completed successfully: {} texts", success_count);
+}
+
+fn is_msgpack(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with(MSGPACK))
+        .unwrap_or(false)
+}
+
+/// Answer in MessagePack when asked to, or when the request was MessagePack and JSON wasn't asked for
+fn wants_msgpack(req: &HttpRequest) -> bool {
+    accepts(req, MSGPACK) || (is_msgpack(req) && !accepts(req, "application/json"))
+}
+
+fn msgpack_response<T: Serialize>(body: &T) -> HttpResponse {
+    match rmp_serde::to_vec_named(body) {
+        Ok(buf) => HttpResponse::Ok().content_type(MSGPACK).body(buf),
+        Err(e) => HttpResponse::InternalServerError().json(ErrorResponse {
+            error: "encode_error".to_string(),
+            message: format!("Failed to encode MessagePack: {}", e),
+        }),
     }
-    
-    Ok(HttpResponse::Ok().json(results))
 }
 
-pub(crate) fn runserver(model: FastText, address: &str, port: u16, workers: usize, config: crate::ServerConfig) {
-    let addr = Address::from((address, port));
-    log::info!("Listening on {}", addr);
-    let model_data = web::Data::new(model);
-    let config_data = web::Data::new(config.clone());
-    
-    // 使用配置参数设置JSON限制
-    let json_cfg = web::JsonConfig::default()
-        .limit((config.max_request_size_mb as usize) * 1_000_000) // MB转换为bytes
-        .content_type(|_mime| true) // Accept any content type
-        .error_handler(|err, _req| {
-            let error_message = format!("Failed to parse JSON: {}", err);
-            log::error!("JSON parsing error: {}", err);
-            actix_web::error::InternalError::from_response(
-                err,
-                HttpResponse::BadRequest().json(ErrorResponse {
-                    error: "json_parse_error".to_string(),
-                    message: error_message,
-                })
-            ).into()
-        });
-        
-    let mut server = HttpServer::new(move || {
-        App::new()
-            .service(
-                web::resource("/predict")
-                    .app_data(model_data.clone())
-                    .app_data(config_data.clone())
-                    .app_data(json_cfg.clone())
-                    .route(web::post().to(predict)),
-            )
-            .service(
-                web::resource("/sentence-vector")
-                    .app_data(model_data.clone())
-                    .app_data(config_data.clone())
-                    .app_data(json_cfg.clone())
-                    .route(web::post().to(sentence_vector)),
-            )
-            .service(
-                web::resource("/health")
-                    .route(web::get().to(health_check)),
-            )
-    })
-    .workers(workers);
+/// Size of an input as the server received it, before any normalization
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+struct InputLength {
+    bytes: usize,
+    chars: usize,
+}
 
-    let sys = System::new();
-    server = match addr {
-        Address::IpPort(address, port) => server.bind((&address[..], port)).expect("bind failed"),
-        Address::Unix(path) => {
-            #[cfg(unix)]
-            {
-                server.bind_uds(path).expect("bind failed")
-            }
-            #[cfg(not(unix))]
-            {
-                panic!("Unix domain socket is not supported on this platform")
-            }
+impl InputLength {
+    fn of(text: &str) -> Self {
+        InputLength {
+            bytes: text.len(),
+            chars: text.chars().count(),
         }
-    };
-    sys.block_on(async { server.run().await }).unwrap();
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::predict;
-    use actix_web::http::StatusCode;
-    use actix_web::test::{call_service, init_service, TestRequest};
-    use actix_web::{web, App};
-    use fasttext::FastText;
+/// 64-bit FNV-1a; unlike `DefaultHasher` its output is the same across builds and restarts
+struct Fnv64(u64);
 
-    #[actix_rt::test]
-    async fn test_predict_empty_input() {
-        let mut fasttext = FastText::new();
-        fasttext
-            .load_model("models/cooking.model.bin")
-            .expect("Failed to load fastText model");
-        let model_data = web::Data::new(fasttext);
-        let mut srv = init_service(
-            App::new()
-                .app_data(model_data)
-                .service(web::resource("/predict").route(web::post().to(predict))),
-        )
-        .await;
-        let data: Vec<String> = Vec::new();
-        let req = TestRequest::post()
-            .uri("/predict")
-            .set_json(&data)
-            .to_request();
-        let resp = call_service(&mut srv, req).await;
-        assert_eq!(resp.status(), StatusCode::OK);
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
     }
+}
 
-    #[actix_rt::test]
-    async fn test_predict() {
-        let mut fasttext = FastText::new();
-        fasttext
-            .load_model("models/cooking.model.bin")
-            .expect("Failed to load fastText model");
-        let model_data = web::Data::new(fasttext);
-        let mut srv = init_service(
-            App::new()
-                .app_data(model_data)
-                .service(web::resource("/predict").route(web::post().to(predict))),
-        )
-        .await;
-        let data = vec!["Which baking dish is best to bake a banana bread?"];
-        let req = TestRequest::post()
-            .uri("/predict")
-            .set_json(&data)
-            .to_request();
-        let resp = call_service(&mut srv, req).await;
-        assert_eq!(resp.status(), StatusCode::OK);
+impl std::hash::Hasher for Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Text whose outputs stand in for the model's weights in `model_checksum`
+const CHECKSUM_PROBE: &str = "fingerprint probe";
+
+/// Checksum of a loaded model from its hyperparameters, labels and outputs on a probe text,
+/// so models that differ in any of them get different fingerprints
+pub(crate) fn model_checksum(model: &FastText) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = Fnv64::default();
+    serde_json::to_vec(&TrainingArgs::of(model)).unwrap_or_default().hash(&mut hasher);
+    let (labels, counts) = model.get_labels().unwrap_or_default();
+    (&labels, counts).hash(&mut hasher);
+    let vector = model.get_sentence_vector(CHECKSUM_PROBE).unwrap_or_default();
+    vector.iter().map(|value| value.to_bits()).for_each(|bits| bits.hash(&mut hasher));
+    let label_count = labels.len().max(1) as i32;
+    if let Ok(predictions) = model.predict(CHECKSUM_PROBE, label_count, 0.0) {
+        for prediction in predictions {
+            (prediction.label, prediction.prob.to_bits()).hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// `?fingerprint=` of one text: the model checksum, the text as received and the options that shape its result
+fn fingerprint(checksum: u64, text: &str, k: u32, threshold: f32, options: &PredictOptions) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = Fnv64::default();
+    (checksum, text, k, threshold.to_bits()).hash(&mut hasher);
+    let shaping = (options.renormalize, options.unique_labels, options.group_depth, options.precision, options.seed);
+    (&options.labels, shaping).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Outcome of one item in the object formats, telling an empty result from a failure
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ItemStatus {
+    /// At least one label passed the threshold
+    Ok,
+    /// The model ran but no label passed the threshold
+    Empty,
+    /// The text was rejected or the model failed, `labels` holds a marker
+    Error,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PredictResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    status: ItemStatus,
+    labels: Vec<String>,
+    scores: Vec<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oov: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entropy: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uncertain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<InputLength>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    /// Set when `--max-total-predictions` cut this result's labels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+    #[serde(skip)]
+    failed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, ToSchema)]
+struct LabelScore {
+    label: String,
+    score: f32,
+}
+
+/// `PredictResult` with labels and scores zipped, returned for `?format=objects`
+#[derive(Serialize, Debug, ToSchema)]
+struct LabeledResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    status: ItemStatus,
+    predictions: Vec<LabelScore>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oov: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entropy: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uncertain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<InputLength>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+}
+
+impl From<PredictResult> for LabeledResult {
+    fn from(result: PredictResult) -> Self {
+        LabeledResult {
+            id: result.id,
+            status: result.status,
+            predictions: result
+                .labels
+                .into_iter()
+                .zip(result.scores)
+                .map(|(label, score)| LabelScore { label, score })
+                .collect(),
+            oov: result.oov,
+            entropy: result.entropy,
+            uncertain: result.uncertain,
+            length: result.length,
+            fingerprint: result.fingerprint,
+            truncated: result.truncated,
+        }
+    }
+}
+
+/// `PredictResult` with only the array selected by `?fields=`
+#[derive(Serialize, Debug)]
+struct FieldsResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    status: ItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scores: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oov: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entropy: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uncertain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<InputLength>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+}
+
+impl FieldsResult {
+    fn new(result: PredictResult, fields: ResultFields) -> Self {
+        let (labels, scores) = match fields {
+            ResultFields::Labels => (Some(result.labels), None),
+            ResultFields::Scores => (None, Some(result.scores)),
+        };
+        FieldsResult {
+            id: result.id,
+            status: result.status,
+            labels,
+            scores,
+            oov: result.oov,
+            entropy: result.entropy,
+            uncertain: result.uncertain,
+            length: result.length,
+            fingerprint: result.fingerprint,
+            truncated: result.truncated,
+        }
+    }
+}
+
+/// `?matrix=true`: the legacy pairs as 2D arrays, row i holding text i's labels and scores
+#[derive(Serialize, Debug)]
+struct ScoreMatrix {
+    labels: Vec<Vec<String>>,
+    scores: Vec<Vec<f32>>,
+}
+
+impl ScoreMatrix {
+    /// Every result succeeded with the same number of labels, so the rows line up
+    fn fits(results: &[PredictResult]) -> bool {
+        let width = results.first().map(|result| result.labels.len());
+        results.iter().all(|result| !result.failed && Some(result.labels.len()) == width)
+    }
+
+    fn new(results: Vec<PredictResult>) -> Self {
+        let (labels, scores) = results.into_iter().map(|result| (result.labels, result.scores)).unzip();
+        ScoreMatrix { labels, scores }
+    }
+}
+
+/// One `/predict` result in the shape selected by `?format=`, `?fields=` and the extra-field options
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ResultItem {
+    Pair(Vec<String>, Vec<f32>),
+    Labeled(LabeledResult),
+    Full(PredictResult),
+    Fields(FieldsResult),
+    /// One result per line of a multi-line text under `--split-lines`
+    Lines(Vec<ResultItem>),
+}
+
+impl ResultItem {
+    fn new(result: PredictResult, format: ResponseFormat, objects: bool, fields: Option<ResultFields>) -> Self {
+        if let Some(fields) = fields {
+            ResultItem::Fields(FieldsResult::new(result, fields))
+        } else if format == ResponseFormat::Objects {
+            ResultItem::Labeled(LabeledResult::from(result))
+        } else if objects {
+            ResultItem::Full(result)
+        } else {
+            ResultItem::Pair(result.labels, result.scores)
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ItemFailure {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    error: &'static str,
+    message: String,
+}
+
+/// `?strict=true` response when some texts failed; `results` keeps the successful
+/// predictions in input order, with `null` at the failed indices
+#[derive(Serialize)]
+struct PartialFailureResponse {
+    error: String,
+    message: String,
+    failures: Vec<ItemFailure>,
+    results: Vec<Option<ResultItem>>,
+}
+
+/// Batch-level aggregates for drift monitoring, computed over the items that didn't fail
+#[derive(Serialize, Debug, PartialEq)]
+struct BatchSummary {
+    count: usize,
+    error_count: usize,
+    mean_top_score: f32,
+    /// Share of items left without labels, i.e. whose top-1 score fell below the threshold
+    empty_fraction: f32,
+    label_counts: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct SummarizedResponse<T: Serialize> {
+    results: T,
+    summary: BatchSummary,
+}
+
+/// The served model, swapped atomically by `/reload`
+type SharedModel = ArcSwap<crate::ServedModel>;
+
+/// Named models available to endpoints that work across models
+type ModelMap = HashMap<String, Arc<SharedModel>>;
+
+#[derive(Serialize)]
+struct StreamEvent {
+    index: usize,
+    labels: Vec<String>,
+    scores: Vec<f32>,
+}
+
+#[derive(Serialize)]
+struct TokenVector {
+    token: String,
+    vector: Vec<f32>,
+}
+
+/// Whether `/predict` would accept a text, and why not
+#[derive(Serialize, Debug, PartialEq)]
+struct Validation {
+    valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Subword n-grams a word decomposes into, with their row ids in the input matrix
+#[derive(Serialize)]
+struct WordSubwords {
+    word: String,
+    subwords: Vec<String>,
+    ids: Vec<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NeighborsRequest {
+    word: String,
+    #[serde(default = "default_neighbor_count")]
+    k: i32,
+}
+
+fn default_neighbor_count() -> i32 {
+    10
+}
+
+/// `a - b + c`, e.g. `king - man + woman`
+#[derive(Deserialize, Debug)]
+struct AnalogyRequest {
+    a: String,
+    b: String,
+    c: String,
+    #[serde(default = "default_neighbor_count")]
+    k: i32,
+}
+
+/// `POST /diff` body: two texts predicted with the same k and threshold
+#[derive(Deserialize, Debug)]
+struct DiffRequest {
+    a: String,
+    b: String,
+    k: Option<u32>,
+    threshold: Option<f32>,
+}
+
+/// A label predicted for both texts, `delta` is `score_b - score_a`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SharedLabel {
+    label: String,
+    score_a: f32,
+    score_b: f32,
+    delta: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct DiffResponse {
+    only_a: Vec<LabelScore>,
+    only_b: Vec<LabelScore>,
+    shared: Vec<SharedLabel>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Neighbor {
+    word: String,
+    similarity: f32,
+}
+
+#[derive(Serialize)]
+struct CachedVectorResponse {
+    id: String,
+    vector: Vec<f32>,
+    cached: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorResponse {
+    error: String,
+    message: String,
+}
+
+/// Runtime-tunable subset of `ServerConfig` accepted by `PATCH /admin/config`
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigPatch {
+    default_threshold: Option<f32>,
+    default_k: Option<u32>,
+    max_text_length: Option<usize>,
+}
+
+/// Fields that are only read while starting up and cannot be changed at runtime
+const LOAD_TIME_FIELDS: &[&str] = &["model", "address", "port", "workers", "max_request_size_mb", "default_vector_dim"];
+
+#[derive(Deserialize, Debug, Default)]
+struct ReloadRequest {
+    path: Option<String>,
+}
+
+/// `POST /quantize` body; the defaults match the `fasttext quantize` CLI
+#[derive(Deserialize, Debug)]
+struct QuantizeRequest {
+    /// Where to write the `.ftz` file
+    output: String,
+    /// Keep only this many words and n-grams, 0 keeps all
+    #[serde(default)]
+    cutoff: usize,
+    #[serde(default = "default_dsub")]
+    dsub: usize,
+    #[serde(default)]
+    qnorm: bool,
+    /// Also quantize the output matrix
+    #[serde(default)]
+    qout: bool,
+}
+
+fn default_dsub() -> usize {
+    2
+}
+
+#[derive(Serialize)]
+struct QuantizeResponse {
+    path: String,
+    size: u64,
+    elapsed_ms: u128,
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    path: String,
+    dimension: usize,
+    elapsed_ms: u128,
+}
+
+/// One step of `/drain-reload`, with the readiness reported once it finished
+#[derive(Serialize, Deserialize, Debug)]
+struct ReloadStep {
+    step: String,
+    ready: bool,
+    elapsed_ms: u128,
+}
+
+#[derive(Serialize)]
+struct DrainReloadResponse {
+    path: String,
+    dimension: usize,
+    elapsed_ms: u128,
+    timeline: Vec<ReloadStep>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HealthResponse {
+    status: String,
+    model_loaded: bool,
+    /// A `/reload` or `/drain-reload` is loading a new model
+    reloading: bool,
+}
+
+#[derive(Serialize)]
+struct ModelInfo {
+    dimension: usize,
+    label_count: usize,
+    labels: Vec<String>,
+    /// Unsupervised (skipgram/cbow) models have no labels
+    supervised: bool,
+    is_quantized: bool,
+    training_args: TrainingArgs,
+}
+
+/// Hyperparameters stored in the model file. `lr`, `thread` and the sampling threshold `t`
+/// aren't kept there or come back truncated from the library, so they're left out
+#[derive(Serialize)]
+struct TrainingArgs {
+    model: &'static str,
+    loss: &'static str,
+    dimension: i32,
+    epoch: i32,
+    word_ngrams: i32,
+    min_count: i32,
+    neg: i32,
+    ws: i32,
+    bucket: i32,
+    minn: i32,
+    maxn: i32,
+    lr_update_rate: i32,
+}
+
+impl TrainingArgs {
+    fn of(model: &FastText) -> Self {
+        let args = model.get_args();
+        // 与fasttext命令行的取值一致
+        let model_name = match args.model() {
+            fasttext::ModelName::CBOW => "cbow",
+            fasttext::ModelName::SG => "skipgram",
+            fasttext::ModelName::SUP => "supervised",
+        };
+        let loss = match args.loss() {
+            fasttext::LossName::HS => "hs",
+            fasttext::LossName::NS => "ns",
+            fasttext::LossName::SOFTMAX => "softmax",
+            fasttext::LossName::OVA => "ova",
+        };
+        TrainingArgs {
+            model: model_name,
+            loss,
+            dimension: args.dim(),
+            epoch: args.epoch(),
+            word_ngrams: args.word_ngrams(),
+            min_count: args.min_count(),
+            neg: args.neg(),
+            ws: args.ws(),
+            bucket: args.bucket(),
+            minn: args.minn(),
+            maxn: args.maxn(),
+            lr_update_rate: args.lr_update_rate(),
+        }
+    }
+}
+
+/// Process facts fixed at startup, reported by `/stats`
+pub(crate) struct ServerInfo {
+    started: Instant,
+    workers: usize,
+}
+
+impl ServerInfo {
+    pub(crate) fn new(workers: usize) -> Self {
+        ServerInfo {
+            started: Instant::now(),
+            workers,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Stats {
+    workers: usize,
+    model_path: String,
+    /// Models served and models that failed to load at startup
+    loaded_models: Vec<String>,
+    failed_models: Vec<crate::FailedModel>,
+    uptime_seconds: u64,
+    /// Requests handled since startup, not counting `/metrics` scrapes
+    total_requests: u64,
+    in_flight: usize,
+    max_batch_size: usize,
+    max_request_size_mb: u32,
+    max_k: u32,
+    max_concurrent_requests: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    rate_limit: Option<f64>,
+    predict_timeout_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VocabQuery {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_vocab_limit")]
+    limit: usize,
+}
+
+/// Words returned per `/vocab` page unless `?limit=` asks for fewer
+const DEFAULT_VOCAB_LIMIT: usize = 1_000;
+const MAX_VOCAB_LIMIT: usize = 10_000;
+
+fn default_vocab_limit() -> usize {
+    DEFAULT_VOCAB_LIMIT
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct VocabWord {
+    word: String,
+    count: i64,
+}
+
+#[derive(Serialize)]
+struct VocabPage {
+    total: usize,
+    offset: usize,
+    words: Vec<VocabWord>,
+}
+
+/// A label with how many training examples carried it and its share of all of them
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct LabelCount {
+    label: String,
+    count: i64,
+    frequency: f64,
+}
+
+async fn metrics_endpoint(metrics: web::Data<Metrics>) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render()))
+}
+
+/// Whether the model is ready to serve; cleared while `/reload` swaps it
+pub(crate) struct Readiness {
+    ready: AtomicBool,
+    /// Held by the one `/reload` or `/drain-reload` allowed to run at a time
+    reloading: AtomicBool,
+    /// `--ready-after-prediction`: not ready before the model has answered a prediction
+    first_prediction: Option<Arc<FirstPrediction>>,
+}
+
+/// Set once a model has answered a real prediction or the default one its warmup, shared by
+/// the prediction paths through the config and by `Readiness`
+#[derive(Debug, Default)]
+pub(crate) struct FirstPrediction(AtomicBool);
+
+impl FirstPrediction {
+    pub(crate) fn record(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn happened(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Marks a reload in progress until dropped
+struct ReloadGuard<'a>(&'a AtomicBool);
+
+impl Drop for ReloadGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl Readiness {
+    pub(crate) fn new(ready: bool) -> Self {
+        Readiness {
+            ready: AtomicBool::new(ready),
+            reloading: AtomicBool::new(false),
+            first_prediction: None,
+        }
+    }
+
+    /// Also wait for `first` before reporting ready
+    pub(crate) fn after_prediction(self, first: Arc<FirstPrediction>) -> Self {
+        Readiness {
+            first_prediction: Some(first),
+            ..self
+        }
+    }
+
+    /// Loaded but the model hasn't answered a prediction yet
+    fn awaiting_prediction(&self) -> bool {
+        let first = self.first_prediction.as_ref();
+        self.ready.load(Ordering::Acquire) && first.is_some_and(|first| !first.happened())
+    }
+
+    /// Start a reload, `None` while another one is still loading so the model isn't held twice
+    fn begin_reload(&self) -> Option<ReloadGuard<'_>> {
+        self.reloading
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| ReloadGuard(&self.reloading))
+    }
+
+    fn is_reloading(&self) -> bool {
+        self.reloading.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire) && !self.awaiting_prediction()
+    }
+
+    pub(crate) fn set(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Release);
+    }
+}
+
+/// Constant input for the health probe, short enough to keep the check cheap
+const HEALTH_PROBE_TEXT: &str = "health";
+
+/// Run a trivial query through the model; unsupervised models can't predict, so they
+/// are probed with a sentence vector instead
+fn model_responds(model: &FastText) -> Result<(), String> {
+    match crate::predict_one_safe(model, HEALTH_PROBE_TEXT, 1, 0.0, usize::MAX, None, false) {
+        Ok(_) => Ok(()),
+        Err(e) => model
+            .get_sentence_vector(HEALTH_PROBE_TEXT)
+            .map(|_| ())
+            .map_err(|_| e.to_string()),
+    }
+}
+
+/// Runtime status for capacity planning; reads counters only, never the model
+async fn stats(
+    info: web::Data<ServerInfo>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+) -> ActixResult<HttpResponse> {
+    let config = config.load();
+    Ok(HttpResponse::Ok().json(Stats {
+        workers: info.workers,
+        model_path: config.model_path.clone(),
+        loaded_models: config.loaded_models.clone(),
+        failed_models: config.failed_models.clone(),
+        uptime_seconds: info.started.elapsed().as_secs(),
+        total_requests: metrics.served(),
+        in_flight: metrics.in_flight(),
+        max_batch_size: config.limits.max_batch_size,
+        max_request_size_mb: config.limits.max_request_size_mb,
+        max_k: config.limits.max_k,
+        max_concurrent_requests: config.limits.max_concurrent_requests,
+        max_connections_per_ip: config.limits.max_connections_per_ip,
+        rate_limit: config.limits.rate_limit,
+        predict_timeout_ms: config.limits.predict_timeout_ms,
+    }))
+}
+
+/// Liveness only says the process is up, it never touches the model
+async fn liveness(readiness: web::Data<Readiness>) -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(HealthResponse {
+        status: "alive".to_string(),
+        model_loaded: true,
+        reloading: readiness.is_reloading(),
+    }))
+}
+
+/// Readiness, served on both `/readyz` and the legacy `/health`
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Model loaded and answering", body = HealthResponse),
+        (status = 503, description = "Model not ready or failing", body = HealthResponse),
+    )
+)]
+async fn health_check(
+    model: web::Data<SharedModel>,
+    readiness: web::Data<Readiness>,
+) -> ActixResult<HttpResponse> {
+    if !readiness.is_ready() {
+        // 已加载但还没有成功预测过的模型单独说明
+        let awaiting = readiness.awaiting_prediction();
+        return Ok(HttpResponse::ServiceUnavailable().json(HealthResponse {
+            status: if awaiting { "awaiting_prediction" } else { "not_ready" }.to_string(),
+            model_loaded: awaiting,
+            reloading: readiness.is_reloading(),
+        }));
+    }
+    match model_responds(&model.load()) {
+        Ok(()) => Ok(HttpResponse::Ok().json(HealthResponse {
+            status: "healthy".to_string(),
+            model_loaded: true,
+            reloading: readiness.is_reloading(),
+        })),
+        Err(e) => {
+            log::error!("Health check failed: {}", e);
+            Ok(HttpResponse::ServiceUnavailable().json(HealthResponse {
+                status: "unhealthy".to_string(),
+                model_loaded: false,
+                reloading: readiness.is_reloading(),
+            }))
+        }
+    }
+}
+
+async fn model_info(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let snapshot = config.load_full();
+    let settings = snapshot.for_model(crate::DEFAULT_MODEL);
+    let labels: Vec<String> = match model.get_labels() {
+        // 与预测结果一致，按配置去掉标签前缀
+        Ok((labels, _)) => labels
+            .into_iter()
+            .map(|label| crate::strip_label(label, settings.label_prefix))
+            .collect(),
+        Err(e) => {
+            log::error!("Failed to read model labels: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "model_error".to_string(),
+                message: e,
+            }));
+        }
+    };
+    Ok(HttpResponse::Ok().json(ModelInfo {
+        dimension: model.get_dimension() as usize,
+        label_count: labels.len(),
+        supervised: !labels.is_empty(),
+        labels,
+        is_quantized: model.is_quantized,
+        training_args: TrainingArgs::of(&model),
+    }))
+}
+
+/// The model's labels sorted by descending training frequency, without the rest of `/model-info`
+async fn labels(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let snapshot = config.load_full();
+    let settings = snapshot.for_model(crate::DEFAULT_MODEL);
+    let (labels, counts) = match model.get_labels() {
+        Ok(labels) => labels,
+        Err(e) => {
+            log::error!("Failed to read model labels: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "model_error".to_string(),
+                message: e,
+            }));
+        }
+    };
+    let total: i64 = counts.iter().sum();
+    let mut labels: Vec<LabelCount> = labels
+        .into_iter()
+        .zip(counts)
+        .map(|(label, count)| LabelCount {
+            label: crate::strip_label(label, settings.label_prefix),
+            count,
+            frequency: if total > 0 { count as f64 / total as f64 } else { 0.0 },
+        })
+        .collect();
+    // 频次相同时按标签名排序，保证输出稳定
+    labels.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.label.cmp(&b.label)));
+    Ok(HttpResponse::Ok().json(labels))
+}
+
+/// One page of the model's vocabulary with frequency counts, in the model's own order
+async fn vocab(model: web::Data<SharedModel>, query: web::Query<VocabQuery>) -> ActixResult<HttpResponse> {
+    if query.limit > MAX_VOCAB_LIMIT {
+        return Ok(bad_request(
+            "input_error",
+            format!("limit must be at most {}, got {}", MAX_VOCAB_LIMIT, query.limit),
+        ));
+    }
+    let model = model.load_full();
+    let (words, counts) = match model.get_vocab() {
+        Ok(vocab) => vocab,
+        Err(e) => {
+            log::error!("Failed to read model vocabulary: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "model_error".to_string(),
+                message: e,
+            }));
+        }
+    };
+    let total = words.len();
+    let words = words
+        .into_iter()
+        .zip(counts)
+        .skip(query.offset)
+        .take(query.limit)
+        .map(|(word, count)| VocabWord { word, count })
+        .collect();
+    Ok(HttpResponse::Ok().json(VocabPage {
+        total,
+        offset: query.offset,
+        words,
+    }))
+}
+
+/// Tokens missing from the model dictionary, whose vectors come from subwords only
+fn oov_tokens(model: &FastText, text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter(|token| model.get_word_id(token).map(|id| id < 0).unwrap_or(true))
+        .map(|token| token.to_string())
+        .collect()
+}
+
+/// Correlation ID of the current request, `-` when none was assigned
+#[derive(Clone, Debug, PartialEq)]
+struct RequestId(String);
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId("-".to_string())
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = futures::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        futures::future::ready(Ok(req.extensions().get::<RequestId>().cloned().unwrap_or_default()))
+    }
+}
+
+/// Reuse the client's `X-Request-Id` if it's sane, otherwise generate one, and attach it to the request
+fn assign_request_id(req: &actix_web::dev::ServiceRequest) -> RequestId {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(|id| RequestId(id.to_string()))
+        .unwrap_or_else(|| RequestId(uuid::Uuid::new_v4().to_string()));
+    req.extensions_mut().insert(request_id.clone());
+    request_id
+}
+
+/// Echo the request ID back so clients can quote it when reporting problems
+fn echo_request_id<B>(res: &mut actix_web::dev::ServiceResponse<B>, request_id: &RequestId) {
+    if let Ok(value) = header::HeaderValue::from_str(&request_id.0) {
+        res.headers_mut().insert(header::HeaderName::from_static(REQUEST_ID), value);
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct EnvelopeQuery {
+    #[serde(default)]
+    envelope: bool,
+}
+
+/// `?envelope=true` response body: the usual JSON under `data`, plus request metadata
+#[derive(Serialize)]
+struct Envelope {
+    data: serde_json::Value,
+    meta: EnvelopeMeta,
+}
+
+#[derive(Serialize)]
+struct EnvelopeMeta {
+    /// Results in `data`, 1 for responses that aren't a list
+    count: usize,
+    model: String,
+    processing_ms: f64,
+}
+
+/// Whether the request asks for `?envelope=true`; unparsable values are left to the handler's own query parsing
+fn wants_envelope(req: &actix_web::dev::ServiceRequest) -> bool {
+    web::Query::<EnvelopeQuery>::from_query(req.query_string())
+        .map(|query| query.envelope)
+        .unwrap_or(false)
+}
+
+/// Name of the model a path is routed to, `default` outside `/models/{name}/...`
+fn routed_model(path: &str) -> &str {
+    path.strip_prefix("/models/")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(crate::DEFAULT_MODEL)
+}
+
+/// Wrap a successful JSON response as `{"data": ..., "meta": {...}}`; errors, NDJSON and
+/// binary responses are passed through unchanged
+async fn wrap_envelope<B: MessageBody + 'static>(
+    res: actix_web::dev::ServiceResponse<B>,
+    model: String,
+    started: Instant,
+) -> ActixResult<actix_web::dev::ServiceResponse<BoxBody>> {
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !res.status().is_success() || !is_json {
+        return Ok(res.map_into_boxed_body());
+    }
+    let (req, res) = res.into_parts();
+    let (head, body) = res.into_parts();
+    let bytes = match actix_web::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let e: Box<dyn std::error::Error> = e.into();
+            return Err(actix_web::error::ErrorInternalServerError(e.to_string()));
+        }
+    };
+    let data: serde_json::Value = match serde_json::from_slice(&bytes) {
+        Ok(data) => data,
+        Err(_) => return Ok(actix_web::dev::ServiceResponse::new(req, head.set_body(bytes).map_into_boxed_body())),
+    };
+    // 带summary时结果在results里
+    let count = match data.get("results").unwrap_or(&data) {
+        serde_json::Value::Array(items) => items.len(),
+        _ => 1,
+    };
+    let envelope = Envelope {
+        data,
+        meta: EnvelopeMeta {
+            count,
+            model,
+            processing_ms: started.elapsed().as_secs_f64() * 1000.0,
+        },
+    };
+    let body = serde_json::to_vec(&envelope)?;
+    Ok(actix_web::dev::ServiceResponse::new(req, head.set_body(body).map_into_boxed_body()))
+}
+
+/// Routes reachable without the API key, so load balancers can probe them
+const PUBLIC_PATHS: &[&str] = &["/health", "/livez", "/readyz"];
+
+/// Check the `Authorization: Bearer` or `X-API-Key` header against the configured key
+fn authorize(req: &actix_web::dev::ServiceRequest, api_key: &str) -> Result<(), actix_web::Error> {
+    if PUBLIC_PATHS.contains(&req.path()) {
+        return Ok(());
+    }
+    let headers = req.headers();
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|value| value.to_str().ok()));
+    match provided {
+        Some(key) if constant_time_eq(key.as_bytes(), api_key.as_bytes()) => Ok(()),
+        provided => {
+            let message = if provided.is_some() { "Invalid API key" } else { "Missing API key" };
+            Err(actix_web::error::InternalError::from_response(
+                message,
+                HttpResponse::Unauthorized().json(ErrorResponse {
+                    error: "unauthorized".to_string(),
+                    message: message.to_string(),
+                }),
+            )
+            .into())
+        }
+    }
+}
+
+/// Routes that run the model and count against `--rate-limit` and `--max-concurrent-requests`
+fn is_rate_limited(path: &str) -> bool {
+    const PREFIXES: &[&str] = &[
+        "/predict",
+        "/sentence-vector",
+        "/word-vector",
+        "/token-vectors",
+        "/subwords",
+        "/nearest-neighbors",
+        "/analogy",
+        "/diff",
+        "/ensemble",
+        "/models/",
+    ];
+    PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Spend one of the client's tokens, answering 429 with `Retry-After` when the bucket is empty
+fn rate_limit(req: &actix_web::dev::ServiceRequest, limiter: &RateLimiter) -> Result<(), actix_web::Error> {
+    // unix socket连接没有对端IP，不做限制
+    let ip = match req.peer_addr() {
+        Some(peer) if is_rate_limited(req.path()) => peer.ip(),
+        _ => return Ok(()),
+    };
+    limiter.check(ip).map_err(|retry_after| {
+        log::warn!("Rate limit exceeded by {}", ip);
+        let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        actix_web::error::InternalError::from_response(
+            "rate limited",
+            HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                .json(ErrorResponse {
+                    error: "rate_limited".to_string(),
+                    message: format!("Rate limit exceeded, retry after {}s", retry_after),
+                }),
+        )
+        .into()
+    })
+}
+
+/// Take a slot for a model route, answering 503 with `Retry-After` when every slot is busy
+fn acquire_slot(
+    req: &actix_web::dev::ServiceRequest,
+    limiter: &ConcurrencyLimiter,
+) -> Result<Option<OwnedSemaphorePermit>, actix_web::Error> {
+    if !is_rate_limited(req.path()) {
+        return Ok(None);
+    }
+    match limiter.try_acquire() {
+        Some(permit) => Ok(Some(permit)),
+        None => {
+            log::warn!("Rejecting {}: all {} prediction slots are busy", req.path(), limiter.max());
+            Err(actix_web::error::InternalError::from_response(
+                "overloaded",
+                HttpResponse::ServiceUnavailable()
+                    .insert_header((header::RETRY_AFTER, "1"))
+                    .json(ErrorResponse {
+                        error: "overloaded".to_string(),
+                        message: "Too many predictions in flight, retry later".to_string(),
+                    }),
+            )
+            .into())
+        }
+    }
+}
+
+/// Checks every request passes before its handler runs: API key, rate limit, memory shedding,
+/// and the per-IP and global concurrency caps
+#[derive(Clone)]
+struct Admission {
+    api_key: Option<Arc<str>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    memory_guard: Option<Arc<MemoryGuard>>,
+    connection_limiter: Option<Arc<ConnectionLimiter>>,
+    concurrency_limiter: Option<Arc<ConcurrencyLimiter>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Admission {
+    /// Answer a rejected request without calling `srv`, otherwise hold its permits until `srv` responds
+    fn call<S, B>(
+        &self,
+        req: actix_web::dev::ServiceRequest,
+        srv: &S,
+    ) -> impl std::future::Future<Output = Result<actix_web::dev::ServiceResponse<B>, actix_web::Error>>
+    where
+        S: Service<
+            actix_web::dev::ServiceRequest,
+            Response = actix_web::dev::ServiceResponse<B>,
+            Error = actix_web::Error,
+        >,
+    {
+        if let Some(api_key) = &self.api_key {
+            if let Err(err) = authorize(&req, api_key) {
+                return futures::future::Either::Left(futures::future::err(err));
+            }
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            if let Err(err) = rate_limit(&req, limiter) {
+                return futures::future::Either::Left(futures::future::err(err));
+            }
+        }
+        if let Some(guard) = &self.memory_guard {
+            if let Err(err) = shed_load(&req, guard) {
+                return futures::future::Either::Left(futures::future::err(err));
+            }
+        }
+        // unix socket连接没有对端IP，不做限制
+        let permit = match (&self.connection_limiter, req.peer_addr()) {
+            (Some(limiter), Some(peer)) => match limiter.try_acquire(peer.ip()) {
+                Some(permit) => Some(permit),
+                None => {
+                    log::warn!("Too many concurrent requests from {}", peer.ip());
+                    let err = actix_web::error::InternalError::from_response(
+                        "too many connections",
+                        HttpResponse::TooManyRequests().json(ErrorResponse {
+                            error: "too_many_connections".to_string(),
+                            message: format!("Too many concurrent requests from {}", peer.ip()),
+                        }),
+                    );
+                    return futures::future::Either::Left(futures::future::err(err.into()));
+                }
+            },
+            _ => None,
+        };
+        let slot = match &self.concurrency_limiter {
+            Some(limiter) => match acquire_slot(&req, limiter) {
+                Ok(slot) => slot,
+                Err(err) => return futures::future::Either::Left(futures::future::err(err)),
+            },
+            None => None,
+        };
+        // 抓取/metrics本身不算在内；/drain-reload等待其他请求结束，不能算上自己
+        let in_flight = match req.path() {
+            "/metrics" | "/drain-reload" => None,
+            _ => Some(self.metrics.enter()),
+        };
+        let fut = srv.call(req);
+        futures::future::Either::Right(async move {
+            let res = fut.await;
+            drop(in_flight);
+            drop(slot);
+            drop(permit);
+            res
+        })
+    }
+}
+
+/// Answer 503 on the model routes while memory is above `--max-memory-mb`, health checks stay up
+fn shed_load(req: &actix_web::dev::ServiceRequest, guard: &MemoryGuard) -> Result<(), actix_web::Error> {
+    if !guard.is_shedding() || !is_rate_limited(req.path()) {
+        return Ok(());
+    }
+    Err(actix_web::error::InternalError::from_response(
+        "memory limit",
+        HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, "1"))
+            .json(ErrorResponse {
+                error: "overloaded".to_string(),
+                message: format!("Memory usage is above {} MB, retry later", guard.limit_mb()),
+            }),
+    )
+    .into())
+}
+
+// 比较耗时与第一个不同字节的位置无关
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn bad_request(error: &str, message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponse {
+        error: error.to_string(),
+        message,
+    })
+}
+
+async fn patch_config(
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    patch: web::Json<serde_json::Value>,
+) -> ActixResult<HttpResponse> {
+    let patch = patch.into_inner();
+    if let Some(fields) = patch.as_object() {
+        if let Some(field) = fields.keys().find(|key| LOAD_TIME_FIELDS.contains(&key.as_str())) {
+            return Ok(bad_request(
+                "config_immutable",
+                format!("`{}` can only be set at startup", field),
+            ));
+        }
+    }
+    let patch: ConfigPatch = match serde_json::from_value(patch) {
+        Ok(patch) => patch,
+        Err(e) => return Ok(bad_request("invalid_config", e.to_string())),
+    };
+    // 与启动参数使用同一套校验
+    let checked = patch
+        .default_threshold
+        .map(crate::check_default_threshold)
+        .transpose()
+        .and_then(|_| patch.default_k.map(crate::check_default_k).transpose());
+    if let Err(e) = checked {
+        return Ok(bad_request("invalid_config", e));
+    }
+
+    let previous = config.rcu(|current| {
+        let mut next = crate::ServerConfig::clone(current);
+        if let Some(threshold) = patch.default_threshold {
+            next.default_threshold = threshold;
+        }
+        if let Some(k) = patch.default_k {
+            next.default_k = k;
+        }
+        if let Some(max_text_length) = patch.max_text_length {
+            next.limits.max_text_length = max_text_length;
+        }
+        next
+    });
+    let current = config.load();
+    log::info!(
+        "Runtime config updated: default_threshold {} -> {}, default_k {} -> {}, max_text_length {} -> {}",
+        previous.default_threshold,
+        current.default_threshold,
+        previous.default_k,
+        current.default_k,
+        previous.limits.max_text_length,
+        current.limits.max_text_length
+    );
+    Ok(HttpResponse::Ok().json(&**current))
+}
+
+/// 413 for batches above `--max-batch-size`, checked before any model work
+fn check_batch_size(len: usize, config: &crate::ServerConfig) -> Result<(), HttpResponse> {
+    if len <= config.limits.max_batch_size {
+        return Ok(());
+    }
+    log::warn!("Rejecting batch of {} texts, limit is {}", len, config.limits.max_batch_size);
+    Err(HttpResponse::PayloadTooLarge().json(ErrorResponse {
+        error: "batch_too_large".to_string(),
+        message: format!(
+            "Batch of {} texts exceeds the maximum batch size of {}",
+            len, config.limits.max_batch_size
+        ),
+    }))
+}
+
+/// Report the time since `started` in a `Server-Timing` header, e.g. `predict;dur=12.345`
+fn server_timing(mut resp: HttpResponse, metric: &str, started: Instant) -> HttpResponse {
+    let millis = started.elapsed().as_secs_f64() * 1000.0;
+    let value = format!("{};dur={:.3}", metric, millis);
+    if let Ok(value) = header::HeaderValue::from_str(&value) {
+        resp.headers_mut().insert(header::HeaderName::from_static("server-timing"), value);
+    }
+    resp
+}
+
+/// 400 for vector endpoints when the served model is quantized
+fn check_not_quantized(model: &crate::ServedModel, operation: &str) -> Result<(), HttpResponse> {
+    match model.quantized_unsupported(operation) {
+        Some(message) => Err(bad_request("quantized_model", message)),
+        None => Ok(()),
+    }
+}
+
+fn model_not_found(name: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(ErrorResponse {
+        error: "model_not_found".to_string(),
+        message: format!("No model named {}", name),
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/predict",
+    request_body = Vec<PredictInput>,
+    params(PredictOptions),
+    responses(
+        (status = 200, description = "One result per text, as `[labels, scores]` pairs unless `format=objects` \
+            or an option that adds fields is set", body = [LabeledResult]),
+        (status = 400, description = "Invalid options", body = ErrorResponse),
+        (status = 413, description = "Batch too large", body = ErrorResponse),
+        (status = 504, description = "Every text timed out", body = ErrorResponse),
+    )
+)]
+async fn predict(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    inputs: Body<Vec<PredictInput>>,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    // 每个请求读取一份配置和模型快照，运行时修改只影响后续请求
+    let model = model.load_full();
+    let snapshot = config.load_full();
+    let (config, settings) = (&*snapshot, snapshot.for_model(crate::DEFAULT_MODEL));
+    let (mut inputs, mut options) = (inputs.into_inner(), options.into_inner());
+    if let Err(resp) = header_options(&req, &mut options) {
+        return Ok(resp);
+    }
+    let capped = cap_k(&mut inputs, &mut options, config, settings, &request_id);
+    let span = telemetry::request_span("predict", &req, &request_id.0);
+    // tracing-opentelemetry只把i64记成数值属性，u64和u32会变成字符串
+    span.record("batch_size", inputs.len() as i64);
+    span.record("k", i64::from(options.k.unwrap_or(settings.default_k)));
+    let started = Instant::now();
+    let msgpack = wants_msgpack(&req);
+    let resp = predict_batch(model, config, settings, metrics, inputs, &options, &request_id, msgpack)
+        .instrument(span)
+        .await;
+    Ok(k_capped(server_timing(resp, "predict", started), capped, config))
+}
+
+/// `/predict` with every label, sorted by descending probability, for calibration work
+async fn predict_all(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    inputs: Body<Vec<PredictInput>>,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let snapshot = config.load_full();
+    let settings = snapshot.for_model(crate::DEFAULT_MODEL);
+    // 完整分布本来就是这个接口的用途，不受--max-k限制
+    let mut options = options.into_inner();
+    let label_count = model.get_labels().map(|(labels, _)| labels.len()).unwrap_or(0);
+    options.k = Some(label_count.max(1) as u32);
+    options.threshold = Some(0.0);
+    let started = Instant::now();
+    let (inputs, msgpack) = (inputs.into_inner(), wants_msgpack(&req));
+    let resp = predict_batch(model, &snapshot, settings, metrics, inputs, &options, &request_id, msgpack).await;
+    Ok(server_timing(resp, "predict", started))
+}
+
+/// `/predict` for a `text/plain` body holding one text per line, answered as NDJSON like the fastText CLI
+async fn batch_predict(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    body: String,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    // 行尾的换行由predict_one_safe补上，末尾的空行不算一条文本
+    let inputs: Vec<PredictInput> = body.lines().map(|line| PredictInput::Text(line.to_string())).collect();
+    if inputs.is_empty() {
+        return Ok(HttpResponse::Ok().content_type(NDJSON).finish());
+    }
+    let mut options = options.into_inner();
+    options.stream = true;
+    predict(req, model, config, metrics, Body(inputs), web::Query(options), request_id).await
+}
+
+/// `/batch-predict` for a text file uploaded as `multipart/form-data` in the `file` field
+async fn predict_upload(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    mut form: Multipart,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    // 表单不经过PayloadConfig，按--max-request-size自己计算上限
+    let limit = config.load().limits.max_request_size_mb as usize * 1_048_576;
+    let mut content = None;
+    while let Some(mut field) = form.try_next().await? {
+        // 其他字段不读取，取下一个字段时会自动跳过
+        if field.content_disposition().get_name() != Some(UPLOAD_FIELD) {
+            continue;
+        }
+        let mut buf = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if buf.len() + chunk.len() > limit {
+                return Ok(HttpResponse::PayloadTooLarge().json(ErrorResponse {
+                    error: "upload_too_large".to_string(),
+                    message: format!("Uploaded file exceeds the maximum request size of {} bytes", limit),
+                }));
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        content = Some(buf);
+    }
+    let body = match content.map(String::from_utf8) {
+        Some(Ok(body)) => body,
+        Some(Err(_)) => return Ok(bad_request("input_error", "Uploaded file is not valid UTF-8".to_string())),
+        None => return Ok(bad_request("input_error", format!("Missing form field {}", UPLOAD_FIELD))),
+    };
+    log::info!("[{}] Received upload of {} bytes", request_id, body.len());
+    batch_predict(req, model, config, metrics, body, options, request_id).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn model_predict(
+    req: HttpRequest,
+    name: web::Path<String>,
+    models: web::Data<ModelMap>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    inputs: Body<Vec<PredictInput>>,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let model = match models.get(name.as_str()) {
+        Some(model) => model.load_full(),
+        None => return Ok(model_not_found(&name)),
+    };
+    let snapshot = config.load();
+    let settings = snapshot.for_model(&name);
+    let mut config = Cow::Borrowed(&**snapshot);
+    if config.shadow_model.is_some() || config.language_router.is_some() {
+        // 指定了模型名时不再按语言路由，影子模型也只与默认模型比较
+        let config = config.to_mut();
+        config.shadow_model = None;
+        config.language_router = None;
+    }
+    let (mut inputs, mut options) = (inputs.into_inner(), options.into_inner());
+    if let Err(resp) = header_options(&req, &mut options) {
+        return Ok(resp);
+    }
+    let capped = cap_k(&mut inputs, &mut options, &config, settings, &request_id);
+    let started = Instant::now();
+    let msgpack = wants_msgpack(&req);
+    let resp = predict_batch(model, &config, settings, metrics, inputs, &options, &request_id, msgpack).await;
+    Ok(k_capped(server_timing(resp, "predict", started), capped, &config))
+}
+
+/// Fill `k` and `threshold` missing from the query string from the `X-Predict-K`/`X-Predict-Threshold` headers
+fn header_options(req: &HttpRequest, options: &mut PredictOptions) -> Result<(), HttpResponse> {
+    fn parse<T: FromStr>(req: &HttpRequest, name: &str) -> Result<Option<T>, HttpResponse> {
+        match req.headers().get(name) {
+            None => Ok(None),
+            Some(value) => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .map(Some)
+                .ok_or_else(|| bad_request("input_error", format!("Invalid {} header: {:?}", name, value))),
+        }
+    }
+    // 查询参数优先
+    if options.k.is_none() {
+        options.k = parse(req, PREDICT_K)?;
+    }
+    if options.threshold.is_none() {
+        options.threshold = parse(req, PREDICT_THRESHOLD)?;
+    }
+    Ok(())
+}
+
+/// Clamp the requested k, from the query or per item, to `--max-k`; true when anything was clamped
+fn cap_k(
+    inputs: &mut [PredictInput],
+    options: &mut PredictOptions,
+    config: &crate::ServerConfig,
+    settings: crate::ModelSettings<'_>,
+    request_id: &RequestId,
+) -> bool {
+    let max_k = config.limits.max_k;
+    let mut capped = false;
+    if options.k.unwrap_or(settings.default_k) > max_k {
+        options.k = Some(max_k);
+        capped = true;
+    }
+    for input in inputs.iter_mut() {
+        if let PredictInput::Item { k: Some(k), .. } = input {
+            if *k > max_k {
+                *k = max_k;
+                capped = true;
+            }
+        }
+    }
+    if capped {
+        log::warn!("[{}] Requested k exceeds the maximum, capped to {}", request_id, max_k);
+    }
+    capped
+}
+
+/// Tell the client its k was clamped, with the cap as the header value
+fn k_capped(mut resp: HttpResponse, capped: bool, config: &crate::ServerConfig) -> HttpResponse {
+    if capped {
+        resp.headers_mut()
+            .insert(header::HeaderName::from_static(K_CAPPED), header::HeaderValue::from(config.limits.max_k));
+    }
+    resp
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn predict_batch(
+    model: Arc<crate::ServedModel>,
+    config: &crate::ServerConfig,
+    settings: crate::ModelSettings<'_>,
+    metrics: web::Data<Metrics>,
+    inputs: Vec<PredictInput>,
+    options: &PredictOptions,
+    request_id: &RequestId,
+    msgpack: bool,
+) -> HttpResponse {
+    metrics.requests.with_label_values(&["predict"]).inc();
+    if let Err(resp) = check_batch_size(inputs.len(), config) {
+        return resp;
+    }
+    if options.fields.is_some() && options.format == ResponseFormat::Objects {
+        return bad_request("input_error", "fields can't be combined with format=objects".to_string());
+    }
+    // 客户端自带的id原样带回，不依赖结果的位置
+    let ids: Vec<Option<String>> = inputs.iter().map(PredictInput::id).collect();
+    let with_ids;
+    let options = if ids.iter().any(Option::is_some) {
+        with_ids = PredictOptions {
+            format: ResponseFormat::Objects,
+            ..options.clone()
+        };
+        &with_ids
+    } else {
+        options
+    };
+    let k = options.k.unwrap_or(settings.default_k);
+    let threshold = options.threshold.unwrap_or(settings.default_threshold);
+    // 每条文本自带的k/threshold优先于查询参数
+    let items: Vec<(String, u32, f32)> = inputs
+        .into_iter()
+        .map(|input| {
+            let (item_k, item_threshold) = input.overrides();
+            (
+                input.into_text(&config.field_separator),
+                item_k.unwrap_or(k),
+                item_threshold.unwrap_or(threshold),
+            )
+        })
+        .collect();
+    if let Err(resp) = check_predict_options(options.k, options.threshold) {
+        return resp;
+    }
+    for (index, (_, k, threshold)) in items.iter().enumerate() {
+        if let Err(resp) = check_predict_options(Some(*k), Some(*threshold)) {
+            log::warn!("[{}] Invalid k or threshold on item {}", request_id, index);
+            return resp;
+        }
+    }
+    if let Err(resp) = check_precision(options.precision) {
+        return resp;
+    }
+    if options.group_depth == Some(0) {
+        return bad_request("input_error", "group_depth must be at least 1, got 0".to_string());
+    }
+    // 流式响应开始发送后没法再改成400
+    if options.stream && options.on_error.unwrap_or(config.on_error) == crate::OnError::Fail {
+        return bad_request(
+            "input_error",
+            "on_error=fail can't abort a streamed response, pass on_error=skip or on_error=mark".to_string(),
+        );
+    }
+    for (txt, _, _) in &items {
+        metrics.input_length.observe(txt.len() as f64);
+    }
+    let text_count = items.len();
+    
+    log::info!("[{}] Processing {} texts with k={}, threshold={}", request_id, text_count, k, threshold);
+    
+    if text_count == 0 {
+        return HttpResponse::Ok().json(Vec::<PredictResult>::new());
+    }
+    
+    let filter = label_filter(options, config);
+    let params = PredictParams {
+        // 过滤标签、去重和计算熵都需要完整的概率分布
+        label_count: if options.uncertainty
+            || options.unique_labels
+            || options.group_depth.is_some()
+            || options.seed.is_some()
+            || filter.is_some()
+            || !config.label_thresholds.is_empty()
+        {
+            Some(model.get_labels().map(|(labels, _)| labels.len() as u32).unwrap_or(k))
+        } else {
+            None
+        },
+        uncertainty: options.uncertainty,
+        unique_labels: options.unique_labels,
+        group: options.group_depth.map(|depth| (depth, config.label_separator.clone())),
+        seed: options.seed,
+        filter,
+        label_thresholds: if config.label_thresholds.is_empty() {
+            None
+        } else {
+            Some(Arc::new(config.label_thresholds.clone()))
+        },
+        max_text_length: settings.max_text_length,
+        label_prefix: settings.label_prefix.map(str::to_string),
+        reject_nan: config.reject_nan,
+        precision: options.precision,
+        cache: config.prediction_cache.clone(),
+        router: config.language_router.clone(),
+    };
+    
+    if config.split_lines && items.iter().any(|(txt, _, _)| is_multi_line(txt)) {
+        let (config, options, request_id) = (config.clone(), options.clone(), request_id.clone());
+        let task =
+            web::block(move || predict_split_lines(&model, &config, &metrics, &items, &params, &options, &request_id));
+        return match task.await {
+            Ok(results) => batch_response(results, None, msgpack),
+            Err(e) => HttpResponse::from_error(e),
+        };
+    }
+
+    if options.stream {
+        return predict_ndjson(model, config, metrics, items, params, options, request_id);
+    }
+
+    let (items, predictions) = match config.limits.predict_timeout_ms {
+        None => {
+            // 预测是CPU密集的同步调用，放到阻塞线程池里，避免卡住其他连接
+            let model = model.clone();
+            let task_config = config.clone();
+            let task_metrics = metrics.clone();
+            let task_params = params.clone();
+            let task = web::block(move || {
+                let predictions = predict_items(&model, &task_config, &task_metrics, &items, &task_params);
+                (items, predictions)
+            });
+            match task.await {
+                Ok(result) => result,
+                Err(e) => return HttpResponse::from_error(e),
+            }
+        }
+        Some(timeout_ms) => {
+            let mut predictions = Vec::with_capacity(text_count);
+            for (txt, k, threshold) in items.iter() {
+                let (k, threshold) = (*k, *threshold);
+                let input = crate::prepare_input(txt, config).into_owned();
+                let timer = metrics.duration.with_label_values(&["predict"]).start_timer();
+                // 超时后阻塞线程上的预测仍会跑完，只是结果被丢弃
+                let (model, params) = (model.clone(), params.clone());
+                let task = web::block(move || {
+                    let routed = params.route(&input);
+                    predict_text(routed.as_deref().unwrap_or(model.as_ref()), &input, k, threshold, &params)
+                });
+                let prediction = match actix_web::rt::time::timeout(Duration::from_millis(timeout_ms), task).await {
+                    Ok(Ok(prediction)) => prediction,
+                    Ok(Err(e)) => Err(crate::PredictError::ModelError(e.to_string())),
+                    Err(_) => Err(crate::PredictError::Timeout(timeout_ms)),
+                };
+                timer.observe_duration();
+                predictions.push(prediction);
+            }
+            (items, predictions)
+        }
+    };
+
+    // 过滤、分组和逐标签阈值改变了top标签，这样的结果没法和影子模型的直接比较
+    let comparable = params.filter.is_none() && params.group.is_none() && params.label_thresholds.is_none();
+    if let (Some(shadow), true) = (&config.shadow_model, comparable) {
+        // 只排队不等待，影子模型跟不上时丢弃这一批
+        let items = items
+            .iter()
+            .zip(&predictions)
+            .filter_map(|((txt, k, threshold), prediction)| {
+                let top = prediction.as_ref().ok()?.0.first().cloned();
+                Some((crate::prepare_input(txt, config).into_owned(), *k, *threshold, top))
+            })
+            .collect();
+        shadow.submit(crate::shadow::ShadowBatch {
+            items,
+            max_text_length: settings.max_text_length,
+            label_prefix: params.label_prefix.clone(),
+            metrics: metrics.clone().into_inner(),
+        });
+    }
+
+    // 使用安全的预测函数，避免单个文本错误导致整个批次失败
+    let mut results = Vec::with_capacity(text_count);
+    let mut success_count = 0;
+    let mut error_count = 0;
+    let mut timeout_count = 0;
+    let mut failures = Vec::new();
+    let on_error = options.on_error.unwrap_or(config.on_error);
+    
+    let checksum = if options.fingerprint { Some(model.checksum()) } else { None };
+    let mut budget = config.limits.max_total_predictions;
+    let mut truncated = 0;
+    for (index, ((txt, k, threshold), prediction)) in items.iter().zip(predictions).enumerate() {
+        let oov = if options.oov_report {
+            Some(oov_tokens(&model, txt))
+        } else {
+            None
+        };
+        match &prediction {
+            Ok(_) => success_count += 1,
+            Err(e) => {
+                log::warn!("[{}] Prediction failed for text (length: {}): {}", request_id, txt.len(), e);
+                if let crate::PredictError::Timeout(_) = e {
+                    timeout_count += 1;
+                }
+                if options.strict {
+                    failures.push(ItemFailure {
+                        index,
+                        id: ids[index].clone(),
+                        error: e.code(),
+                        message: e.to_string(),
+                    });
+                }
+                metrics.errors.with_label_values(&["predict"]).inc();
+                if on_error == crate::OnError::Fail {
+                    // 第一个失败就放弃整批
+                    return bad_request(e.code(), format!("Text {} failed, batch aborted: {}", index, e));
+                }
+                error_count += 1;
+            }
+        }
+        let mut result = predict_result(prediction, oov, config);
+        result.id = ids[index].clone();
+        if options.include_length {
+            result.length = Some(InputLength::of(txt));
+        }
+        if let Some(checksum) = checksum {
+            result.fingerprint = Some(fingerprint(checksum, txt, *k, *threshold, options));
+        }
+        if spend_budget(&mut result, &mut budget) {
+            truncated += 1;
+        }
+        count_top_label(&metrics, config, &result);
+        record_prediction(config, &result);
+        results.push(result);
+    }
+    
+    if error_count > 0 {
+        log::warn!(
+            "[{}] Batch processing completed with {} errors out of {} texts",
+            request_id, error_count, text_count
+        );
+    } else {
+        log::info!("[{}] Batch processing completed successfully: {} texts", request_id, success_count);
+    }
+    if timeout_count == text_count {
+        return HttpResponse::GatewayTimeout().json(ErrorResponse {
+            error: "predict_timeout".to_string(),
+            message: format!(
+                "All {} texts exceeded the prediction timeout of {}ms",
+                text_count,
+                config.limits.predict_timeout_ms.unwrap_or_default()
+            ),
+        });
+    }
+    
+    if !failures.is_empty() {
+        let objects = options.wants_objects();
+        return HttpResponse::UnprocessableEntity().json(PartialFailureResponse {
+            error: "prediction_failed".to_string(),
+            message: format!("{} of {} texts failed", failures.len(), text_count),
+            failures,
+            results: results
+                .into_iter()
+                .map(|result| {
+                    if result.failed {
+                        None
+                    } else {
+                        Some(ResultItem::new(result, options.format, objects, options.fields))
+                    }
+                })
+                .collect(),
+        });
+    }
+    
+    if on_error == crate::OnError::Skip {
+        results.retain(|result| !result.failed);
+    }
+    
+    let summary = if options.summary {
+        Some(summarize(&results))
+    } else {
+        None
+    };
+    
+    let resp = if let Some(fields) = options.fields {
+        let projected: Vec<FieldsResult> = results.into_iter().map(|r| FieldsResult::new(r, fields)).collect();
+        batch_response(projected, summary, msgpack)
+    } else if options.format == ResponseFormat::Objects {
+        let labeled: Vec<LabeledResult> = results.into_iter().map(LabeledResult::from).collect();
+        batch_response(labeled, summary, msgpack)
+    } else if options.wants_objects() {
+        // OOV报告、不确定度等额外字段只能使用对象格式返回
+        batch_response(results, summary, msgpack)
+    } else if options.matrix && ScoreMatrix::fits(&results) {
+        batch_response(ScoreMatrix::new(results), summary, msgpack)
+    } else {
+        if options.matrix {
+            log::debug!("[{}] Results differ in length, answering per item instead of a matrix", request_id);
+        }
+        // 转换为原始格式 [(labels, scores), ...]
+        let legacy_results: Vec<(Vec<String>, Vec<f32>)> = results
+            .into_iter()
+            .map(|r| (r.labels, r.scores))
+            .collect();
+        batch_response(legacy_results, summary, msgpack)
+    };
+    predictions_truncated(resp, truncated)
+}
+
+/// `--max-total-predictions`: cut a result to the labels left in the batch budget,
+/// true when anything was cut. Failed items' markers don't count against the budget
+fn spend_budget(result: &mut PredictResult, budget: &mut Option<usize>) -> bool {
+    let left = match budget {
+        Some(left) if !result.failed => left,
+        _ => return false,
+    };
+    let kept = result.labels.len().min(*left);
+    *left -= kept;
+    if kept == result.labels.len() {
+        return false;
+    }
+    result.labels.truncate(kept);
+    result.scores.truncate(kept);
+    result.truncated = Some(true);
+    true
+}
+
+/// Tell the client how many results were truncated, also visible in the legacy array shape
+fn predictions_truncated(mut resp: HttpResponse, truncated: usize) -> HttpResponse {
+    if truncated > 0 {
+        resp.headers_mut().insert(
+            header::HeaderName::from_static(PREDICTIONS_TRUNCATED),
+            header::HeaderValue::from(truncated),
+        );
+    }
+    resp
+}
+
+/// `--per-label-metrics`: count the top label of a successful prediction
+fn count_top_label(metrics: &Metrics, config: &crate::ServerConfig, result: &PredictResult) {
+    if !config.per_label_metrics || result.failed {
+        return;
+    }
+    if let Some(label) = result.labels.first() {
+        metrics.label_predicted.with_label_values(&[label]).inc();
+    }
+}
+
+/// `--ready-after-prediction`: a successful result shows the model can answer
+fn record_prediction(config: &crate::ServerConfig, result: &PredictResult) {
+    if let (Some(first), false) = (&config.first_prediction, result.failed) {
+        first.record();
+    }
+}
+
+/// Turn one prediction into its response item, with an `error`/`timeout` marker label on failure
+fn predict_result(
+    prediction: Result<TextPrediction, crate::PredictError>,
+    oov: Option<Vec<String>>,
+    config: &crate::ServerConfig,
+) -> PredictResult {
+    match prediction {
+        Ok((labels, probs, entropy)) => PredictResult {
+            id: None,
+            status: if labels.is_empty() { ItemStatus::Empty } else { ItemStatus::Ok },
+            labels,
+            scores: probs,
+            oov,
+            entropy,
+            uncertain: entropy.map(|entropy| entropy > config.uncertainty_threshold),
+            length: None,
+            fingerprint: None,
+            truncated: None,
+            failed: false,
+        },
+        // 返回默认结果而不是失败
+        Err(e) => {
+            let (labels, scores) = config.error_marker(&e);
+            PredictResult {
+                id: None,
+                status: ItemStatus::Error,
+                labels,
+                scores,
+                oov,
+                entropy: None,
+                uncertain: None,
+                length: None,
+                fingerprint: None,
+                truncated: None,
+                failed: true,
+            }
+        }
+    }
+}
+
+/// Stream `?stream=true` results as newline-delimited JSON, one line per input in input order.
+/// Texts are predicted a chunk at a time so only one chunk of output is buffered;
+/// `--predict-timeout-ms`, `?summary` and `?strict` don't apply to streamed responses.
+/// `on_error=skip` drops failed lines, `fail` is rejected before streaming starts
+fn predict_ndjson(
+    model: Arc<crate::ServedModel>,
+    config: &crate::ServerConfig,
+    metrics: web::Data<Metrics>,
+    items: Vec<(String, u32, f32)>,
+    params: PredictParams,
+    options: &PredictOptions,
+    request_id: &RequestId,
+) -> HttpResponse {
+    let config = config.clone();
+    let request_id = request_id.clone();
+    let (format, oov_report, objects) = (options.format, options.oov_report, options.wants_objects());
+    let (include_length, fields) = (options.include_length, options.fields);
+    let checksum = if options.fingerprint { Some(model.checksum()) } else { None };
+    let skip_failed = options.on_error.unwrap_or(config.on_error) == crate::OnError::Skip;
+    let options = Arc::new(options.clone());
+    // 各块按顺序处理，预算在块之间延续
+    let budget = Arc::new(Mutex::new(config.limits.max_total_predictions));
+    let mut items = items.into_iter();
+    let chunks = std::iter::from_fn(move || {
+        let chunk: Vec<_> = items.by_ref().take(PARALLEL_BATCH_SIZE).collect();
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(chunk)
+        }
+    });
+    let lines = stream::iter(chunks).then(move |chunk| {
+        let (model, config, metrics) = (model.clone(), config.clone(), metrics.clone());
+        let (request_id, params, options) = (request_id.clone(), params.clone(), options.clone());
+        let budget = budget.clone();
+        let task = web::block(move || {
+            let predictions = predict_items(&model, &config, &metrics, &chunk, &params);
+            let mut buf = Vec::new();
+            for ((txt, k, threshold), prediction) in chunk.iter().zip(predictions) {
+                if let Err(e) = &prediction {
+                    log::warn!("[{}] Prediction failed for text (length: {}): {}", request_id, txt.len(), e);
+                    metrics.errors.with_label_values(&["predict"]).inc();
+                }
+                let oov = if oov_report { Some(oov_tokens(&model, txt)) } else { None };
+                let mut result = predict_result(prediction, oov, &config);
+                if include_length {
+                    result.length = Some(InputLength::of(txt));
+                }
+                if let Some(checksum) = checksum {
+                    result.fingerprint = Some(fingerprint(checksum, txt, *k, *threshold, &options));
+                }
+                spend_budget(&mut result, &mut budget.lock().unwrap());
+                count_top_label(&metrics, &config, &result);
+                record_prediction(&config, &result);
+                if skip_failed && result.failed {
+                    continue;
+                }
+                // 每行的形状与非流式响应中的单个元素一致
+                let line = serde_json::to_vec(&ResultItem::new(result, format, objects, fields));
+                buf.extend(line.unwrap_or_else(|_| b"{}".to_vec()));
+                buf.push(b'\n');
+            }
+            web::Bytes::from(buf)
+        });
+        async move { task.await.map_err(actix_web::Error::from) }
+    });
+
+    HttpResponse::Ok().content_type(NDJSON).streaming(lines)
+}
+
+/// Whether a text has line breaks other than a trailing one
+fn is_multi_line(text: &str) -> bool {
+    text.trim_end_matches(['\n', '\r']).contains('\n')
+}
+
+/// `--split-lines`: predict each line of a multi-line text on its own, as the fastText CLI does
+/// for a file, and answer such texts with a nested list of per-line results
+fn predict_split_lines(
+    model: &crate::ServedModel,
+    config: &crate::ServerConfig,
+    metrics: &Metrics,
+    items: &[(String, u32, f32)],
+    params: &PredictParams,
+    options: &PredictOptions,
+    request_id: &RequestId,
+) -> Vec<ResultItem> {
+    let (format, objects, fields) = (options.format, options.wants_objects(), options.fields);
+    let predict_line = |line: &str, k: u32, threshold: f32| {
+        let input = crate::prepare_input(line, config);
+        let routed = params.route(&input);
+        let prediction = predict_text(routed.as_deref().map_or(model, |routed| routed), &input, k, threshold, params);
+        if let Err(e) = &prediction {
+            log::warn!("[{}] Prediction failed for line (length: {}): {}", request_id, line.len(), e);
+            metrics.errors.with_label_values(&["predict"]).inc();
+        }
+        ResultItem::new(predict_result(prediction, None, config), format, objects, fields)
+    };
+    items
+        .iter()
+        .map(|(txt, k, threshold)| {
+            if is_multi_line(txt) {
+                // 单行失败只影响该行的结果
+                ResultItem::Lines(txt.lines().map(|line| predict_line(line, *k, *threshold)).collect())
+            } else {
+                predict_line(txt, *k, *threshold)
+            }
+        })
+        .collect()
+}
+
+/// Batches at least this large are spread across the rayon pool
+const PARALLEL_BATCH_SIZE: usize = 64;
+
+/// `--predict-threads`: rayon pool for large prediction batches, sized apart from the actix workers
+pub(crate) fn predict_pool(threads: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("predict-{}", index))
+        .build()
+        .expect("failed to build the predict thread pool")
+}
+
+/// Predict every `(text, k, threshold)` item, in input order
+fn predict_items(
+    model: &crate::ServedModel,
+    config: &crate::ServerConfig,
+    metrics: &Metrics,
+    items: &[(String, u32, f32)],
+    params: &PredictParams,
+) -> Vec<Result<TextPrediction, crate::PredictError>> {
+    let predict_item = |(txt, k, threshold): &(String, u32, f32)| {
+        let input = crate::prepare_input(txt, config);
+        let routed = params.route(&input);
+        let _timer = metrics.duration.with_label_values(&["predict"]).start_timer();
+        predict_text(routed.as_deref().map_or(model, |routed| routed), &input, *k, *threshold, params)
+    };
+    // FastText的预测只读模型，可以多线程并发调用；collect保持输入顺序
+    if items.len() >= PARALLEL_BATCH_SIZE {
+        match &config.predict_pool {
+            Some(pool) => pool.install(|| items.par_iter().map(predict_item).collect()),
+            None => items.par_iter().map(predict_item).collect(),
+        }
+    } else {
+        items.iter().map(predict_item).collect()
+    }
+}
+
+/// Labels kept in `/predict` results, from `?labels=` or `--label-filter`
+struct LabelFilter {
+    labels: HashSet<String>,
+    renormalize: bool,
+}
+
+fn label_filter(options: &PredictOptions, config: &crate::ServerConfig) -> Option<Arc<LabelFilter>> {
+    let labels: HashSet<String> = match &options.labels {
+        Some(labels) => labels.split(',').map(|label| label.trim().to_string()).collect(),
+        None => config.label_filter.iter().cloned().collect(),
+    };
+    if labels.is_empty() {
+        return None;
+    }
+    Some(Arc::new(LabelFilter {
+        labels,
+        renormalize: options.renormalize,
+    }))
+}
+
+/// Batch-wide settings for `predict_text`
+#[derive(Clone, Default)]
+struct PredictParams {
+    /// Score this many labels, i.e. the full distribution, before thresholding and truncating to k
+    label_count: Option<u32>,
+    /// Report the normalized entropy of the full distribution
+    uncertainty: bool,
+    /// Keep only the first, highest scored, occurrence of each label
+    unique_labels: bool,
+    /// `?group_depth=` and the `--label-separator` splitting labels into path segments
+    group: Option<(usize, String)>,
+    /// `?seed=` breaking ties between equally scored labels
+    seed: Option<u64>,
+    filter: Option<Arc<LabelFilter>>,
+    /// Thresholds replacing the request's for individual labels
+    label_thresholds: Option<Arc<HashMap<String, f32>>>,
+    max_text_length: usize,
+    label_prefix: Option<String>,
+    reject_nan: bool,
+    precision: Option<u32>,
+    cache: Option<Arc<PredictionCache>>,
+    /// Picks the model for each text's language under `--lang-detect-model`
+    router: Option<Arc<LanguageRouter>>,
+}
+
+impl PredictParams {
+    /// Model registered for `input`'s language, `None` to predict with the batch's model
+    fn route(&self, input: &str) -> Option<Arc<crate::ServedModel>> {
+        self.router.as_ref()?.route(input)
+    }
+}
+
+/// `predict_one_safe` with the batch's settings, answered from `--cache-size`'s cache when possible
+fn predict_model(
+    model: &crate::ServedModel,
+    input: &str,
+    k: u32,
+    threshold: f32,
+    params: &PredictParams,
+) -> Result<(Vec<String>, Vec<f32>), crate::PredictError> {
+    let (max_text_length, reject_nan) = (params.max_text_length, params.reject_nan);
+    let label_prefix = params.label_prefix.as_deref();
+    let predict = || crate::predict_one_safe(model, input, k, threshold, max_text_length, label_prefix, reject_nan);
+    match &params.cache {
+        Some(cache) => cache.get_or_predict(model.checksum(), input, k, threshold, predict),
+        None => predict(),
+    }
+}
+
+/// Labels and scores of one text, with the normalized entropy when `?uncertainty` asks for it
+type TextPrediction = (Vec<String>, Vec<f32>, Option<f32>);
+
+/// Predict one prepared text; with `label_count` set, the full label distribution is scored first
+/// so filtering, per-label thresholds and the entropy see every label
+fn predict_text(
+    model: &crate::ServedModel,
+    input: &str,
+    k: u32,
+    threshold: f32,
+    params: &PredictParams,
+) -> Result<TextPrediction, crate::PredictError> {
+    let label_count = match params.label_count {
+        Some(label_count) => label_count,
+        None => {
+            return predict_model(model, input, k, threshold, params).map(|(labels, mut probs)| {
+                round_scores(&mut probs, params.precision);
+                (labels, probs, None)
+            })
+        }
+    };
+    let (labels, mut probs) = predict_model(model, input, label_count, 0.0, params)?;
+    let entropy = if params.uncertainty {
+        Some(normalized_entropy(&probs))
+    } else {
+        None
+    };
+    if let Some(filter) = &params.filter {
+        if filter.renormalize {
+            let kept: f32 = labels
+                .iter()
+                .zip(&probs)
+                .filter(|(label, _)| filter.labels.contains(label.as_str()))
+                .map(|(_, prob)| prob)
+                .sum();
+            if kept > 0.0 {
+                probs.iter_mut().for_each(|prob| *prob /= kept);
+            }
+        }
+    }
+    // 先过滤标签再应用阈值和k，被过滤掉的标签不占名额
+    let (labels, probs): (Vec<String>, Vec<f32>) = labels
+        .into_iter()
+        .zip(probs)
+        .filter(|(label, _)| params.filter.as_ref().is_none_or(|filter| filter.labels.contains(label)))
+        .unzip();
+    // 分组后阈值和k作用于组的总分
+    let (labels, probs) = match &params.group {
+        Some((depth, separator)) => group_labels(&labels, &probs, *depth, separator),
+        None => (labels, probs),
+    };
+    // 同分标签的先后由种子决定，k截断时保留哪个也随之确定
+    let (labels, probs) = match params.seed {
+        Some(seed) => break_ties(labels, probs, seed),
+        None => (labels, probs),
+    };
+    let mut seen = HashSet::new();
+    let (labels, mut probs): (Vec<String>, Vec<f32>) = labels
+        .into_iter()
+        .zip(probs)
+        .filter(|(label, prob)| {
+            let threshold = params
+                .label_thresholds
+                .as_ref()
+                .and_then(|thresholds| thresholds.get(label))
+                .copied()
+                .unwrap_or(threshold);
+            *prob >= threshold
+        })
+        // 分数已按降序排列，保留的第一次出现即最高分
+        .filter(|(label, _)| !params.unique_labels || seen.insert(label.clone()))
+        .take(k.max(1) as usize)
+        .unzip();
+    round_scores(&mut probs, params.precision);
+    Ok((labels, probs, entropy))
+}
+
+/// Sum the scores of labels sharing their first `depth` path segments, e.g. `food/dessert/cake`
+/// and `food/dessert/pie` into `food/dessert` at depth 2, highest group first
+fn group_labels(labels: &[String], probs: &[f32], depth: usize, separator: &str) -> (Vec<String>, Vec<f32>) {
+    let mut groups: HashMap<String, f32> = HashMap::new();
+    for (label, prob) in labels.iter().zip(probs) {
+        let group: Vec<&str> = label.split(separator).take(depth).collect();
+        *groups.entry(group.join(separator)).or_insert(0.0) += prob;
+    }
+    let mut groups: Vec<(String, f32)> = groups.into_iter().collect();
+    groups.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+    groups.into_iter().unzip()
+}
+
+/// Order labels with equal scores by a hash of `seed` and the label instead of the model's order
+fn break_ties(labels: Vec<String>, probs: Vec<f32>, seed: u64) -> (Vec<String>, Vec<f32>) {
+    use std::hash::{Hash, Hasher};
+    let mut ranked: Vec<(u64, String, f32)> = labels
+        .into_iter()
+        .zip(probs)
+        .map(|(label, prob)| {
+            let mut hasher = Fnv64::default();
+            (seed, &label).hash(&mut hasher);
+            (hasher.finish(), label, prob)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+    ranked.into_iter().map(|(_, label, prob)| (label, prob)).unzip()
+}
+
+/// Weighted average of per-model label scores, aligning labels by name.
+/// Labels a model didn't return count as a zero score for that model.
+fn combine_weighted(predictions: &[(f32, Vec<String>, Vec<f32>)]) -> (Vec<String>, Vec<f32>) {
+    let total_weight: f32 = predictions.iter().map(|(weight, _, _)| weight).sum();
+    let mut combined: HashMap<&str, f32> = HashMap::new();
+    if total_weight > 0.0 {
+        for (weight, labels, scores) in predictions {
+            for (label, score) in labels.iter().zip(scores) {
+                *combined.entry(label.as_str()).or_insert(0.0) += weight * score / total_weight;
+            }
+        }
+    }
+    let mut combined: Vec<(&str, f32)> = combined.into_iter().collect();
+    combined.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(b.0)));
+    combined
+        .into_iter()
+        .map(|(label, score)| (label.to_string(), score))
+        .unzip()
+}
+
+/// One `/ensemble` model with its per-model settings resolved once per request
+struct EnsembleMember {
+    weight: f32,
+    name: String,
+    model: Arc<crate::ServedModel>,
+    label_count: u32,
+    max_text_length: usize,
+    label_prefix: Option<String>,
+}
+
+async fn ensemble(
+    models: web::Data<ModelMap>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    texts: web::Json<Vec<String>>,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(texts.len(), &config) {
+        return Ok(resp);
+    }
+    let k = options.k.unwrap_or(config.default_k).clamp(1, config.limits.max_k) as usize;
+    let threshold = options.threshold.unwrap_or(config.default_threshold);
+    if let Err(resp) = check_predict_options(options.k, options.threshold) {
+        return Ok(resp);
+    }
+    if let Err(resp) = check_precision(options.precision) {
+        return Ok(resp);
+    }
+    // 每个成员的设置和标签数只取一次，不随文本重复计算
+    let member = |weight: f32, name: &str, model: &SharedModel| {
+        let (model, settings) = (model.load_full(), config.for_model(name));
+        EnsembleMember {
+            weight,
+            name: name.to_string(),
+            // 需要完整的概率分布才能按标签对齐
+            label_count: model.get_labels().map(|(labels, _)| labels.len()).unwrap_or(k) as u32,
+            max_text_length: settings.max_text_length,
+            label_prefix: settings.label_prefix.map(str::to_string),
+            model,
+        }
+    };
+    // 未配置权重时所有已加载模型等权
+    let members: Vec<EnsembleMember> = if config.ensemble_weights.is_empty() {
+        models.iter().map(|(name, model)| member(1.0, name, model)).collect()
+    } else {
+        config
+            .ensemble_weights
+            .iter()
+            .filter_map(|(name, weight)| models.get(name).map(|model| member(*weight, name, model)))
+            .collect()
+    };
+    if members.is_empty() {
+        return Ok(HttpResponse::ServiceUnavailable().json(ErrorResponse {
+            error: "no_models".to_string(),
+            message: "No ensemble members are loaded".to_string(),
+        }));
+    }
+    log::info!("[{}] Processing {} texts with an ensemble of {} models", request_id, texts.len(), members.len());
+
+    let (texts, precision) = (texts.into_inner(), options.precision);
+    let results = web::block(move || {
+        let mut results: Vec<(Vec<String>, Vec<f32>)> = Vec::with_capacity(texts.len());
+        for txt in texts.iter() {
+            let input = crate::prepare_input(txt, &config);
+            let mut predictions = Vec::with_capacity(members.len());
+            for member in &members {
+                let prediction = crate::predict_one_safe(
+                    &member.model,
+                    &input,
+                    member.label_count,
+                    0.0,
+                    member.max_text_length,
+                    member.label_prefix.as_deref(),
+                    config.reject_nan,
+                );
+                match prediction {
+                    Ok((labels, scores)) => predictions.push((member.weight, labels, scores)),
+                    Err(e) => {
+                        log::warn!(
+                            "[{}] Ensemble member {} failed for text (length: {}): {}",
+                            request_id, member.name, txt.len(), e
+                        );
+                    }
+                }
+            }
+            if predictions.is_empty() {
+                results.push((vec![config.marker_label("error")], vec![0.0]));
+                continue;
+            }
+            let (labels, scores) = combine_weighted(&predictions);
+            let (labels, mut scores): (Vec<String>, Vec<f32>) = labels
+                .into_iter()
+                .zip(scores)
+                .filter(|(_, score)| *score >= threshold)
+                .take(k)
+                .unzip();
+            round_scores(&mut scores, precision);
+            results.push((labels, scores));
+        }
+        results
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+fn sse_event<T: Serialize>(event: &str, data: &T) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_else(|_| "{}".to_string());
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Emit each prediction as a Server-Sent Event as soon as it's computed, then a final `done` event
+async fn predict_stream(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    texts: web::Json<Vec<String>>,
+    options: web::Query<PredictOptions>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(texts.len(), &config) {
+        return Ok(resp);
+    }
+    let mut options = options.into_inner();
+    if let Err(resp) = header_options(&req, &mut options) {
+        return Ok(resp);
+    }
+    let (k, threshold) = match resolve_predict_options(&options, &config, config.for_model(crate::DEFAULT_MODEL)) {
+        Ok(resolved) => resolved,
+        Err(resp) => return Ok(resp),
+    };
+    let texts = texts.into_inner();
+    let text_count = texts.len();
+    log::info!("[{}] Streaming {} predictions with k={}, threshold={}", request_id, text_count, k, threshold);
+
+    let precision = options.precision;
+    let events = stream::iter(texts.into_iter().enumerate())
+        .then(move |(index, txt)| {
+            let (model, config, request_id) = (model.clone(), config.clone(), request_id.clone());
+            async move {
+                // 逐条放到阻塞线程池里预测，算完一条推送一条
+                let (labels, mut scores) = web::block(move || {
+                    let settings = config.for_model(crate::DEFAULT_MODEL);
+                    let input = crate::prepare_input(&txt, &config);
+                    let (max_len, prefix) = (settings.max_text_length, settings.label_prefix);
+                    match crate::predict_one_safe(&model, &input, k, threshold, max_len, prefix, config.reject_nan) {
+                        Ok(result) => result,
+                        Err(e) => {
+                            log::warn!("[{}] Prediction failed for text (length: {}): {}", request_id, txt.len(), e);
+                            config.error_marker(&e)
+                        }
+                    }
+                })
+                .await?;
+                round_scores(&mut scores, precision);
+                Ok::<_, actix_web::Error>(sse_event("prediction", &StreamEvent { index, labels, scores }))
+            }
+        })
+        .chain(stream::once(async move {
+            Ok(sse_event("done", &serde_json::json!({ "count": text_count })))
+        }));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
+
+/// Shannon entropy of the distribution scaled to [0, 1] by the maximum `ln(n)`
+fn normalized_entropy(scores: &[f32]) -> f32 {
+    let total: f32 = scores.iter().sum();
+    if scores.len() < 2 || total <= 0.0 {
+        return 0.0;
+    }
+    let entropy: f32 = scores
+        .iter()
+        .map(|score| score / total)
+        .filter(|p| *p > 0.0)
+        .map(|p| -p * p.ln())
+        .sum();
+    entropy / (scores.len() as f32).ln()
+}
+
+fn summarize(results: &[PredictResult]) -> BatchSummary {
+    let mut count = 0;
+    let mut score_sum = 0.0;
+    let mut empty = 0;
+    let mut label_counts = BTreeMap::new();
+    for result in results.iter().filter(|r| !r.failed) {
+        count += 1;
+        // 没有任何标签通过阈值时，top-1得分按0计
+        let top_score = result.scores.first().copied().unwrap_or(0.0);
+        score_sum += top_score;
+        if result.labels.is_empty() {
+            empty += 1;
+        }
+        if let Some(label) = result.labels.first() {
+            *label_counts.entry(label.clone()).or_insert(0) += 1;
+        }
+    }
+    let (mean_top_score, empty_fraction) = if count > 0 {
+        (score_sum / count as f32, empty as f32 / count as f32)
+    } else {
+        (0.0, 0.0)
+    };
+    BatchSummary {
+        count,
+        error_count: results.len() - count,
+        mean_top_score,
+        empty_fraction,
+        label_counts,
+    }
+}
+
+fn batch_response<T: Serialize>(results: T, summary: Option<BatchSummary>, msgpack: bool) -> HttpResponse {
+    match (summary, msgpack) {
+        (Some(summary), false) => HttpResponse::Ok().json(SummarizedResponse { results, summary }),
+        (Some(summary), true) => msgpack_response(&SummarizedResponse { results, summary }),
+        (None, false) => HttpResponse::Ok().json(results),
+        (None, true) => msgpack_response(&results),
+    }
+}
+
+fn accepts(req: &HttpRequest, mime: &str) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(',').any(|item| item.trim().starts_with(mime)))
+        .unwrap_or(false)
+}
+
+/// Encode vectors as `count: u32 | dim: u32 | count * dim f32`, all little-endian
+/// `dim` is the model's, every row must have exactly that many values or the layout breaks
+fn encode_vectors(vectors: &[Vec<f32>], dim: usize) -> Result<Vec<u8>, String> {
+    if let Some((index, vector)) = vectors.iter().enumerate().find(|(_, vector)| vector.len() != dim) {
+        return Err(format!("vector {} has {} values, the model's dimension is {}", index, vector.len(), dim));
+    }
+    let mut buf = Vec::with_capacity(8 + vectors.len() * dim * 4);
+    buf.extend_from_slice(&(vectors.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(dim as u32).to_le_bytes());
+    for vector in vectors {
+        for value in vector {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    Ok(buf)
+}
+
+fn vectors_response(req: &HttpRequest, mut vectors: Vec<Vec<f32>>, dim: usize) -> HttpResponse {
+    match web::Query::<VectorOptions>::from_query(req.query_string()) {
+        Ok(options) => {
+            if let Err(resp) = check_precision(options.precision) {
+                return resp;
+            }
+            // 先归一化再取整，取整后的范数可能与1略有出入
+            if options.normalize {
+                vectors.iter_mut().for_each(|vector| normalize_vector(vector));
+            }
+            vectors.iter_mut().for_each(|vector| round_scores(vector, options.precision));
+        }
+        Err(e) => return bad_request("input_error", e.to_string()),
+    }
+    if accepts(req, OCTET_STREAM) {
+        match encode_vectors(&vectors, dim) {
+            Ok(body) => HttpResponse::Ok().content_type(OCTET_STREAM).body(body),
+            Err(e) => {
+                log::error!("Can't encode sentence vectors: {}", e);
+                HttpResponse::InternalServerError().json(ErrorResponse {
+                    error: "vector_dimension_mismatch".to_string(),
+                    message: e,
+                })
+            }
+        }
+    } else if wants_msgpack(req) {
+        msgpack_response(&vectors)
+    } else {
+        HttpResponse::Ok().json(vectors)
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/sentence-vector",
+    request_body = Vec<String>,
+    responses(
+        (status = 200, description = "One vector per text, or the binary encoding for \
+            `Accept: application/octet-stream`", body = Vec<Vec<f32>>),
+        (status = 413, description = "Batch too large", body = ErrorResponse),
+    )
+)]
+async fn sentence_vector(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    texts: Body<Vec<String>>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(texts.len(), &config) {
+        return Ok(resp);
+    }
+    if let Err(resp) = check_not_quantized(&model, "Sentence vectors") {
+        return Ok(resp);
+    }
+    let texts = texts.into_inner();
+    let span = telemetry::request_span("sentence_vector", &req, &request_id.0);
+    span.record("batch_size", texts.len() as i64);
+    let started = Instant::now();
+    let dim = model.dimension;
+    let vectors = web::block(move || sentence_vectors(&model, &config, &metrics, &texts, &request_id.0))
+        .instrument(span)
+        .await?;
+    Ok(server_timing(vectors_response(&req, vectors, dim), "sentence_vector", started))
+}
+
+async fn model_sentence_vector(
+    req: HttpRequest,
+    name: web::Path<String>,
+    models: web::Data<ModelMap>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    texts: Body<Vec<String>>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let model = match models.get(name.as_str()) {
+        Some(model) => model.load_full(),
+        None => return Ok(model_not_found(&name)),
+    };
+    // 维度是这个模型加载时探测的，不是默认模型的
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(texts.len(), &config) {
+        return Ok(resp);
+    }
+    if let Err(resp) = check_not_quantized(&model, "Sentence vectors") {
+        return Ok(resp);
+    }
+    let texts = texts.into_inner();
+    let started = Instant::now();
+    let dim = model.dimension;
+    let vectors = web::block(move || sentence_vectors(&model, &config, &metrics, &texts, &request_id.0)).await?;
+    Ok(server_timing(vectors_response(&req, vectors, dim), "sentence_vector", started))
+}
+
+pub(crate) fn sentence_vectors(
+    model: &crate::ServedModel,
+    config: &crate::ServerConfig,
+    metrics: &Metrics,
+    texts: &[String],
+    request_id: &str,
+) -> Vec<Vec<f32>> {
+    metrics.requests.with_label_values(&["sentence_vector"]).inc();
+    let text_count = texts.len();
+    log::info!("[{}] Processing {} texts for sentence vectors", request_id, text_count);
+    
+    let mut results = Vec::with_capacity(text_count);
+    let mut success_count = 0;
+    let mut error_count = 0;
+    
+    for txt in texts.iter() {
+        let timer = metrics.duration.with_label_values(&["sentence_vector"]).start_timer();
+        let vector = crate::sentence_vector_safe(model, &crate::prepare_input(txt, config), config.reject_nan);
+        timer.observe_duration();
+        match vector {
+            Ok(vector) => {
+                results.push(vector);
+                success_count += 1;
+            }
+            Err(e) => {
+                log::warn!("[{}] Sentence vector failed for text (length: {}): {}", request_id, txt.len(), e);
+                // 返回零向量而不是失败
+                results.push(vec![0.0; model.dimension]); // 与模型真实维度一致
+                metrics.errors.with_label_values(&["sentence_vector"]).inc();
+                error_count += 1;
+            }
+        }
+    }
+    
+    if error_count > 0 {
+        log::warn!(
+            "[{}] Sentence vector processing completed with {} errors out of {} texts",
+            request_id, error_count, text_count
+        );
+    } else if text_count > 0 {
+        log::info!("[{}] Sentence vector processing completed successfully: {} texts", request_id, success_count);
+    }
+    
+    results
+}
+
+async fn word_vector(
+    req: HttpRequest,
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    metrics: web::Data<Metrics>,
+    words: web::Json<Vec<String>>,
+    request_id: RequestId,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(words.len(), &config) {
+        return Ok(resp);
+    }
+    if let Err(resp) = check_not_quantized(&model, "Word vectors") {
+        return Ok(resp);
+    }
+    let words = words.into_inner();
+    let dim = model.get_dimension() as usize;
+    let vectors = web::block(move || word_vectors(&model, &metrics, &words, &request_id.0)).await?;
+    Ok(vectors_response(&req, vectors, dim))
+}
+
+/// Word vectors for `/word-vector`, with a zero vector for each word that fails
+fn word_vectors(
+    model: &crate::ServedModel,
+    metrics: &Metrics,
+    words: &[String],
+    request_id: &str,
+) -> Vec<Vec<f32>> {
+    metrics.requests.with_label_values(&["word_vector"]).inc();
+    let word_count = words.len();
+    log::info!("[{}] Processing {} words for word vectors", request_id, word_count);
+
+    let mut results = Vec::with_capacity(word_count);
+    let mut error_count = 0;
+    for word in words.iter() {
+        let timer = metrics.duration.with_label_values(&["word_vector"]).start_timer();
+        // 词表外的词由fastText用子词向量合成
+        let vector = model.get_word_vector(word);
+        timer.observe_duration();
+        match vector {
+            Ok(vector) => results.push(vector),
+            Err(e) => {
+                log::warn!("[{}] Word vector failed for word (length: {}): {}", request_id, word.len(), e);
+                // 返回零向量而不是失败
+                results.push(vec![0.0; model.dimension]);
+                metrics.errors.with_label_values(&["word_vector"]).inc();
+                error_count += 1;
+            }
+        }
+    }
+
+    if error_count > 0 {
+        log::warn!(
+            "[{}] Word vector processing completed with {} errors out of {} words",
+            request_id, error_count, word_count
+        );
+    } else if word_count > 0 {
+        log::info!("[{}] Word vector processing completed successfully: {} words", request_id, word_count);
+    }
+    results
+}
+
+async fn cached_vector(
+    model: web::Data<SharedModel>,
+    cache: web::Data<VectorCache>,
+    id: web::Path<String>,
+) -> ActixResult<HttpResponse> {
+    let id = id.into_inner();
+    let model = model.load_full();
+    if let Some(vector) = cache.get(&id) {
+        return Ok(HttpResponse::Ok().json(CachedVectorResponse {
+            id,
+            vector: vector.clone(),
+            cached: true,
+        }));
+    }
+    // 缓存未命中时把id当作文本现场计算
+    match model.get_sentence_vector(&id) {
+        Ok(vector) => Ok(HttpResponse::Ok().json(CachedVectorResponse {
+            id,
+            vector,
+            cached: false,
+        })),
+        Err(e) => {
+            log::warn!("Sentence vector failed for uncached id (length: {}): {}", id.len(), e);
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "model_error".to_string(),
+                message: e,
+            }))
+        }
+    }
+}
+
+/// Vocabulary words closest to `word` by cosine similarity of their word vectors
+async fn nearest_neighbors(
+    model: web::Data<SharedModel>,
+    index: web::Data<WordIndex>,
+    request: web::Json<NeighborsRequest>,
+) -> ActixResult<HttpResponse> {
+    let NeighborsRequest { word, k } = request.into_inner();
+    if k < 1 {
+        return Ok(bad_request("input_error", format!("k must be at least 1, got {}", k)));
+    }
+    let model = model.load_full();
+    // 词表外的词只能由子词拼出向量，近邻没有意义，返回空列表
+    if model.get_word_id(&word).map(|id| id < 0).unwrap_or(true) {
+        return Ok(HttpResponse::Ok().json(Vec::<Neighbor>::new()));
+    }
+    // 模型加载后首次调用需要预计算整个词表的向量，放到阻塞线程池
+    let neighbors = web::block(move || index.matrix(&model)?.nearest(&model, &word, k as usize)).await?;
+    match neighbors {
+        Ok(neighbors) => Ok(HttpResponse::Ok().json(
+            neighbors
+                .into_iter()
+                .map(|(similarity, word)| Neighbor { word, similarity })
+                .collect::<Vec<_>>(),
+        )),
+        Err(e) => {
+            log::warn!("Nearest neighbors lookup failed: {}", e);
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "model_error".to_string(),
+                message: e,
+            }))
+        }
+    }
+}
+
+/// Vocabulary words closest to `a - b + c`, over the same word matrix as the nearest neighbours
+async fn analogy(
+    model: web::Data<SharedModel>,
+    index: web::Data<WordIndex>,
+    request: web::Json<AnalogyRequest>,
+) -> ActixResult<HttpResponse> {
+    let AnalogyRequest { a, b, c, k } = request.into_inner();
+    if k < 1 {
+        return Ok(bad_request("input_error", format!("k must be at least 1, got {}", k)));
+    }
+    let model = model.load_full();
+    let missing: Vec<&str> = [&a, &b, &c]
+        .iter()
+        .filter(|word| model.get_word_id(word).map(|id| id < 0).unwrap_or(true))
+        .map(|word| word.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Ok(bad_request(
+            "input_error",
+            format!("Words not in the model vocabulary: {}", missing.join(", ")),
+        ));
+    }
+    let analogies =
+        web::block(move || index.matrix(&model)?.analogies(&model, (&a, &b, &c), k as usize)).await?;
+    match analogies {
+        Ok(analogies) => Ok(HttpResponse::Ok().json(
+            analogies
+                .into_iter()
+                .map(|(similarity, word)| Neighbor { word, similarity })
+                .collect::<Vec<_>>(),
+        )),
+        Err(e) => {
+            log::warn!("Analogy query failed: {}", e);
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "model_error".to_string(),
+                message: e,
+            }))
+        }
+    }
+}
+
+/// Split two predictions into labels unique to each and shared labels with their score change,
+/// each list in the order of the first prediction it appears in
+fn diff_predictions(a: (Vec<String>, Vec<f32>), b: (Vec<String>, Vec<f32>)) -> DiffResponse {
+    let scores_b: HashMap<&str, f32> = b.0.iter().map(String::as_str).zip(b.1.iter().copied()).collect();
+    let mut diff = DiffResponse {
+        only_a: Vec::new(),
+        only_b: Vec::new(),
+        shared: Vec::new(),
+    };
+    for (label, score_a) in a.0.iter().zip(&a.1) {
+        match scores_b.get(label.as_str()) {
+            Some(score_b) => diff.shared.push(SharedLabel {
+                label: label.clone(),
+                score_a: *score_a,
+                score_b: *score_b,
+                delta: score_b - score_a,
+            }),
+            None => diff.only_a.push(LabelScore {
+                label: label.clone(),
+                score: *score_a,
+            }),
+        }
+    }
+    for (label, score) in b.0.into_iter().zip(b.1) {
+        if !a.0.contains(&label) {
+            diff.only_b.push(LabelScore { label, score });
+        }
+    }
+    diff
+}
+
+/// Predict two texts and report which labels differ, for comparing model behavior during QA
+async fn diff(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    request: web::Json<DiffRequest>,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let config = config.load_full();
+    let DiffRequest { a, b, k, threshold } = request.into_inner();
+    if let Err(resp) = check_predict_options(k, threshold) {
+        return Ok(resp);
+    }
+    let predictions = web::block(move || {
+        let settings = config.for_model(crate::DEFAULT_MODEL);
+        let k = k.unwrap_or(settings.default_k).min(config.limits.max_k);
+        let threshold = threshold.unwrap_or(settings.default_threshold);
+        let predict = |text: &str| {
+            let input = crate::prepare_input(text, &config);
+            let (max_len, prefix) = (settings.max_text_length, settings.label_prefix);
+            crate::predict_one_safe(&model, &input, k, threshold, max_len, prefix, config.reject_nan)
+        };
+        (predict(&a), predict(&b))
+    })
+    .await?;
+    match predictions {
+        (Ok(a), Ok(b)) => Ok(HttpResponse::Ok().json(diff_predictions(a, b))),
+        (Err(e), _) | (_, Err(e)) => Ok(bad_request(e.code(), e.to_string())),
+    }
+}
+
+/// Per-token word vectors for each sentence, in token order, for client-side pooling
+async fn token_vectors(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    texts: web::Json<Vec<String>>,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(texts.len(), &config) {
+        return Ok(resp);
+    }
+    if let Err(resp) = check_not_quantized(&model, "Token vectors") {
+        return Ok(resp);
+    }
+    let text_count = texts.len();
+    log::info!("Processing {} texts for token vectors", text_count);
+
+    let mut results = Vec::with_capacity(text_count);
+    let mut error_count = 0;
+    for txt in texts.iter() {
+        let input = crate::prepare_input(txt, &config);
+        let tokens = match model.tokenize(&input) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                log::warn!("Tokenization failed for text (length: {}): {}", txt.len(), e);
+                error_count += 1;
+                results.push(Vec::new());
+                continue;
+            }
+        };
+        let mut vectors = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            match model.get_word_vector(&token) {
+                Ok(vector) => vectors.push(TokenVector { token, vector }),
+                Err(e) => {
+                    log::warn!("Word vector failed for token (length: {}): {}", token.len(), e);
+                    // 返回零向量，保持token数量与分词结果一致
+                    vectors.push(TokenVector {
+                        token,
+                        vector: vec![0.0; model.dimension],
+                    });
+                    error_count += 1;
+                }
+            }
+        }
+        results.push(vectors);
+    }
+
+    if error_count > 0 {
+        log::warn!("Token vector processing completed with {} errors", error_count);
+    } else {
+        log::info!("Token vector processing completed successfully: {} texts", text_count);
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Tokens fastText derives from each text after the server's normalization, i.e. what the model sees
+async fn tokenize(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    texts: web::Json<Vec<String>>,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    let config = config.load_full();
+    if let Err(resp) = check_batch_size(texts.len(), &config) {
+        return Ok(resp);
+    }
+    let results: Vec<Vec<String>> = texts
+        .iter()
+        .map(|txt| match model.tokenize(&crate::prepare_input(txt, &config)) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                log::warn!("Tokenization failed for text (length: {}): {}", txt.len(), e);
+                Vec::new()
+            }
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Run the per-text input checks of `/predict` without invoking the model
+async fn validate(
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    inputs: Body<Vec<PredictInput>>,
+) -> ActixResult<HttpResponse> {
+    let config = config.load();
+    let max_text_length = config.for_model(crate::DEFAULT_MODEL).max_text_length;
+    if let Err(resp) = check_batch_size(inputs.len(), &config) {
+        return Ok(resp);
+    }
+    let results: Vec<Validation> = inputs
+        .into_inner()
+        .into_iter()
+        .map(|input| {
+            let text = input.into_text(&config.field_separator);
+            // 与预测时一样，按预处理后的文本检查
+            match crate::validate_text(&crate::prepare_input(&text, &config), max_text_length) {
+                Ok(()) => Validation { valid: true, reason: None },
+                Err(crate::PredictError::InputError(reason)) => Validation {
+                    valid: false,
+                    reason: Some(reason),
+                },
+                Err(e) => Validation {
+                    valid: false,
+                    reason: Some(e.to_string()),
+                },
+            }
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Character n-grams of `<word>` from `minn` to `maxn` characters, in the order fastText's dictionary adds them
+fn char_ngrams(word: &str, minn: usize, maxn: usize) -> Vec<String> {
+    let chars: Vec<char> = format!("<{}>", word).chars().collect();
+    let mut ngrams = Vec::new();
+    for start in 0..chars.len() {
+        for n in minn..=maxn {
+            let end = start + n;
+            if end > chars.len() {
+                break;
+            }
+            // 单字符的n-gram不包括词首词尾的标记
+            if n == 1 && (start == 0 || end == chars.len()) {
+                continue;
+            }
+            ngrams.push(chars[start..end].iter().collect());
+        }
+    }
+    ngrams
+}
+
+/// The word itself when it's in the vocabulary, then its n-grams, each with its row in the input matrix
+fn word_subwords(
+    model: &FastText,
+    word: &str,
+    ngram_sizes: Option<(usize, usize)>,
+) -> Result<(Vec<String>, Vec<i32>), String> {
+    let (mut subwords, mut ids) = (Vec::new(), Vec::new());
+    let id = model.get_word_id(word)?;
+    if id >= 0 {
+        subwords.push(word.to_string());
+        ids.push(id as i32);
+    }
+    if let Some((minn, maxn)) = ngram_sizes {
+        // 与fastText一样，句末标记</s>没有n-gram
+        if word != "</s>" {
+            for ngram in char_ngrams(word, minn, maxn) {
+                ids.push(model.get_subword_id(&ngram)? as i32);
+                subwords.push(ngram);
+            }
+        }
+    }
+    Ok((subwords, ids))
+}
+
+/// Subwords of each word, for debugging how out-of-vocabulary words are represented
+async fn subwords(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    words: web::Json<Vec<String>>,
+) -> ActixResult<HttpResponse> {
+    let model = model.load_full();
+    if let Err(resp) = check_batch_size(words.len(), &config.load()) {
+        return Ok(resp);
+    }
+    let args = model.get_args();
+    let ngram_sizes = if args.maxn() > 0 && args.bucket() > 0 {
+        Some((args.minn().max(1) as usize, args.maxn() as usize))
+    } else {
+        None
+    };
+    let mut results = Vec::with_capacity(words.len());
+    for word in words.into_inner() {
+        // 没有子词的词（如maxn=0时的词表外词）返回空列表
+        let (subwords, ids) = if word.is_empty() {
+            (Vec::new(), Vec::new())
+        } else {
+            word_subwords(&model, &word, ngram_sizes).unwrap_or_else(|e| {
+                log::warn!("Subwords failed for word (length: {}): {}", word.len(), e);
+                (Vec::new(), Vec::new())
+            })
+        };
+        results.push(WordSubwords { word, subwords, ids });
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Load a fresh model and atomically swap it in; the old model keeps serving on failure
+async fn reload(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    readiness: web::Data<Readiness>,
+    body: web::Bytes,
+) -> ActixResult<HttpResponse> {
+    let request: ReloadRequest = if body.is_empty() {
+        ReloadRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => return Ok(bad_request("json_parse_error", format!("Failed to parse JSON: {}", e))),
+        }
+    };
+    let path = request.path.unwrap_or_else(|| config.load().model_path.clone());
+    let _reloading = match readiness.begin_reload() {
+        Some(guard) => guard,
+        None => return Ok(reload_in_progress(&path)),
+    };
+    log::info!("Reloading model from {}", path);
+
+    let started = Instant::now();
+    let warmup = config.load().warmup;
+    readiness.set(false);
+    let loaded = load_replacement(path.clone(), warmup, false).await;
+    // 加载失败时旧模型继续服务，同样恢复就绪
+    readiness.set(true);
+    let new_model = match loaded {
+        Ok(new_model) => new_model,
+        Err(e) => {
+            log::error!("Failed to reload model from {}, keeping the current one: {}", path, e);
+            return Ok(reload_failed(e));
+        }
+    };
+    swap_model(&model, &config, new_model);
+    let elapsed_ms = started.elapsed().as_millis();
+    log::info!("Model reloaded from {} in {}ms", path, elapsed_ms);
+    Ok(HttpResponse::Ok().json(ReloadResponse {
+        path,
+        dimension: model.load().dimension,
+        elapsed_ms,
+    }))
+}
+
+/// Load the model at `path` on a blocking thread, optionally checking that it answers a probe
+/// and warming it up; loading is slow and mustn't stall other connections
+async fn load_replacement(path: String, warmup: bool, validate: bool) -> Result<FastText, String> {
+    let loaded = web::block(move || {
+        let fasttext = crate::remote::load_model(&path)?;
+        if validate {
+            model_responds(&fasttext).map_err(|e| format!("model at {} failed validation: {}", path, e))?;
+        }
+        // 预热完成前不恢复就绪
+        if warmup {
+            if let Err(e) = crate::warm_up(&fasttext) {
+                log::warn!("Warmup of model {} failed: {}", path, e);
+            }
+        }
+        Ok::<_, String>(fasttext)
+    })
+    .await;
+    loaded.map_err(|e| e.to_string())?
+}
+
+/// Atomically serve `new_model`; requests already running keep the model they started with
+fn swap_model(model: &SharedModel, config: &ArcSwap<crate::ServerConfig>, new_model: FastText) {
+    // 探测维度要跑一次模型，在切换之前做完，切换本身只有一次指针写入
+    model.store(Arc::new(crate::ServedModel::new(new_model)));
+    // 缓存的是旧模型的结果
+    if let Some(cache) = &config.load().prediction_cache {
+        cache.clear();
+    }
+}
+
+/// 409 for a reload requested while another one is loading
+fn reload_in_progress(path: &str) -> HttpResponse {
+    log::warn!("Rejecting reload from {}, another reload is in progress", path);
+    HttpResponse::Conflict().json(ErrorResponse {
+        error: "reload_in_progress".to_string(),
+        message: "Another reload is in progress, retry once it has finished".to_string(),
+    })
+}
+
+fn reload_failed(message: String) -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(ErrorResponse {
+        error: "reload_failed".to_string(),
+        message,
+    })
+}
+
+/// Reload for rolling deploys: report not-ready so the load balancer stops sending traffic,
+/// wait up to `--shutdown-timeout` for in-flight requests to finish, load and validate the
+/// new model, warm it up, swap it in and report ready again. Answers with a timeline of the steps
+async fn drain_reload(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    readiness: web::Data<Readiness>,
+    metrics: web::Data<Metrics>,
+    body: web::Bytes,
+) -> ActixResult<HttpResponse> {
+    let request: ReloadRequest = if body.is_empty() {
+        ReloadRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(request) => request,
+            Err(e) => return Ok(bad_request("json_parse_error", format!("Failed to parse JSON: {}", e))),
+        }
+    };
+    let snapshot = config.load_full();
+    let path = request.path.unwrap_or_else(|| snapshot.model_path.clone());
+    let _reloading = match readiness.begin_reload() {
+        Some(guard) => guard,
+        None => return Ok(reload_in_progress(&path)),
+    };
+    let started = Instant::now();
+    let mut timeline = Vec::new();
+    let mut record = |step: &str, ready: bool| {
+        log::info!("Drain reload: {} after {}ms", step, started.elapsed().as_millis());
+        timeline.push(ReloadStep {
+            step: step.to_string(),
+            ready,
+            elapsed_ms: started.elapsed().as_millis(),
+        });
+    };
+
+    readiness.set(false);
+    record("not_ready", false);
+    // 本请求不计入in-flight，等其他请求处理完
+    let deadline = started + Duration::from_secs(snapshot.limits.shutdown_timeout_secs);
+    while metrics.in_flight() > 0 && Instant::now() < deadline {
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    match metrics.in_flight() {
+        0 => record("drained", false),
+        // 超时也继续：正在处理的请求持有旧模型的引用，切换不影响它们
+        in_flight => {
+            log::warn!("Drain reload: {} requests still in flight, swapping anyway", in_flight);
+            record("drain_timeout", false);
+        }
+    }
+
+    let new_model = match load_replacement(path.clone(), snapshot.warmup, true).await {
+        Ok(new_model) => new_model,
+        Err(e) => {
+            readiness.set(true);
+            log::error!("Failed to reload model from {}, keeping the current one: {}", path, e);
+            return Ok(reload_failed(e));
+        }
+    };
+    record(if snapshot.warmup { "loaded_and_warmed_up" } else { "loaded" }, false);
+    swap_model(&model, &config, new_model);
+    record("swapped", false);
+    readiness.set(true);
+    record("ready", true);
+
+    Ok(HttpResponse::Ok().json(DrainReloadResponse {
+        path,
+        dimension: model.load().dimension,
+        elapsed_ms: started.elapsed().as_millis(),
+        timeline,
+    }))
+}
+
+/// Quantize a copy of the model file at `--model` and save it to `output`.
+/// The served model is untouched, the copy is loaded and quantized on a blocking thread
+async fn quantize(
+    model: web::Data<SharedModel>,
+    config: web::Data<ArcSwap<crate::ServerConfig>>,
+    request: web::Json<QuantizeRequest>,
+) -> ActixResult<HttpResponse> {
+    let config = config.load_full();
+    // 会在服务器上写文件，没有配置API key时不开放
+    if config.api_key.is_none() {
+        return Ok(HttpResponse::Forbidden().json(ErrorResponse {
+            error: "admin_disabled".to_string(),
+            message: "Quantization requires the server to run with --api-key".to_string(),
+        }));
+    }
+    if model.load().is_quantized {
+        return Ok(bad_request("quantized_model", "The model is already quantized".to_string()));
+    }
+    let request = request.into_inner();
+    if request.output.is_empty() || request.dsub == 0 {
+        return Ok(bad_request("input_error", "output must be set and dsub must be at least 1".to_string()));
+    }
+    log::info!("Quantizing {} into {}", config.model_path, request.output);
+
+    let started = Instant::now();
+    let output = request.output.clone();
+    let quantized = web::block(move || {
+        let mut model = crate::remote::load_model(&config.model_path)?;
+        let mut args = fasttext::Args::new();
+        args.set_cutoff(request.cutoff);
+        args.set_dsub(request.dsub);
+        args.set_qnorm(request.qnorm);
+        args.set_qout(request.qout);
+        model.quantize(&args)?;
+        model.save_model(&request.output)?;
+        std::fs::metadata(&request.output).map(|meta| meta.len()).map_err(|e| e.to_string())
+    })
+    .await;
+    let size = match quantized {
+        Ok(Ok(size)) => size,
+        Ok(Err(e)) => {
+            log::error!("Failed to quantize the model into {}: {}", output, e);
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse {
+                error: "quantize_failed".to_string(),
+                message: e,
+            }));
+        }
+        Err(e) => return Ok(HttpResponse::from_error(e)),
+    };
+    let elapsed_ms = started.elapsed().as_millis();
+    log::info!("Saved quantized model to {} ({} bytes) in {}ms", output, size, elapsed_ms);
+    Ok(HttpResponse::Ok().json(QuantizeResponse {
+        path: output,
+        size,
+        elapsed_ms,
+    }))
+}
+
+/// OpenAPI document for the core endpoints, served at `/openapi.json`
+#[derive(OpenApi)]
+#[openapi(
+    paths(predict, sentence_vector, health_check),
+    components(schemas(
+        PredictInput,
+        PredictResult,
+        LabelScore,
+        LabeledResult,
+        ItemStatus,
+        InputLength,
+        ResponseFormat,
+        ErrorResponse,
+        HealthResponse
+    ))
+)]
+struct ApiDoc;
+
+async fn openapi_spec() -> ActixResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(ApiDoc::openapi()))
+}
+
+/// CORS policy for `--cors-allow-origin`; preflight requests are answered before the
+/// API key check so browsers can discover the allowed headers. `extra_headers` come from
+/// `--cors-allow-headers` and `max_age` from `--cors-max-age`
+fn cors(origins: &[String], extra_headers: &[String], max_age: Option<usize>) -> Cors {
+    let cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST", "PATCH"])
+        .allowed_headers(vec![
+            header::CONTENT_TYPE,
+            header::CONTENT_ENCODING,
+            header::ACCEPT,
+            header::AUTHORIZATION,
+            header::HeaderName::from_static("x-api-key"),
+        ])
+        .allowed_headers(extra_headers.iter().map(String::as_str))
+        .max_age(max_age);
+    if origins.iter().any(|origin| origin == "*") {
+        return cors.allow_any_origin();
+    }
+    origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
+
+/// Raw body limit for MessagePack requests, matching the JSON limit
+fn payload_config(max_request_size_mb: u32) -> web::PayloadConfig {
+    web::PayloadConfig::new((max_request_size_mb as usize) * 1_048_576)
+}
+
+/// JSON extractor config shared by all POST routes, rejecting bodies above `max_request_size_mb`.
+/// Bodies sent with `Content-Encoding: gzip` are inflated by the extractor, and the limit
+/// applies to the inflated size
+fn json_config(max_request_size_mb: u32) -> web::JsonConfig {
+    // 使用配置参数设置JSON限制
+    web::JsonConfig::default()
+        .limit((max_request_size_mb as usize) * 1_048_576) // MB转换为bytes
+        .content_type(|_mime| true) // Accept any content type
+        .error_handler(|err, _req| {
+            let error_message = format!("Failed to parse JSON: {}", err);
+            log::error!("JSON parsing error: {}", err);
+            actix_web::error::InternalError::from_response(
+                err,
+                HttpResponse::BadRequest().json(ErrorResponse {
+                    error: "json_parse_error".to_string(),
+                    message: error_message,
+                })
+            ).into()
+        })
+}
+
+/// Query extractor config answering unparsable parameters like `?k=abc` with an `ErrorResponse`
+fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let message = format!("Invalid query parameters: {}", err);
+        actix_web::error::InternalError::from_response(
+            err,
+            HttpResponse::BadRequest().json(ErrorResponse {
+                error: "invalid_query".to_string(),
+                message,
+            }),
+        )
+        .into()
+    })
+}
+
+/// Single-threaded runtime for the main system, with the configured stack and blocking pool sizes
+fn runtime_builder(stack_size: Option<usize>, blocking_threads: Option<usize>) -> tokio::runtime::Builder {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    builder.enable_all();
+    if let Some(size) = stack_size {
+        builder.thread_stack_size(size);
+    }
+    if let Some(threads) = blocking_threads {
+        builder.max_blocking_threads(threads);
+    }
+    builder
+}
+
+pub(crate) fn runserver(
+    models: Vec<(String, Arc<crate::ServedModel>)>,
+    address: &str,
+    port: u16,
+    workers: usize,
+    mut config: crate::ServerConfig,
+) {
+    let addrs = parse_addresses(address, port).unwrap_or_else(|e| {
+        log::error!("Invalid --address: {}", e);
+        std::process::exit(1);
+    });
+    let tls_config = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls_config = load_tls_config(cert, key).unwrap_or_else(|e| {
+                log::error!("Failed to load TLS certificate: {}", e);
+                std::process::exit(1);
+            });
+            log::info!("Serving HTTPS with certificate {}", cert);
+            Some(tls_config)
+        }
+        _ => None,
+    };
+    // 第一个模型同时用于不带模型名的路由
+    let mut model_map = ModelMap::new();
+    let mut default_model = None;
+    for (name, model) in models {
+        let model = Arc::new(ArcSwap::new(model));
+        if default_model.is_none() {
+            log::info!("Serving model {} on the default routes", name);
+            default_model = Some(model.clone());
+        }
+        log::info!("Serving model {} under /models/{}/", name, name);
+        model_map.insert(name, model);
+    }
+    let models = model_map;
+    let model_data = web::Data::from(default_model.expect("no model loaded"));
+    for name in config.ensemble_weights.keys() {
+        if !models.contains_key(name) {
+            log::warn!("Ensemble weight configured for unknown model: {}", name);
+        }
+    }
+    if let Some(path) = config.lang_detect_model.clone() {
+        log::info!("Loading language identification model from: {}", path);
+        let detector = crate::remote::load_model(&path).unwrap_or_else(|e| {
+            log::error!("Failed to load language identification model {}: {}", path, e);
+            std::process::exit(1);
+        });
+        let router = LanguageRouter::new(detector, &models);
+        if router.languages().is_empty() {
+            log::warn!("No model is named after a language {} detects, /predict uses the default model", path);
+        } else {
+            log::info!("Routing /predict texts by language to models: {}", router.languages().join(", "));
+        }
+        config.language_router = Some(Arc::new(router));
+    }
+    let models_data = web::Data::new(models);
+    let metrics_data = web::Data::new(Metrics::new());
+    // 模型在启动服务前已加载并预热完成，--ready-after-prediction时还要等第一次成功预测
+    let readiness = Readiness::new(true);
+    let readiness_data = web::Data::new(match config.first_prediction.clone() {
+        Some(first) => readiness.after_prediction(first),
+        None => readiness,
+    });
+    let info_data = web::Data::new(ServerInfo::new(workers));
+    let config_data = web::Data::new(ArcSwap::from_pointee(config.clone()));
+    let vector_cache = match &config.vector_cache_path {
+        Some(path) => {
+            let cache = VectorCache::load(path, model_data.load().get_dimension() as usize).unwrap_or_else(|e| {
+                log::error!("Failed to load vector cache {}: {}", path, e);
+                std::process::exit(1);
+            });
+            log::info!("Loaded {} cached vectors from {}", cache.len(), path);
+            cache
+        }
+        None => VectorCache::default(),
+    };
+    let cache_data = web::Data::new(vector_cache);
+    let word_index = web::Data::new(WordIndex::default());
+    
+    let json_cfg = json_config(config.limits.max_request_size_mb);
+    let payload_cfg = payload_config(config.limits.max_request_size_mb);
+        
+    let connection_limiter = config.limits.max_connections_per_ip.map(|max| {
+        log::info!("Limiting each client IP to {} concurrent requests", max);
+        Arc::new(ConnectionLimiter::new(max))
+    });
+
+    let concurrency_limiter = config.limits.max_concurrent_requests.map(|max| {
+        log::info!("Limiting predictions in flight to {}", max);
+        Arc::new(ConcurrencyLimiter::new(max))
+    });
+    let rate_limiter = config.limits.rate_limit.map(|rate| {
+        log::info!("Limiting each client IP to {} prediction requests per second", rate);
+        Arc::new(RateLimiter::new(rate))
+    });
+    let memory_guard = config.limits.max_memory_mb.map(|limit| {
+        if crate::limits::resident_memory().is_none() {
+            log::warn!("Can't read this process's memory usage, --max-memory-mb has no effect");
+        }
+        log::info!("Shedding predictions while memory usage is above {} MB", limit);
+        Arc::new(MemoryGuard::new(limit))
+    });
+    let api_key: Option<Arc<str>> = config.api_key.as_deref().map(Arc::from);
+    let compression = config.compression;
+    let cors_origins = config.cors_allow_origins.clone();
+    let cors_headers = config.cors_allow_headers.clone();
+    let cors_max_age = config.cors_max_age_secs;
+    if !cors_origins.is_empty() {
+        log::info!("Allowing cross-origin requests from {:?}", cors_origins);
+    }
+    if compression {
+        log::info!("Compressing responses for clients sending Accept-Encoding");
+    }
+    if api_key.is_some() {
+        log::info!("API key required on all routes except {:?}", PUBLIC_PATHS);
+    }
+
+    let admission = Admission {
+        api_key,
+        rate_limiter,
+        // 后台采样任务还要用同一个guard
+        memory_guard: memory_guard.clone(),
+        connection_limiter,
+        concurrency_limiter,
+        metrics: metrics_data.clone().into_inner(),
+    };
+    let draining_metrics = metrics_data.clone();
+    let tcp_nodelay = config.tcp_nodelay;
+    let mut server = HttpServer::new(move || {
+        let admission = admission.clone();
+        App::new()
+            .app_data(query_config())
+            // 在压缩之前包装，读到的是未压缩的响应体
+            .wrap_fn(|req, srv| {
+                let envelope = wants_envelope(&req).then(|| (routed_model(req.path()).to_string(), Instant::now()));
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await?;
+                    match envelope {
+                        Some((model, started)) => wrap_envelope(res, model, started).await,
+                        None => Ok(res.map_into_boxed_body()),
+                    }
+                }
+            })
+            .wrap(Condition::new(compression, Compress::default()))
+            .wrap_fn(move |req, srv| admission.call(req, srv))
+            .wrap(Condition::new(!cors_origins.is_empty(), cors(&cors_origins, &cors_headers, cors_max_age)))
+            .wrap_fn(|req, srv| {
+                let request_id = assign_request_id(&req);
+                let fut = srv.call(req);
+                async move {
+                    let mut res = fut.await?;
+                    echo_request_id(&mut res, &request_id);
+                    Ok(res)
+                }
+            })
+            .service(
+                web::resource("/predict")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(json_cfg.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(predict)),
+            )
+            .service(
+                web::resource("/predict-all")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(json_cfg.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(predict_all)),
+            )
+            .service(
+                web::resource("/batch-predict")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(batch_predict)),
+            )
+            .service(
+                web::resource("/predict-upload")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .route(web::post().to(predict_upload)),
+            )
+            .service(
+                web::resource("/predict/stream")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(predict_stream)),
+            )
+            .service(
+                web::resource("/sentence-vector")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(json_cfg.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(sentence_vector)),
+            )
+            .service(
+                web::resource("/word-vector")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(word_vector)),
+            )
+            .service(
+                web::resource("/models/{name}/predict")
+                    .app_data(models_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(json_cfg.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(model_predict)),
+            )
+            .service(
+                web::resource("/models/{name}/sentence-vector")
+                    .app_data(models_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .app_data(json_cfg.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(model_sentence_vector)),
+            )
+            .service(
+                web::resource("/token-vectors")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(token_vectors)),
+            )
+            .service(
+                web::resource("/tokenize")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(tokenize)),
+            )
+            .service(
+                web::resource("/validate")
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .app_data(payload_cfg.clone())
+                    .route(web::post().to(validate)),
+            )
+            .service(
+                web::resource("/subwords")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(subwords)),
+            )
+            .service(
+                web::resource("/nearest-neighbors")
+                    .app_data(model_data.clone())
+                    .app_data(word_index.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(nearest_neighbors)),
+            )
+            .service(
+                web::resource("/analogy")
+                    .app_data(model_data.clone())
+                    .app_data(word_index.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(analogy)),
+            )
+            .service(
+                web::resource("/diff")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(diff)),
+            )
+            .service(
+                web::resource("/ensemble")
+                    .app_data(models_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(json_cfg.clone())
+                    .route(web::post().to(ensemble)),
+            )
+            .service(
+                web::resource("/cached-vector/{id}")
+                    .app_data(model_data.clone())
+                    .app_data(cache_data.clone())
+                    .route(web::get().to(cached_vector)),
+            )
+            .service(
+                web::resource("/reload")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(readiness_data.clone())
+                    .route(web::post().to(reload)),
+            )
+            .service(
+                web::resource("/drain-reload")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(readiness_data.clone())
+                    .app_data(metrics_data.clone())
+                    .route(web::post().to(drain_reload)),
+            )
+            .service(
+                web::resource("/quantize")
+                    .app_data(config_data.clone())
+                    .route(web::post().to(quantize)),
+            )
+            .service(
+                web::resource("/admin/config")
+                    .app_data(config_data.clone())
+                    .route(web::patch().to(patch_config)),
+            )
+            .service(web::resource("/openapi.json").route(web::get().to(openapi_spec)))
+            .service(
+                web::resource("/model-info")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .route(web::get().to(model_info)),
+            )
+            .service(
+                web::resource("/labels")
+                    .app_data(model_data.clone())
+                    .app_data(config_data.clone())
+                    .route(web::get().to(labels)),
+            )
+            .service(
+                web::resource("/vocab")
+                    .app_data(model_data.clone())
+                    .route(web::get().to(vocab)),
+            )
+            .service(
+                web::resource("/metrics")
+                    .app_data(metrics_data.clone())
+                    .route(web::get().to(metrics_endpoint)),
+            )
+            .service(
+                web::resource("/stats")
+                    .app_data(info_data.clone())
+                    .app_data(config_data.clone())
+                    .app_data(metrics_data.clone())
+                    .route(web::get().to(stats)),
+            )
+            .service(
+                web::resource("/health")
+                    .app_data(model_data.clone())
+                    .app_data(readiness_data.clone())
+                    .route(web::get().to(health_check)),
+            )
+            .service(
+                web::resource("/readyz")
+                    .app_data(model_data.clone())
+                    .app_data(readiness_data.clone())
+                    .route(web::get().to(health_check)),
+            )
+            .service(
+                web::resource("/livez")
+                    .app_data(readiness_data.clone())
+                    .route(web::get().to(liveness)),
+            )
+    })
+    .workers(workers)
+    // 信号由shutdown_signal统一处理，超时后强制关闭剩余连接
+    .disable_signals()
+    .shutdown_timeout(config.limits.shutdown_timeout_secs)
+    .keep_alive(keep_alive(config.limits.keep_alive_secs))
+    .client_request_timeout(Duration::from_millis(config.limits.client_request_timeout_ms))
+    .client_disconnect_timeout(Duration::from_millis(config.limits.client_disconnect_timeout_ms))
+    .backlog(config.backlog)
+    .on_connect(move |conn, _ext| set_nodelay(conn, tcp_nodelay));
+    if let Some(threads) = config.blocking_threads {
+        server = server.worker_max_blocking_threads(threads);
+    }
+
+    let (stack_size, blocking_threads) = (config.worker_stack_size, config.blocking_threads);
+    let sys = System::with_tokio_rt(move || runtime_builder(stack_size, blocking_threads).build().unwrap());
+    for addr in addrs {
+        server = match addr {
+            Address::IpPort(host, port) => {
+                let listeners = bind_tcp(&host, port, config.tcp_nodelay, config.backlog).unwrap_or_else(|e| {
+                    log::error!("Failed to bind {}: {}", Address::IpPort(host.clone(), port), e);
+                    std::process::exit(1);
+                });
+                for listener in listeners {
+                    if let Ok(local) = listener.local_addr() {
+                        log::info!("Listening on {}", local);
+                    }
+                    server = match &tls_config {
+                        Some(tls_config) => server.listen_rustls(listener, tls_config.clone()),
+                        None => server.listen(listener),
+                    }
+                    .expect("bind failed");
+                }
+                server
+            }
+            Address::Unix(path) => {
+                if tls_config.is_some() {
+                    log::error!("TLS is not supported on unix domain sockets");
+                    std::process::exit(1);
+                }
+                log::info!("Listening on {}{}", UNIX_PREFIX, path);
+                #[cfg(unix)]
+                {
+                    server.bind_uds(path).expect("bind failed")
+                }
+                #[cfg(not(unix))]
+                {
+                    panic!("Unix domain socket is not supported on this platform")
+                }
+            }
+        };
+    }
+    if let Some(endpoint) = config.otlp_endpoint.as_deref() {
+        sys.block_on(async { telemetry::init(endpoint) }).unwrap_or_else(|e| {
+            log::error!("Failed to set up OTLP tracing: {}", e);
+            std::process::exit(1);
+        });
+        log::info!("Exporting traces to {}", endpoint);
+    }
+    let shutdown = async move {
+        shutdown_signal().await;
+        actix_web::rt::spawn(log_draining(draining_metrics));
+    };
+    sys.block_on(async move {
+        if let Some(guard) = memory_guard {
+            actix_web::rt::spawn(watch_memory(guard, Duration::from_secs(1), crate::limits::resident_memory));
+        }
+        serve_until(server.run(), shutdown).await
+    })
+    .unwrap();
+    if config.otlp_endpoint.is_some() {
+        // 导出依赖主运行时上的gRPC连接，在blocking线程中等待剩余span发送完
+        let _ = sys.block_on(web::block(telemetry::shutdown));
+    }
+    log::info!("HTTP server stopped");
+}
+
+/// `--keep-alive` seconds as actix's setting, 0 turns keep-alive off
+fn keep_alive(secs: u64) -> KeepAlive {
+    if secs == 0 {
+        KeepAlive::Disabled
+    } else {
+        KeepAlive::Timeout(Duration::from_secs(secs))
+    }
+}
+
+/// Resolves on SIGINT, or SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        let terminate = Box::pin(async move {
+            terminate.recv().await;
+        });
+        futures::future::select(Box::pin(actix_web::rt::signal::ctrl_c()), terminate).await;
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = actix_web::rt::signal::ctrl_c().await;
+    }
+}
+
+/// Run `server` until `shutdown` resolves, then stop accepting connections and let
+/// in-flight requests finish within the server's shutdown timeout
+async fn serve_until<F>(server: actix_web::dev::Server, shutdown: F) -> io::Result<()>
+where
+    F: std::future::Future<Output = ()> + 'static,
+{
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown.await;
+        log::info!("Shutdown signal received, draining in-flight requests");
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+/// Log the number of requests still in flight every second until none are left
+async fn log_draining(metrics: web::Data<Metrics>) {
+    let mut ticker = actix_web::rt::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        let in_flight = metrics.in_flight();
+        log::info!("Draining: {} requests in flight", in_flight);
+        if in_flight == 0 {
+            break;
+        }
+    }
+}
+
+/// Feed `guard` a memory sample every `period`, for as long as the server runs
+async fn watch_memory(guard: Arc<MemoryGuard>, period: Duration, sample: impl Fn() -> Option<u64>) {
+    let mut ticker = actix_web::rt::time::interval(period);
+    loop {
+        ticker.tick().await;
+        if let Some(used) = sample() {
+            guard.update(used);
+        }
+    }
+}
+
+/// Build a rustls server config from PEM encoded certificate chain and private key files
+fn load_tls_config(cert_path: &str, key_path: &str) -> Result<rustls::ServerConfig, String> {
+    let cert_file = fs::File::open(cert_path).map_err(|e| format!("{}: {}", cert_path, e))?;
+    let certs: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut io::BufReader::new(cert_file))
+        .map_err(|e| format!("{}: {}", cert_path, e))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("{}: no certificate found", cert_path));
+    }
+    let key_file = fs::File::open(key_path).map_err(|e| format!("{}: {}", key_path, e))?;
+    let key = rustls_pemfile::read_all(&mut io::BufReader::new(key_file))
+        .map_err(|e| format!("{}: {}", key_path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("{}: no private key found", key_path))?;
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        acquire_slot, analogy, assign_request_id, authorize, batch_predict, bind_tcp, cached_vector, char_ngrams,
+        combine_weighted, cors, diff, diff_predictions, drain_reload, echo_request_id, encode_vectors, health_check,
+        json_config, keep_alive, labels, liveness, load_tls_config, metrics_endpoint, model_checksum, model_info,
+        model_predict, nearest_neighbors, normalized_entropy, openapi_spec, parse_addresses, patch_config, predict,
+        predict_all, predict_items, predict_pool, predict_result, predict_stream, predict_text, predict_upload,
+        quantize, query_config, rate_limit, reload, routed_model, runtime_builder, sentence_vector, serve_until,
+        set_nodelay, stats, subwords, summarize, token_vectors, tokenize, validate, vocab, wants_envelope, watch_memory,
+        word_vector, wrap_envelope, Admission, DiffResponse, InputLength, K_CAPPED, LabelCount, LabelScore, ModelMap,
+        PredictInput, PredictParams, PredictResult, Readiness, ReloadStep, ServerInfo, FirstPrediction, ItemStatus,
+        PREDICTIONS_TRUNCATED, VocabWord,
+    };
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use actix_web::dev::Service;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::{call_service, init_service, read_body, read_body_json, TestRequest};
+    use actix_web::rt::System;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use arc_swap::ArcSwap;
+    use futures::future;
+    use fasttext::FastText;
+
+    use crate::language::LanguageRouter;
+    use crate::limits::{ConcurrencyLimiter, MemoryGuard, RateLimiter};
+    use crate::metrics::Metrics;
+    use crate::neighbors::WordIndex;
+    use crate::prediction_cache::PredictionCache;
+    use crate::vector_cache::VectorCache;
+    use crate::{Limits, ServedModel, ServerConfig};
+
+    fn load_model() -> FastText {
+        let mut fasttext = FastText::new();
+        fasttext
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        fasttext
+    }
+
+    fn model_data(model: FastText) -> web::Data<ArcSwap<ServedModel>> {
+        web::Data::new(ArcSwap::from_pointee(ServedModel::new(model)))
+    }
+
+    fn config_data(config: ServerConfig) -> web::Data<ArcSwap<ServerConfig>> {
+        web::Data::new(ArcSwap::from_pointee(config))
+    }
+
+    /// `call_service` for apps whose middleware rejects with an error, answered the way the server would
+    async fn call_rejectable<S, R, B>(srv: &S, req: R) -> HttpResponse
+    where
+        S: Service<R, Response = actix_web::dev::ServiceResponse<B>, Error = actix_web::Error>,
+        B: actix_web::body::MessageBody + 'static,
+    {
+        match srv.call(req).await {
+            Ok(resp) => resp.into_parts().1.map_into_boxed_body(),
+            Err(err) => err.error_response(),
+        }
+    }
+
+    async fn response_json(resp: HttpResponse) -> serde_json::Value {
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_empty_input() {
+        let model_data = model_data(load_model());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data)
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data: Vec<String> = Vec::new();
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict() {
+        let model_data = model_data(load_model());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data)
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_patch_config_default_threshold() {
+        let model_data = model_data(load_model());
+        let config = config_data(ServerConfig::default());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data)
+                .app_data(config.clone())
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/admin/config").route(web::patch().to(patch_config))),
+        )
+        .await;
+
+        let req = TestRequest::patch()
+            .uri("/admin/config")
+            .set_json(serde_json::json!({ "default_threshold": 1.0 }))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(config.load().default_threshold, 1.0);
+
+        // 阈值为1.0时所有标签都会被过滤
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        let results: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert!(results[0].0.is_empty());
+
+        let req = TestRequest::patch()
+            .uri("/admin/config")
+            .set_json(serde_json::json!({ "model": "models/other.bin" }))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        for patch in [serde_json::json!({ "default_k": 0 }), serde_json::json!({ "default_threshold": 1.5 })] {
+            let req = TestRequest::patch().uri("/admin/config").set_json(&patch).to_request();
+            assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(config.load().default_k, 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_rejects_nan_threshold() {
+        let model_data = model_data(load_model());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data)
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict?threshold=nan")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["error"], "input_error");
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_rejects_invalid_query() {
+        let srv = init_service(
+            App::new()
+                .app_data(query_config())
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        for (query, error) in [
+            ("k=abc", "invalid_query"),
+            ("threshold=high", "invalid_query"),
+            ("k=-1", "invalid_query"),
+            ("k=0", "input_error"),
+            ("threshold=1.5", "input_error"),
+            ("threshold=-0.1", "input_error"),
+        ] {
+            let req = TestRequest::post()
+                .uri(&format!("/predict?{}", query))
+                .set_json(&data)
+                .to_request();
+            let resp = call_service(&srv, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", query);
+            let body: serde_json::Value = read_body_json(resp).await;
+            assert_eq!(body["error"], error, "{}", query);
+        }
+        // 每条文本自带的k同样检查
+        let req = TestRequest::post()
+            .uri("/predict?k=2&threshold=1")
+            .set_json(serde_json::json!([{ "text": "banana bread", "k": 0 }]))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_sentence_vector_binary() {
+        let model_data = model_data(load_model());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data)
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["banana bread", "how to boil an egg"];
+        let req = TestRequest::post()
+            .uri("/sentence-vector")
+            .set_json(&data)
+            .to_request();
+        let expected: Vec<Vec<f32>> = read_body_json(call_service(&srv, req).await).await;
+
+        let req = TestRequest::post()
+            .uri("/sentence-vector")
+            .insert_header((header::ACCEPT, "application/octet-stream"))
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = read_body(resp).await;
+        let count = u32::from_le_bytes([body[0], body[1], body[2], body[3]]) as usize;
+        let dim = u32::from_le_bytes([body[4], body[5], body[6], body[7]]) as usize;
+        assert_eq!(count, expected.len());
+        assert_eq!(body.len(), 8 + count * dim * 4);
+        let vectors: Vec<Vec<f32>> = body[8..]
+            .chunks_exact(dim * 4)
+            .map(|row| {
+                row.chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(vectors, expected);
+        assert_eq!(dim, load_model().get_dimension() as usize);
+    }
+
+    #[test]
+    fn test_encode_vectors_checks_dimension() {
+        let body = encode_vectors(&[vec![1.0, 2.0], vec![0.0, 0.0]], 2).unwrap();
+        assert_eq!(body.len(), 8 + 2 * 2 * 4);
+        // 维度不一致的行会破坏二进制布局，直接拒绝
+        assert!(encode_vectors(&[vec![1.0, 2.0], vec![0.0; 3]], 2).is_err());
+        assert!(encode_vectors(&[vec![1.0, 2.0]], 3).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_oov_report() {
+        let model_data = model_data(load_model());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data)
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["how to bake banana bread with flurbenquox"];
+        let req = TestRequest::post()
+            .uri("/predict?oov_report=true")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let oov = body[0]["oov"].as_array().unwrap();
+        assert!(oov.iter().any(|token| token == "flurbenquox"));
+        assert!(!oov.iter().any(|token| token == "banana"));
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_include_length() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["how to bake banana bread", "crème brûlée"];
+        let req = TestRequest::post()
+            .uri("/predict?include_length=true")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        for (item, text) in body.as_array().unwrap().iter().zip(&data) {
+            let length: InputLength = serde_json::from_value(item["length"].clone()).unwrap();
+            assert_eq!(length, InputLength { bytes: text.len(), chars: text.chars().count() });
+        }
+        // 非ASCII文本的字节数大于字符数
+        assert_eq!(body[1]["length"]["chars"], 12);
+        assert_eq!(body[1]["length"]["bytes"], 15);
+    }
+
+    #[test]
+    fn test_predict_result_uses_error_marker() {
+        let config = ServerConfig {
+            keep_label_prefix: true,
+            marker_message: true,
+            ..Default::default()
+        };
+        let error = || crate::PredictError::InputError("text too long".to_string());
+        let result = predict_result(Err(error()), None, &config);
+        assert!(result.failed);
+        assert_eq!((result.labels, result.scores), config.error_marker(&error()));
+    }
+
+    #[test]
+    fn test_summarize_batch() {
+        let item = |labels: &[&str], scores: &[f32], failed: bool| PredictResult {
+            id: None,
+            status: if failed { ItemStatus::Error } else { ItemStatus::Ok },
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            scores: scores.to_vec(),
+            oov: None,
+            entropy: None,
+            uncertain: None,
+            length: None,
+            fingerprint: None,
+            truncated: None,
+            failed,
+        };
+        let results = vec![
+            item(&["baking"], &[0.8], false),
+            item(&["baking"], &[0.4], false),
+            item(&["eggs"], &[0.6], false),
+            item(&[], &[], false),
+            item(&["error"], &[0.0], true),
+        ];
+        let summary = summarize(&results);
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.error_count, 1);
+        assert!((summary.mean_top_score - 0.45).abs() < 1e-6);
+        assert!((summary.empty_fraction - 0.25).abs() < 1e-6);
+        assert_eq!(summary.label_counts["baking"], 2);
+        assert_eq!(summary.label_counts["eggs"], 1);
+        assert!(!summary.label_counts.contains_key("error"));
+    }
+
+    #[actix_rt::test]
+    async fn test_cached_vector() {
+        let path = std::env::temp_dir().join("fasttext-serving-vector-cache.vec");
+        let model = load_model();
+        let dim = model.get_dimension() as usize;
+        let row = |id: &str, value: f32| format!("{} {}\n", id, vec![value.to_string(); dim].join(" "));
+        std::fs::write(&path, format!("2 {}\n{}{}", dim, row("doc-1", 0.1), row("doc-2", 0.5))).unwrap();
+        let cache = VectorCache::load(path.to_str().unwrap(), dim).unwrap();
+        // 维度与模型不一致的缓存文件在加载时就被拒绝
+        assert!(VectorCache::load(path.to_str().unwrap(), dim + 1).is_err());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(web::Data::new(cache))
+                .service(web::resource("/cached-vector/{id}").route(web::get().to(cached_vector))),
+        )
+        .await;
+        let req = TestRequest::get().uri("/cached-vector/doc-2").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["cached"], true);
+        assert_eq!(body["vector"], serde_json::json!(vec![0.5; dim]));
+
+        let req = TestRequest::get().uri("/cached-vector/banana").to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body["cached"], false);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_stream_sse() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict/stream").route(web::post().to(predict_stream))),
+        )
+        .await;
+        let data = vec!["banana bread", "", "how to boil an egg"];
+        let req = TestRequest::post()
+            .uri("/predict/stream")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "text/event-stream");
+        let body = read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        let events: Vec<&str> = body.split("\n\n").filter(|e| !e.is_empty()).collect();
+        assert_eq!(events.len(), data.len() + 1);
+        assert!(events[..data.len()].iter().all(|e| e.starts_with("event: prediction\n")));
+        assert!(events[data.len()].starts_with("event: done\n"));
+    }
+
+    #[test]
+    fn test_combine_weighted() {
+        let labels = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let predictions = vec![
+            (3.0, labels(&["baking", "eggs"]), vec![0.8, 0.2]),
+            (1.0, labels(&["eggs", "baking"]), vec![0.6, 0.4]),
+        ];
+        let (labels, scores) = combine_weighted(&predictions);
+        assert_eq!(labels, vec!["baking".to_string(), "eggs".to_string()]);
+        // 加权平均必然落在两个模型的得分之间
+        assert!((scores[0] - 0.7).abs() < 1e-6);
+        assert!(scores[0] > 0.4 && scores[0] < 0.8);
+        assert!((scores[1] - 0.3).abs() < 1e-6);
+        assert!(scores[1] > 0.2 && scores[1] < 0.6);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_respects_configured_max_text_length() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["banana", "Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let results: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_ne!(results[0].0, vec!["error".to_string()]);
+        assert_eq!(results[1].0, vec!["error".to_string()]);
+    }
+
+    #[actix_rt::test]
+    async fn test_health_responds_during_large_predict() {
+        let model_data = model_data(load_model());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data.clone())
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .app_data(json_config(500))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread? ".repeat(200); 2_000];
+        let predict_req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let health_req = TestRequest::get().uri("/health").to_request();
+        // 单线程运行时：预测若在handler里同步执行，/health要等它跑完才会被调度
+        let predicting = async {
+            let resp = srv.call(predict_req).await.unwrap();
+            (resp.status(), Instant::now())
+        };
+        let probing = async {
+            let resp = srv.call(health_req).await.unwrap();
+            (resp.status(), Instant::now())
+        };
+        let ((predict_status, predicted_at), (health_status, probed_at)) =
+            future::join(predicting, probing).await;
+        assert_eq!(predict_status, StatusCode::OK);
+        assert_eq!(health_status, StatusCode::OK);
+        assert!(probed_at < predicted_at);
+    }
+
+    #[test]
+    fn test_parallel_batch_matches_serial() {
+        let model = ServedModel::new(load_model());
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 40,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let metrics = Metrics::new();
+        let texts = [
+            "Which baking dish is best to bake a banana bread?",
+            "",
+            "Why not put knives in the dishwasher?",
+            "How long should I boil an egg for a soft yolk in the middle?",
+            "   ",
+        ];
+        // 混合空文本、超长文本和逐条不同的k/threshold，数量超过并行阈值
+        let items: Vec<(String, u32, f32)> = (0..super::PARALLEL_BATCH_SIZE * 2 + 3)
+            .map(|i| (texts[i % texts.len()].to_string(), (i % 3) as u32 + 1, (i % 4) as f32 * 0.1))
+            .collect();
+        let label_count = model.get_labels().unwrap().0.len() as u32;
+        let plain = PredictParams {
+            max_text_length: config.limits.max_text_length,
+            label_prefix: config.label_prefix().map(str::to_string),
+            ..Default::default()
+        };
+        let uncertainty = PredictParams {
+            label_count: Some(label_count),
+            uncertainty: true,
+            ..plain.clone()
+        };
+        for params in [plain, uncertainty].iter() {
+            let parallel: Vec<_> = predict_items(&model, &config, &metrics, &items, params)
+                .into_iter()
+                .map(|prediction| prediction.map_err(|e| e.to_string()))
+                .collect();
+            let serial: Vec<_> = items
+                .iter()
+                .map(|(txt, k, threshold)| {
+                    let input = crate::prepare_input(txt, &config);
+                    predict_text(&model, &input, *k, *threshold, params).map_err(|e| e.to_string())
+                })
+                .collect();
+            assert_eq!(parallel, serial);
+            assert!(serial.iter().any(|prediction| prediction.is_err()));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_batch_predict_plain_lines() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/batch-predict").route(web::post().to(batch_predict))),
+        )
+        .await;
+        let body = "Which baking dish is best to bake a banana bread?\r\nWhy are knives dull?\n\nbanana\n";
+        let req = TestRequest::post()
+            .uri("/batch-predict?k=2")
+            .insert_header((header::CONTENT_TYPE, "text/plain"))
+            .set_payload(body)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/x-ndjson");
+        let body = read_body(resp).await;
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line is a JSON value"))
+            .collect();
+        // 末尾换行不会多出一条，中间的空行得到错误标记
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0][0].as_array().unwrap().len(), 2);
+        assert_eq!(lines[2][0][0], "error");
+        assert_eq!(lines[3][0].as_array().unwrap().len(), 2);
+
+        let req = TestRequest::post().uri("/batch-predict").set_payload("").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(read_body(resp).await.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_threads() {
+        let pool = Arc::new(predict_pool(2));
+        assert_eq!(pool.current_num_threads(), 2);
+        let config = ServerConfig {
+            predict_threads: Some(2),
+            predict_pool: Some(pool),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        // 足够大的批次才会交给线程池
+        let data = vec!["Which baking dish is best to bake a banana bread?"; super::PARALLEL_BATCH_SIZE * 2];
+        let req = TestRequest::post().uri("/predict?k=2").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_eq!(body.len(), data.len());
+        assert!(body.iter().all(|(labels, _)| labels.len() == 2 && labels[0] != "error"));
+        assert!(body.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_upload() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict-upload").route(web::post().to(predict_upload))),
+        )
+        .await;
+        let upload = |field: &str, content: &str| {
+            let body = format!(
+                "--BOUNDARY\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"texts.txt\"\r\n\
+                 Content-Type: text/plain\r\n\r\n{}\r\n--BOUNDARY--\r\n",
+                field, content
+            );
+            TestRequest::post()
+                .uri("/predict-upload?k=2")
+                .insert_header((header::CONTENT_TYPE, "multipart/form-data; boundary=BOUNDARY"))
+                .set_payload(body)
+                .to_request()
+        };
+        let req = upload("file", "Which baking dish is best to bake a banana bread?\nWhy are knives dull?\nbanana\n");
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/x-ndjson");
+        let body = read_body(resp).await;
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line is a JSON value"))
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line[0].as_array().unwrap().len() == 2));
+
+        let resp = call_service(&srv, upload("other", "banana")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Train a small supervised model on `data`, one `__label__x text` example per line
+    fn train_model(data: &str) -> FastText {
+        let dir = std::env::temp_dir().join(format!("fasttext-train-{}-{}", std::process::id(), data.len()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("train.txt");
+        std::fs::write(&input, data).unwrap();
+        let mut args = fasttext::Args::new();
+        args.set_input(input.to_str().unwrap()).unwrap();
+        args.set_model(fasttext::ModelName::SUP);
+        args.set_loss(fasttext::LossName::SOFTMAX);
+        args.set_min_count(1);
+        args.set_lr(1.0);
+        args.set_epoch(50);
+        args.set_thread(1);
+        args.set_verbose(0);
+        let mut model = FastText::new();
+        model.train(&args).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        model
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_routes_by_language() {
+        let detector = train_model(concat!(
+            "__label__en how to bake banana bread\n",
+            "__label__en why not put knives in the dishwasher\n",
+            "__label__fr comment faire du pain à la banane\n",
+            "__label__fr pourquoi ne pas mettre les couteaux au lave-vaisselle\n",
+        ));
+        let french = train_model("__label__cuisine pain banane couteaux\n");
+        // 只有fr注册了模型，英文回落到默认模型
+        let mut models = ModelMap::new();
+        models.insert("fr".to_string(), Arc::new(ArcSwap::from_pointee(ServedModel::new(french))));
+        models.insert("de".to_string(), Arc::new(ArcSwap::from_pointee(ServedModel::new(load_model()))));
+        let router = LanguageRouter::new(detector, &models);
+        assert_eq!(router.languages(), vec!["fr"]);
+        let config = ServerConfig {
+            language_router: Some(Arc::new(router)),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["how to bake banana bread", "comment faire du pain à la banane"];
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_ne!(body[0].0, vec!["cuisine".to_string()]);
+        assert_eq!(body[1].0, vec!["cuisine".to_string()]);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_unique_labels() {
+        // 去掉前缀__label__z后，第一个标签与第二个同名
+        let model = train_model(concat!(
+            "__label__z__label__x banana bread\n",
+            "__label__x banana loaf\n",
+            "__label__y knives in the dishwasher\n",
+        ));
+        let config = ServerConfig {
+            label_prefix: "__label__z".to_string(),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["banana bread"];
+        let req = TestRequest::post().uri("/predict?k=3").set_json(&data).to_request();
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        let (labels, scores) = &body[0];
+        assert_eq!(labels.iter().filter(|label| *label == "__label__x").count(), 2);
+        let best_x = labels
+            .iter()
+            .zip(scores)
+            .filter(|(label, _)| *label == "__label__x")
+            .map(|(_, score)| *score)
+            .fold(0.0, f32::max);
+
+        let req = TestRequest::post().uri("/predict?k=3&unique_labels=true").set_json(&data).to_request();
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        let (labels, scores) = &body[0];
+        let mut unique = labels.clone();
+        unique.sort();
+        assert_eq!(unique, vec!["__label__x", "__label__y"]);
+        let x = labels.iter().position(|label| label == "__label__x").unwrap();
+        assert_eq!(scores[x], best_x);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_fields() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post().uri("/predict?k=2&fields=labels").set_json(&data).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body[0]["labels"].as_array().unwrap().len(), 2);
+        assert!(body[0].get("scores").is_none());
+
+        let req = TestRequest::post().uri("/predict?k=2&fields=scores").set_json(&data).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body[0]["scores"].as_array().unwrap().len(), 2);
+        assert!(body[0].get("labels").is_none());
+
+        // predictions数组里标签和得分成对出现，无法只保留一个
+        let req = TestRequest::post().uri("/predict?fields=labels&format=objects").set_json(&data).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_matrix() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?",
+            "Why not put knives in the dishwasher?",
+            "How do I cover up the white spots on my cast iron stove?",
+        ];
+        let req = TestRequest::post().uri("/predict?k=3").set_json(&data).to_request();
+        let pairs: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        let req = TestRequest::post().uri("/predict?k=3&matrix=true").set_json(&data).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        let labels: Vec<Vec<String>> = serde_json::from_value(body["labels"].clone()).unwrap();
+        let scores: Vec<Vec<f32>> = serde_json::from_value(body["scores"].clone()).unwrap();
+        assert_eq!(labels.len(), 3);
+        assert_eq!(scores.len(), 3);
+        assert!(labels.iter().all(|row| row.len() == 3));
+        assert!(scores.iter().all(|row| row.len() == 3));
+        let rows: Vec<(Vec<String>, Vec<f32>)> = labels.into_iter().zip(scores).collect();
+        assert_eq!(rows, pairs);
+
+        // 条目的k不同，行长度不一致时退回逐条格式
+        let mixed = serde_json::json!([data[0], { "text": data[1], "k": 1 }]);
+        let req = TestRequest::post().uri("/predict?k=3&matrix=true").set_json(&mixed).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(body[1][0].as_array().unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_group_depth() {
+        let model = train_model(concat!(
+            "__label__food/dessert/cake chocolate cake with frosting\n",
+            "__label__food/dessert/pie apple pie with cinnamon\n",
+            "__label__food/main/steak grilled steak with pepper\n",
+            "__label__tools/knife sharpen a kitchen knife\n",
+        ));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["apple cake with frosting"];
+        let mut results = Vec::new();
+        for query in ["", "&group_depth=1", "&group_depth=2", "&group_depth=5"] {
+            let req = TestRequest::post().uri(&format!("/predict?k=4{}", query)).set_json(&data).to_request();
+            let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+            let (labels, scores) = body.into_iter().next().unwrap();
+            results.push(labels.into_iter().zip(scores).collect::<HashMap<String, f32>>());
+        }
+        let all = &results[0];
+        assert_eq!(all.len(), 4);
+        let sum = |prefix: &str| all.iter().filter(|(label, _)| label.starts_with(prefix)).map(|(_, s)| s).sum::<f32>();
+
+        let groups = &results[1];
+        assert_eq!(groups.len(), 2);
+        assert!((groups["food"] - sum("food/")).abs() < 1e-5);
+        assert!((groups["tools"] - all["tools/knife"]).abs() < 1e-5);
+
+        let groups = &results[2];
+        assert_eq!(groups.len(), 3);
+        assert!((groups["food/dessert"] - sum("food/dessert/")).abs() < 1e-5);
+        assert!((groups["food/main"] - all["food/main/steak"]).abs() < 1e-5);
+
+        // 深度超过路径长度时保持原标签
+        assert_eq!(results[3].len(), 4);
+        let req = TestRequest::post().uri("/predict?group_depth=0").set_json(&data).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_fingerprint() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["banana bread", "banana bread", "how to boil an egg"];
+        let req = TestRequest::post().uri("/predict?fingerprint=true").set_json(&data).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        let fingerprints: Vec<&str> = (0..3).map(|i| body[i]["fingerprint"].as_str().unwrap()).collect();
+        assert_eq!(fingerprints[0].len(), 16);
+        assert_eq!(fingerprints[0], fingerprints[1]);
+        assert_ne!(fingerprints[0], fingerprints[2]);
+
+        // 同一模型在另一个请求中给出相同的指纹，参数不同则不同
+        let req = TestRequest::post().uri("/predict?fingerprint=true").set_json(&data).to_request();
+        let again: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(again[0]["fingerprint"], body[0]["fingerprint"]);
+        let req = TestRequest::post().uri("/predict?fingerprint=true&k=2").set_json(&data).to_request();
+        let other: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_ne!(other[0]["fingerprint"], body[0]["fingerprint"]);
+
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let plain: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(plain.len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_seed_breaks_ties() {
+        // 学习率为0时输出层保持全零，所有标签的概率完全相同
+        let dir = std::env::temp_dir().join(format!("fasttext-seed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("train.txt");
+        std::fs::write(&input, "__label__a one\n__label__b two\n__label__c three\n__label__d four\n").unwrap();
+        let mut args = fasttext::Args::new();
+        args.set_input(input.to_str().unwrap()).unwrap();
+        args.set_model(fasttext::ModelName::SUP);
+        args.set_loss(fasttext::LossName::SOFTMAX);
+        args.set_min_count(1);
+        args.set_lr(0.0);
+        args.set_epoch(1);
+        args.set_thread(1);
+        args.set_verbose(0);
+        let mut model = FastText::new();
+        model.train(&args).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["one two"];
+        let mut orders = Vec::new();
+        for query in ["", "&seed=7", "&seed=7", "&seed=1", "&seed=2", "&seed=3", "&seed=4", "&seed=5"] {
+            let req = TestRequest::post().uri(&format!("/predict?k=4{}", query)).set_json(&data).to_request();
+            let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+            let (labels, scores) = body.into_iter().next().unwrap();
+            assert!(scores.iter().all(|score| *score == scores[0]));
+            orders.push(labels);
+        }
+        assert_eq!(orders[1], orders[2]);
+        let distinct: HashSet<&Vec<String>> = orders[1..].iter().collect();
+        assert!(distinct.len() > 1, "{:?}", orders);
+    }
+
+    #[actix_rt::test]
+    async fn test_split_lines_nests_results() {
+        let config = ServerConfig {
+            split_lines: true,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?\nWhy not put knives in the dishwasher?",
+            "banana bread\n",
+        ];
+        let req = TestRequest::post()
+            .uri("/predict?format=objects&k=2")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        // 两行文本得到两条结果，只有末尾换行的文本仍是单条结果
+        let lines = body[0].as_array().expect("multi-line text should give a nested list");
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line["predictions"].as_array().unwrap().len() == 2));
+        assert_eq!(body[1]["predictions"].as_array().unwrap().len(), 2);
+
+        let req = TestRequest::post()
+            .uri("/predict?format=objects&k=2")
+            .set_json(vec![data[0].replace('\n', " ")])
+            .to_request();
+        let single: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert!(single[0].is_object());
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_ndjson_stream() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?",
+            "Why not put knives in the dishwasher?",
+            "",
+        ];
+        let req = TestRequest::post()
+            .uri("/predict?stream=true&format=objects&k=2")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/x-ndjson");
+        let body = read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.ends_with('\n'));
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line is a JSON value"))
+            .collect();
+        assert_eq!(lines.len(), data.len());
+        assert_eq!(lines[0]["predictions"].as_array().unwrap().len(), 2);
+        assert_eq!(lines[2]["predictions"][0]["label"], "error");
+
+        let req = TestRequest::post()
+            .uri("/predict?format=objects&k=2")
+            .set_json(&data)
+            .to_request();
+        let batch: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(serde_json::Value::Array(lines), batch);
+    }
+
+    #[actix_rt::test]
+    async fn test_server_timing_header() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        for (uri, metric) in [("/predict", "predict"), ("/sentence-vector", "sentence_vector")].iter() {
+            let req = TestRequest::post().uri(uri).set_json(&data).to_request();
+            let resp = call_service(&srv, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let timing = resp.headers().get("server-timing").expect("missing Server-Timing header");
+            let timing = timing.to_str().unwrap();
+            let prefix = format!("{};dur=", metric);
+            assert!(timing.starts_with(&prefix), "unexpected Server-Timing: {}", timing);
+            let millis: f64 = timing[prefix.len()..].parse().unwrap();
+            assert!(millis >= 0.0);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_envelope() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .wrap_fn(|req, srv| {
+                    let envelope = wants_envelope(&req).then(|| (routed_model(req.path()).to_string(), Instant::now()));
+                    let fut = srv.call(req);
+                    async move {
+                        let res = fut.await?;
+                        match envelope {
+                            Some((model, started)) => wrap_envelope(res, model, started).await,
+                            None => Ok(res.map_into_boxed_body()),
+                        }
+                    }
+                })
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?", "Why are knives dull?", "banana"];
+        let req = TestRequest::post().uri("/predict?envelope=true&k=2").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let results = body["data"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(body["meta"]["count"], 3);
+        assert_eq!(body["meta"]["model"], "default");
+        assert!(body["meta"]["processing_ms"].as_f64().unwrap() >= 0.0);
+
+        // 默认仍是裸数组，错误响应不包装
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body.as_array().unwrap().len(), 3);
+        let req = TestRequest::post().uri("/predict?envelope=true&k=0").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert!(body["error"].is_string());
+    }
+
+    #[test]
+    fn test_routed_model() {
+        assert_eq!(routed_model("/predict"), "default");
+        assert_eq!(routed_model("/models/spam/predict"), "spam");
+        assert_eq!(routed_model("/models//predict"), "default");
+    }
+
+    #[actix_rt::test]
+    async fn test_request_id_echoed() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .wrap_fn(|req, srv| {
+                    let request_id = assign_request_id(&req);
+                    let fut = srv.call(req);
+                    async move {
+                        let mut res = fut.await?;
+                        echo_request_id(&mut res, &request_id);
+                        Ok(res)
+                    }
+                })
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header(("X-Request-Id", "client-abc-123"))
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), "client-abc-123");
+
+        // 没有带ID时服务端生成一个
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        let generated = resp.headers().get("x-request-id").unwrap().to_str().unwrap();
+        assert_eq!(generated.len(), 36);
+    }
+
+    #[actix_rt::test]
+    async fn test_spans_emitted_per_request() {
+        use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+        use opentelemetry::trace::{TraceId, TracerProvider};
+        use opentelemetry::{Key, Value};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        #[derive(Debug, Clone, Default)]
+        struct MockExporter(Arc<std::sync::Mutex<Vec<SpanData>>>);
+
+        impl SpanExporter for MockExporter {
+            fn export(&mut self, batch: Vec<SpanData>) -> futures::future::BoxFuture<'static, ExportResult> {
+                self.0.lock().unwrap().extend(batch);
+                Box::pin(futures::future::ready(Ok(())))
+            }
+        }
+
+        let exporter = MockExporter::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+        let guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?", "Why are knives dull?"];
+        let req = TestRequest::post()
+            .uri("/predict?k=2")
+            .insert_header(("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"))
+            .set_json(&data)
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+        let req = TestRequest::post().uri("/predict").set_json(&data[..1]).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+        let req = TestRequest::post().uri("/sentence-vector").set_json(&data).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+
+        // 关闭provider会等待simple exporter导出完所有span
+        drop(guard);
+        drop(provider);
+        let spans = exporter.0.lock().unwrap();
+        let predict_spans: Vec<_> = spans.iter().filter(|span| span.name == "predict").collect();
+        assert_eq!(predict_spans.len(), 2);
+        assert_eq!(spans.iter().filter(|span| span.name == "sentence_vector").count(), 1);
+
+        let traced = predict_spans
+            .iter()
+            .find(|span| span.attributes.get(&Key::new("batch_size")) == Some(&Value::I64(2)))
+            .expect("missing span for the first request");
+        assert_eq!(traced.attributes.get(&Key::new("k")), Some(&Value::I64(2)));
+        assert_eq!(
+            traced.span_context.trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        let untraced = predict_spans.iter().find(|span| span.span_context.trace_id() != traced.span_context.trace_id());
+        assert!(untraced.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn test_token_vectors() {
+        let model = load_model();
+        let dim = model.get_dimension() as usize;
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/token-vectors").route(web::post().to(token_vectors))),
+        )
+        .await;
+        let data = vec!["how to bake banana bread"];
+        let req = TestRequest::post()
+            .uri("/token-vectors")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let tokens = body[0].as_array().unwrap();
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[3]["token"], "banana");
+        assert!(tokens.iter().all(|t| t["vector"].as_array().unwrap().len() == dim));
+    }
+
+    #[actix_rt::test]
+    async fn test_tokenize() {
+        let config = ServerConfig {
+            normalize_lowercase: true,
+            normalize_strip_punct: true,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .service(web::resource("/tokenize").route(web::post().to(tokenize))),
+        )
+        .await;
+        let data = vec!["How to bake banana bread?", ""];
+        let req = TestRequest::post().uri("/tokenize").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Vec<Vec<String>> = read_body_json(resp).await;
+        // 归一化后问号变成空格，大写被转为小写
+        assert_eq!(body[0], vec!["how", "to", "bake", "banana", "bread"]);
+        assert!(body[1].is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_validate_flags_bad_inputs() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 20,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(config_data(config))
+                .service(web::resource("/validate").route(web::post().to(validate))),
+        )
+        .await;
+        let data = vec!["banana bread", "", "a much longer text than twenty bytes"];
+        let req = TestRequest::post().uri("/validate").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body[0], serde_json::json!({ "valid": true }));
+        assert_eq!(body[1]["valid"], false);
+        assert_eq!(body[1]["reason"], "Empty text input");
+        assert_eq!(body[2]["valid"], false);
+        assert!(body[2]["reason"].as_str().unwrap().starts_with("Text too long"));
+    }
+
+    #[test]
+    fn test_char_ngrams() {
+        assert_eq!(char_ngrams("ab", 1, 3), vec!["<a", "<ab", "a", "ab", "ab>", "b", "b>"]);
+        // 按字符而不是字节切分
+        assert_eq!(char_ngrams("é", 3, 3), vec!["<é>"]);
+        assert!(char_ngrams("ab", 5, 6).is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_subwords() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .service(web::resource("/subwords").route(web::post().to(subwords))),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/subwords")
+            .set_json(vec!["banana", "qzxvkjw", ""])
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body[0]["word"], "banana");
+        let subwords = body[0]["subwords"].as_array().unwrap();
+        assert!(!subwords.is_empty());
+        assert_eq!(subwords.len(), body[0]["ids"].as_array().unwrap().len());
+        // 词表外的词和空字符串不会报错
+        assert_eq!(body[1]["word"], "qzxvkjw");
+        assert!(body[2]["subwords"].as_array().unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_sentence_vector_fallback_uses_model_dimension() {
+        let model = load_model();
+        let dim = model.get_dimension() as usize;
+        // 故意配置一个与模型不一致的维度
+        let config = ServerConfig {
+            default_vector_dim: dim + 7,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["banana bread", "invalid\u{0}text"];
+        let req = TestRequest::post()
+            .uri("/sentence-vector")
+            .set_json(&data)
+            .to_request();
+        let vectors: Vec<Vec<f32>> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(vectors.len(), 2);
+        assert!(vectors.iter().all(|v| v.len() == dim));
+        assert!(vectors[1].iter().all(|x| *x == 0.0));
+    }
+
+    #[actix_rt::test]
+    async fn test_reload_keeps_serving() {
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/reload").route(web::post().to(reload))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let predict_req = || {
+            TestRequest::post()
+                .uri("/predict")
+                .set_json(&data)
+                .to_request()
+        };
+        let before: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+
+        let req = TestRequest::post().uri("/reload").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let after: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+        assert_eq!(before, after);
+
+        let req = TestRequest::post()
+            .uri("/reload")
+            .set_json(serde_json::json!({ "path": "models/missing.bin" }))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let still: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+        assert_eq!(before, still);
+    }
+
+    #[test]
+    fn test_snapshot_never_half_swapped() {
+        // 维度和模型在同一个快照里，两个线程同时切换也读不到别的模型的维度
+        let cooking = Arc::new(ServedModel::new(load_model()));
+        let other = Arc::new(ServedModel::new(train_model(
+            "__label__alpha banana bread dish\n__label__beta knife steel edge\n",
+        )));
+        let model = Arc::new(ArcSwap::new(cooking.clone()));
+        let done = Arc::new(AtomicBool::new(false));
+        let swappers: Vec<_> = [(cooking.clone(), other.clone()), (other.clone(), cooking.clone())]
+            .iter()
+            .cloned()
+            .map(|(first, second)| {
+                let model = model.clone();
+                std::thread::spawn(move || {
+                    for i in 0..2000 {
+                        model.store(if i % 2 == 0 { first.clone() } else { second.clone() });
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (model, done) = (model.clone(), done.clone());
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::SeqCst) {
+                        let model = model.load_full();
+                        assert_eq!(model.dimension, model.get_dimension() as usize);
+                    }
+                })
+            })
+            .collect();
+        for swapper in swappers {
+            swapper.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+
+    #[actix_rt::test]
+    async fn test_predict_during_reloads() {
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/reload").route(web::post().to(reload))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?", "Why not put knives in the dishwasher?"];
+        let expected: Vec<(Vec<String>, Vec<f32>)> = {
+            let req = TestRequest::post().uri("/predict?k=2").set_json(&data).to_request();
+            read_body_json(srv.call(req).await.unwrap()).await
+        };
+        for _ in 0..5 {
+            let reloads = (0..4).map(|_| srv.call(TestRequest::post().uri("/reload").to_request()));
+            let predict_req = || TestRequest::post().uri("/predict?k=2").set_json(&data).to_request();
+            let predicts = (0..50).map(|_| srv.call(predict_req()));
+            let (reloaded, predicted) = future::join(future::join_all(reloads), future::join_all(predicts)).await;
+            for resp in reloaded {
+                // 同时只允许一个重载，其余的应当被拒绝而不是出错
+                let status = resp.unwrap().status();
+                assert!(status == StatusCode::OK || status == StatusCode::CONFLICT, "reload answered {}", status);
+            }
+            for resp in predicted {
+                let resp = resp.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+                assert_eq!(body, expected);
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_prediction_cache() {
+        let cache = Arc::new(PredictionCache::new(16));
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            prediction_cache: Some(cache.clone()),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/reload").route(web::post().to(reload))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let predict_req = || TestRequest::post().uri("/predict?k=2").set_json(&data).to_request();
+        let first: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+        let second: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+        assert_eq!(first, second);
+        // 第二次直接命中缓存，模型只调用了一次
+        assert_eq!((cache.misses(), cache.hits()), (1, 1));
+
+        let resp = call_service(&srv, TestRequest::post().uri("/reload").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(cache.len(), 0);
+        let _ = call_service(&srv, predict_req()).await;
+        assert_eq!(cache.misses(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn test_prediction_cache_keyed_by_model() {
+        let mut other = train_model("__label__alpha banana bread dish\n__label__beta knife steel edge\n");
+        let path = std::env::temp_dir().join(format!("fasttext-cache-reload-{}.bin", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        other.save_model(&path).unwrap();
+        let cache = Arc::new(PredictionCache::new(16));
+        let cooking = load_model();
+        assert_ne!(model_checksum(&cooking), model_checksum(&other));
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            prediction_cache: Some(cache.clone()),
+            warmup: false,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(cooking))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/reload").route(web::post().to(reload))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let predict_req = || TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let before: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+        assert!(!before[0].0.is_empty());
+
+        let req = TestRequest::post().uri("/reload").set_json(serde_json::json!({"path": path})).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+        std::fs::remove_file(&path).ok();
+        // 同一文本在新模型上重新预测，不会拿到旧模型的标签
+        let after: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, predict_req()).await).await;
+        assert_ne!(before, after);
+        assert!(after[0].0.iter().all(|label| label == "alpha" || label == "beta"), "{:?}", after);
+        assert_eq!(cache.hits(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_multi_field_input() {
+        let input: PredictInput = serde_json::from_str(r#"{"fields": ["banana bread", "which dish"]}"#).unwrap();
+        assert_eq!(input.into_text(" | "), "banana bread | which dish");
+
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = serde_json::json!([
+            { "fields": ["Which baking dish", "is best to bake a banana bread?"] },
+            "Which baking dish is best to bake a banana bread?",
+        ]);
+        let req = TestRequest::post()
+            .uri("/predict?k=3")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let results: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[actix_rt::test]
+    async fn test_multiple_named_models() {
+        let mut models = ModelMap::new();
+        models.insert("a".to_string(), Arc::new(ArcSwap::from_pointee(ServedModel::new(load_model()))));
+        models.insert("b".to_string(), Arc::new(ArcSwap::from_pointee(ServedModel::new(load_model()))));
+        let mut config = ServerConfig::default();
+        config.model_overrides.insert(
+            "b".to_string(),
+            crate::ModelOverrides {
+                default_k: Some(3),
+                ..Default::default()
+            },
+        );
+        let srv = init_service(
+            App::new()
+                .app_data(web::Data::new(models))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/models/{name}/predict").route(web::post().to(model_predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        for (name, expected) in &[("a", 1), ("b", 3)] {
+            let req = TestRequest::post()
+                .uri(&format!("/models/{}/predict", name))
+                .set_json(&data)
+                .to_request();
+            let results: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+            assert_eq!(results[0].0.len(), *expected);
+        }
+        let req = TestRequest::post()
+            .uri("/models/missing/predict")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_normalized_entropy() {
+        assert!((normalized_entropy(&[0.25, 0.25, 0.25, 0.25]) - 1.0).abs() < 1e-6);
+        assert!(normalized_entropy(&[0.97, 0.01, 0.01, 0.01]) < 0.2);
+        assert_eq!(normalized_entropy(&[1.0]), 0.0);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_flags_uncertain() {
+        let config = ServerConfig {
+            uncertainty_threshold: 0.0,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict?uncertainty=true&k=2")
+            .set_json(&data)
+            .to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body[0]["labels"].as_array().unwrap().len(), 2);
+        assert!(body[0]["entropy"].as_f64().unwrap() > 0.0);
+        assert_eq!(body[0]["uncertain"], true);
+    }
+
+    #[actix_rt::test]
+    async fn test_stats() {
+        let metrics = web::Data::new(Metrics::new());
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            loaded_models: vec!["default".to_string()],
+            failed_models: vec![crate::FailedModel {
+                name: "broken".to_string(),
+                path: "models/broken.bin".to_string(),
+                error: "not a model".to_string(),
+            }],
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(web::Data::new(ServerInfo::new(4)))
+                .app_data(config_data(config))
+                .app_data(metrics.clone())
+                .service(web::resource("/stats").route(web::get().to(stats))),
+        )
+        .await;
+        // 模拟中间件处理完的一个请求
+        drop(metrics.clone().into_inner().enter());
+        let body: serde_json::Value =
+            read_body_json(call_service(&srv, TestRequest::get().uri("/stats").to_request()).await).await;
+        assert!(body["uptime_seconds"].is_u64());
+        assert_eq!(body["total_requests"], 1);
+        assert_eq!(body["in_flight"], 0);
+        assert_eq!(body["workers"], 4);
+        assert_eq!(body["model_path"], "models/cooking.model.bin");
+        assert_eq!(body["loaded_models"], serde_json::json!(["default"]));
+        assert_eq!(body["failed_models"][0]["name"], "broken");
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics_counts_predictions() {
+        let metrics = web::Data::new(Metrics::new());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(metrics.clone())
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/metrics").route(web::get().to(metrics_endpoint))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        call_service(&srv, req).await;
+
+        let req = TestRequest::get().uri("/metrics").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("fasttext_predict_requests_total{endpoint=\"predict\"} 1"));
+        assert!(body.contains("fasttext_predict_duration_seconds_count{endpoint=\"predict\"} 1"));
+    }
+
+    #[actix_rt::test]
+    async fn test_per_label_metrics() {
+        for per_label_metrics in [false, true] {
+            let config = ServerConfig {
+                per_label_metrics,
+                ..Default::default()
+            };
+            let srv = init_service(
+                App::new()
+                    .app_data(model_data(load_model()))
+                    .app_data(config_data(config))
+                    .app_data(web::Data::new(Metrics::new()))
+                    .service(web::resource("/predict").route(web::post().to(predict)))
+                    .service(web::resource("/metrics").route(web::get().to(metrics_endpoint))),
+            )
+            .await;
+            let data = vec!["Which baking dish is best to bake a banana bread?"; 2];
+            let req = TestRequest::post().uri("/predict?k=3").set_json(&data).to_request();
+            let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+            let top = &body[0].0[0];
+
+            let req = TestRequest::get().uri("/metrics").to_request();
+            let metrics = read_body(call_service(&srv, req).await).await;
+            let metrics = std::str::from_utf8(&metrics).unwrap();
+            // 只统计top-1标签
+            let counter = format!("fasttext_label_predicted_total{{label=\"{}\"}} 2", top);
+            assert_eq!(metrics.contains(&counter), per_label_metrics, "{}", metrics);
+            assert_eq!(metrics.matches("fasttext_label_predicted_total{").count(), per_label_metrics as usize);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics_input_length() {
+        let metrics = web::Data::new(Metrics::new());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(metrics.clone())
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/metrics").route(web::get().to(metrics_endpoint))),
+        )
+        .await;
+        let data = vec!["banana bread".to_string(), "banana bread ".repeat(10)];
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        call_service(&srv, req).await;
+
+        let req = TestRequest::get().uri("/metrics").to_request();
+        let body = read_body(call_service(&srv, req).await).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("fasttext_input_length_bytes_count 2"));
+        assert!(body.contains("fasttext_input_length_bytes_sum 142"));
+        // 12字节落在16的桶里，130字节要到256的桶
+        assert!(body.contains("fasttext_input_length_bytes_bucket{le=\"16\"} 1"));
+        assert!(body.contains("fasttext_input_length_bytes_bucket{le=\"256\"} 2"));
+    }
+
+    #[test]
+    fn test_load_tls_config() {
+        let cert = "tests/fixtures/tls/cert.pem";
+        let key = "tests/fixtures/tls/key.pem";
+        assert!(load_tls_config(cert, key).is_ok());
+        // 证书和私钥文件互换时解析失败
+        assert!(load_tls_config(key, cert).is_err());
+        assert!(load_tls_config("tests/fixtures/tls/missing.pem", key).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_api_key_required() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .wrap_fn(|req, srv| {
+                    let auth = authorize(&req, "secret");
+                    let fut = srv.call(req);
+                    async move {
+                        auth?;
+                        fut.await
+                    }
+                })
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_rejectable(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let body = response_json(resp).await;
+        assert_eq!(body["error"], "unauthorized");
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header(("X-API-Key", "wrong"))
+            .set_json(&data)
+            .to_request();
+        assert_eq!(call_rejectable(&srv, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .set_json(&data)
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header(("X-API-Key", "secret"))
+            .set_json(&data)
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+
+        let req = TestRequest::get().uri("/health").to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_concurrency_limit_rejects_overflow() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(1));
+        let wrapped = limiter.clone();
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .wrap_fn(move |req, srv| {
+                    let slot = acquire_slot(&req, &wrapped);
+                    let fut = srv.call(req);
+                    async move {
+                        let _slot = slot?;
+                        fut.await
+                    }
+                })
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+
+        // 占满唯一的名额，模拟一个正在进行的大批量预测
+        let busy = limiter.try_acquire().unwrap();
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_rejectable(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "1");
+        let body = response_json(resp).await;
+        assert_eq!(body["error"], "overloaded");
+        let req = TestRequest::get().uri("/health").to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+
+        drop(busy);
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+        // 请求结束后名额归还
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[actix_rt::test]
+    async fn test_memory_limit_sheds_predictions() {
+        let guard = Arc::new(MemoryGuard::new(1));
+        // 用可调的读数代替真实的RSS
+        let used = Arc::new(AtomicU64::new(0));
+        let sampled = used.clone();
+        let sample = move || Some(sampled.load(Ordering::Relaxed));
+        actix_web::rt::spawn(watch_memory(guard.clone(), Duration::from_millis(5), sample));
+        // 与run_server相同的准入中间件，被拒绝的请求不会到达handler
+        let admission = Admission {
+            api_key: None,
+            rate_limiter: None,
+            memory_guard: Some(guard.clone()),
+            connection_limiter: None,
+            concurrency_limiter: None,
+            metrics: Arc::new(Metrics::new()),
+        };
+        let metrics = web::Data::new(Metrics::new());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(metrics.clone())
+                .wrap_fn(move |req, srv| admission.call(req, srv))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+
+        used.store(2 * 1024 * 1024, Ordering::Relaxed);
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+        assert!(guard.is_shedding());
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_rejectable(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = response_json(resp).await;
+        assert_eq!(body["error"], "overloaded");
+        // 被拒绝的预测没有进入handler
+        assert_eq!(metrics.requests.with_label_values(&["predict"]).get(), 0);
+        let req = TestRequest::get().uri("/health").to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+
+        used.store(512 * 1024, Ordering::Relaxed);
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_rate_limit_rejects_excess_requests() {
+        let limiter = Arc::new(RateLimiter::new(2.0));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .wrap_fn(move |req, srv| {
+                    let limited = rate_limit(&req, &limiter);
+                    let fut = srv.call(req);
+                    async move {
+                        limited?;
+                        fut.await
+                    }
+                })
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let peer = "10.0.0.1:4000".parse().unwrap();
+
+        let mut statuses = Vec::new();
+        for _ in 0..4 {
+            let req = TestRequest::post()
+                .uri("/predict")
+                .peer_addr(peer)
+                .set_json(&data)
+                .to_request();
+            let resp = call_rejectable(&srv, req).await;
+            if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                assert!(resp.headers().contains_key(header::RETRY_AFTER));
+            }
+            statuses.push(resp.status());
+        }
+        assert_eq!(statuses[..2], [StatusCode::OK, StatusCode::OK]);
+        assert!(statuses[2..].iter().all(|status| *status == StatusCode::TOO_MANY_REQUESTS));
+
+        // 健康检查不计入限流
+        let req = TestRequest::get().uri("/health").peer_addr(peer).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_max_total_predictions_truncates() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_total_predictions: Some(3),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["banana bread"; 3];
+        let req = TestRequest::post()
+            .uri("/predict?k=2&threshold=0&format=objects")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(PREDICTIONS_TRUNCATED).unwrap(), "2");
+        let body: serde_json::Value = read_body_json(resp).await;
+        let counts: Vec<usize> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["predictions"].as_array().unwrap().len())
+            .collect();
+        assert_eq!(counts, vec![2, 1, 0]);
+        assert!(body[0].get("truncated").is_none());
+        assert_eq!(body[1]["truncated"], true);
+        assert_eq!(body[2]["truncated"], true);
+
+        // 未超出上限时不带截断标记
+        let req = TestRequest::post().uri("/predict?k=1").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert!(resp.headers().get(PREDICTIONS_TRUNCATED).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_oversized_batch_rejected() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_batch_size: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["banana bread"; 3];
+        for uri in &["/predict", "/sentence-vector"] {
+            let req = TestRequest::post().uri(uri).set_json(&data).to_request();
+            let resp = call_service(&srv, req).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            let body: serde_json::Value = read_body_json(resp).await;
+            assert_eq!(body["error"], "batch_too_large");
+        }
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data[..2])
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_openapi_spec() {
+        let srv =
+            init_service(App::new().service(web::resource("/openapi.json").route(web::get().to(openapi_spec)))).await;
+        let req = TestRequest::get().uri("/openapi.json").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let spec: serde_json::Value = read_body_json(resp).await;
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        let predict = &spec["paths"]["/predict"]["post"];
+        let body = &predict["requestBody"]["content"]["application/json"]["schema"];
+        assert_eq!(body["type"], "array");
+        assert_eq!(body["items"]["$ref"], "#/components/schemas/PredictInput");
+        assert!(spec["components"]["schemas"]["PredictInput"].is_object());
+        let params: Vec<&str> = predict["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect();
+        assert!(params.contains(&"k") && params.contains(&"threshold"));
+        assert!(spec["paths"]["/sentence-vector"]["post"].is_object());
+        assert!(spec["paths"]["/health"]["get"].is_object());
+    }
+
+    #[actix_rt::test]
+    async fn test_quantize_endpoint() {
+        let source = "models/cooking.model.bin";
+        let output = std::env::temp_dir().join(format!("fasttext-serving-quantize-{}.ftz", std::process::id()));
+        let output = output.to_str().unwrap().to_string();
+        let config = ServerConfig {
+            model_path: source.to_string(),
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .service(web::resource("/quantize").route(web::post().to(quantize))),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/quantize")
+            .set_json(serde_json::json!({ "output": output }))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let size = std::fs::metadata(&output).unwrap().len();
+        assert_eq!(body["size"], size);
+        assert!(size < std::fs::metadata(source).unwrap().len());
+        let mut quantized = FastText::new();
+        quantized.load_model(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert!(quantized.is_quant());
+
+        // 没有API key时拒绝写文件
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .service(web::resource("/quantize").route(web::post().to(quantize))),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/quantize")
+            .set_json(serde_json::json!({ "output": output }))
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn test_quantized_model_rejects_vectors() {
+        // 用测试模型现场量化出一个.ftz
+        let mut model = load_model();
+        model.quantize(&fasttext::Args::new()).expect("Failed to quantize model");
+        let path = std::env::temp_dir().join(format!("fasttext-serving-{}.ftz", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        model.save_model(&path).expect("Failed to save quantized model");
+        let mut quantized = FastText::new();
+        quantized.load_model(&path).expect("Failed to load quantized model");
+        std::fs::remove_file(&path).unwrap();
+        assert!(quantized.is_quant());
+
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(quantized))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector)))
+                .service(web::resource("/word-vector").route(web::post().to(word_vector)))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        for uri in ["/sentence-vector", "/word-vector"].iter() {
+            let req = TestRequest::post().uri(uri).set_json(&data).to_request();
+            let resp = call_service(&srv, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let body: serde_json::Value = read_body_json(resp).await;
+            assert_eq!(body["error"], "quantized_model");
+            assert!(body["message"].as_str().unwrap().contains("quantized"));
+        }
+        // 预测不受影响
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_msgpack_round_trip() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?", "Why not put knives in the dishwasher?"];
+        let msgpack_req = |uri: &str| {
+            TestRequest::post()
+                .uri(uri)
+                .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+                .insert_header((header::ACCEPT, "application/msgpack"))
+                .set_payload(rmp_serde::to_vec(&data).unwrap())
+                .to_request()
+        };
+
+        let resp = call_service(&srv, msgpack_req("/predict")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/msgpack");
+        let predictions: Vec<(Vec<String>, Vec<f32>)> = rmp_serde::from_slice(&read_body(resp).await).unwrap();
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let expected: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(predictions, expected);
+
+        let resp = call_service(&srv, msgpack_req("/sentence-vector")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let vectors: Vec<Vec<f32>> = rmp_serde::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(vectors.len(), data.len());
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+            .set_payload("not msgpack")
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_vocab_pagination() {
+        let model = load_model();
+        let (words, counts) = model.get_vocab().unwrap();
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .service(web::resource("/vocab").route(web::get().to(vocab))),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/vocab?offset=5&limit=3").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["total"], words.len());
+        assert_eq!(body["offset"], 5);
+        let page: Vec<VocabWord> = serde_json::from_value(body["words"].clone()).unwrap();
+        let expected: Vec<VocabWord> = words[5..8]
+            .iter()
+            .zip(&counts[5..8])
+            .map(|(word, count)| VocabWord { word: word.clone(), count: *count })
+            .collect();
+        assert_eq!(page, expected);
+
+        // 默认只返回一页，越界返回空列表
+        let req = TestRequest::get().uri("/vocab").to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body["words"].as_array().unwrap().len(), words.len().min(1_000));
+        let req = TestRequest::get().uri(&format!("/vocab?offset={}", words.len())).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        assert!(body["words"].as_array().unwrap().is_empty());
+
+        let req = TestRequest::get().uri("/vocab?limit=1000000").to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_model_info() {
+        let model = load_model();
+        let dim = model.get_dimension();
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .service(web::resource("/model-info").route(web::get().to(model_info))),
+        )
+        .await;
+        let req = TestRequest::get().uri("/model-info").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["dimension"], dim);
+        let labels = body["labels"].as_array().unwrap();
+        assert!(!labels.is_empty());
+        assert_eq!(body["label_count"], labels.len());
+        assert_eq!(body["supervised"], true);
+        assert!(labels.iter().all(|label| !label.as_str().unwrap().starts_with("__label__")));
+        let args = &body["training_args"];
+        assert_eq!(args["dimension"], dim);
+        assert_eq!(args["model"], "supervised");
+        assert!(["hs", "ns", "softmax", "ova"].contains(&args["loss"].as_str().unwrap()));
+        assert!(args["epoch"].as_i64().unwrap() > 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_labels() {
+        let model = load_model();
+        let label_count = model.get_labels().unwrap().0.len();
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .service(web::resource("/labels").route(web::get().to(labels))),
+        )
+        .await;
+        let req = TestRequest::get().uri("/labels").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Vec<LabelCount> = read_body_json(resp).await;
+        assert_eq!(body.len(), label_count);
+        assert!(body.windows(2).all(|pair| pair[0].count >= pair[1].count));
+        assert!(body.iter().all(|label| !label.label.starts_with("__label__")));
+        let total: f64 = body.iter().map(|label| label.frequency).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+
+    #[actix_rt::test]
+    async fn test_json_body_limit() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(json_config(1))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["banana ".repeat(200_000)];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["error"], "json_parse_error");
+    }
+
+    #[actix_rt::test]
+    async fn test_server_stops_on_shutdown_signal() {
+        let server = HttpServer::new(|| {
+            App::new().service(web::resource("/health").route(web::get().to(health_check)))
+        })
+        .workers(1)
+        .disable_signals()
+        .shutdown_timeout(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap()
+        .run();
+        let stopped = actix_web::rt::time::timeout(
+            std::time::Duration::from_secs(5),
+            serve_until(server, async {}),
+        )
+        .await;
+        assert!(matches!(stopped, Ok(Ok(()))));
+    }
+
+    #[actix_rt::test]
+    async fn test_in_flight_gauge() {
+        let metrics = web::Data::new(Metrics::new());
+        let tracked = metrics.clone().into_inner();
+        let srv = init_service(
+            App::new()
+                .wrap_fn(move |req, srv| {
+                    let in_flight = tracked.enter();
+                    let fut = srv.call(req);
+                    async move {
+                        let res = fut.await;
+                        drop(in_flight);
+                        res
+                    }
+                })
+                .route(
+                    "/slow",
+                    web::get().to(|| async {
+                        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+        assert_eq!(metrics.in_flight(), 0);
+        let request = call_service(&srv, TestRequest::get().uri("/slow").to_request());
+        let probe = async {
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+            (metrics.in_flight(), metrics.render())
+        };
+        let (resp, (during, rendered)) = future::join(request, probe).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(during, 1);
+        assert!(rendered.contains("fasttext_in_flight_requests 1"), "{}", rendered);
+        assert_eq!(metrics.in_flight(), 0);
+        assert!(metrics.render().contains("fasttext_in_flight_requests 0"));
+    }
+
+    #[test]
+    fn test_server_with_small_blocking_pool() {
+        let sys = System::with_tokio_rt(|| runtime_builder(Some(4 * 1024 * 1024), Some(1)).build().unwrap());
+        let (model, config) = (model_data(load_model()), config_data(ServerConfig::default()));
+        let metrics = web::Data::new(Metrics::new());
+        let response = sys.block_on(async move {
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(model.clone())
+                    .app_data(config.clone())
+                    .app_data(metrics.clone())
+                    .app_data(json_config(500))
+                    .service(web::resource("/predict").route(web::post().to(predict)))
+            })
+            .workers(1)
+            .worker_max_blocking_threads(1)
+            .disable_signals()
+            .shutdown_timeout(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+            let addr = server.addrs()[0];
+            let server = server.run();
+            let handle = server.handle();
+            actix_web::rt::spawn(server);
+            // 批量预测在blocking线程池中执行，请求由独立线程同步发送
+            let response = web::block(move || {
+                use std::io::{Read, Write};
+                let body = r#"["Which baking dish is best to bake a banana bread?", "Why are knives dull?"]"#;
+                let mut stream = std::net::TcpStream::connect(addr).unwrap();
+                write!(
+                    stream,
+                    "POST /predict HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            })
+            .await
+            .unwrap();
+            handle.stop(true).await;
+            response
+        });
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    #[test]
+    fn test_parse_addresses() {
+        let addrs = parse_addresses("127.0.0.1, [::1],unix:/tmp/fasttext.sock", 8000).unwrap();
+        let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+        assert_eq!(addrs, vec!["127.0.0.1:8000", "[::1]:8000", "unix:/tmp/fasttext.sock"]);
+        assert!(parse_addresses("127.0.0.1,", 8000).is_err());
+        assert!(parse_addresses("not an address", 8000).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_bind_ipv4_and_ipv6() {
+        let v4 = bind_tcp("127.0.0.1", 0, true, 1024).unwrap().remove(0);
+        let port = v4.local_addr().unwrap().port();
+        // IPv6 socket只监听IPv6，可以和IPv4共用端口
+        let v6 = bind_tcp("::1", port, true, 1024).unwrap().remove(0);
+        let server = HttpServer::new(|| {
+            App::new()
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/livez").route(web::get().to(liveness)))
+        })
+            .workers(1)
+            .disable_signals()
+            .shutdown_timeout(1)
+            .listen(v4)
+            .unwrap()
+            .listen(v6)
+            .unwrap();
+        let addrs = server.addrs();
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let responses = web::block(move || {
+            use std::io::{Read, Write};
+            addrs
+                .into_iter()
+                .map(|addr| {
+                    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+                    write!(stream, "GET /livez HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+                    let mut response = String::new();
+                    stream.read_to_string(&mut response).unwrap();
+                    response
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap();
+        handle.stop(true).await;
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|response| response.starts_with("HTTP/1.1 200")), "{:?}", responses);
+    }
+
+    #[actix_rt::test]
+    async fn test_tcp_nodelay() {
+        let listener = bind_tcp("127.0.0.1", 0, true, 16).unwrap().remove(0);
+        assert!(socket2::SockRef::from(&listener).nodelay().unwrap());
+        // 处理函数返回连接上实际的TCP_NODELAY
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/nodelay",
+                web::get().to(|req: actix_web::HttpRequest| async move {
+                    HttpResponse::Ok().body(format!("{:?}", req.conn_data::<bool>()))
+                }),
+            )
+        })
+        .workers(1)
+        .disable_signals()
+        .shutdown_timeout(1)
+        .on_connect(|conn, ext| {
+            set_nodelay(conn, true);
+            if let Some(stream) = conn.downcast_ref::<actix_web::rt::net::TcpStream>() {
+                ext.insert(stream.nodelay().unwrap());
+            }
+        })
+        .listen(listener)
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let response = web::block(move || {
+            use std::io::{Read, Write};
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            write!(stream, "GET /nodelay HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        handle.stop(true).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("Some(true)"), "{}", response);
+    }
+
+    #[actix_rt::test]
+    async fn test_slow_client_cut_off() {
+        let config = ServerConfig {
+            limits: Limits {
+                client_request_timeout_ms: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let server = HttpServer::new(|| {
+            App::new()
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/livez").route(web::get().to(liveness)))
+        })
+            .workers(1)
+            .disable_signals()
+            .shutdown_timeout(1)
+            .keep_alive(keep_alive(config.limits.keep_alive_secs))
+            .client_request_timeout(Duration::from_millis(config.limits.client_request_timeout_ms))
+            .client_disconnect_timeout(Duration::from_millis(config.limits.client_disconnect_timeout_ms))
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        let (response, elapsed) = web::block(move || {
+            use std::io::{Read, Write};
+            let started = Instant::now();
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            // 只发一半请求头，之后不再发送
+            stream.write_all(b"GET /livez HTTP/1.1\r\nHost: local").unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            (response, started.elapsed())
+        })
+        .await
+        .unwrap();
+        handle.stop(true).await;
+        assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+        assert!(elapsed < Duration::from_secs(2));
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_timeout() {
+        let config = ServerConfig {
+            limits: Limits {
+                predict_timeout_ms: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(json_config(500))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["banana bread ".repeat(300_000)];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["error"], "predict_timeout");
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_strict_reports_failures() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?".to_string(),
+            "banana bread ".repeat(100),
+            "Why not put knives in the dishwasher?".to_string(),
+            String::new(),
+        ];
+        let req = TestRequest::post()
+            .uri("/predict?strict=true")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["error"], "prediction_failed");
+        let failures = body["failures"].as_array().unwrap();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0]["index"], 1);
+        assert_eq!(failures[0]["error"], "input_error");
+        assert!(failures[0]["message"].as_str().unwrap().contains("too long"));
+        assert_eq!(failures[1]["index"], 3);
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), data.len());
+        assert!(results[0].is_array());
+        assert!(results[1].is_null());
+        assert!(results[2].is_array());
+        assert!(results[3].is_null());
+
+        // 不开启strict时保持200加错误标记
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_echoes_ids() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = serde_json::json!([
+            { "id": "abc", "text": "Which baking dish is best to bake a banana bread?" },
+            { "id": "too-long", "text": "banana bread ".repeat(100) },
+            { "text": "Why not put knives in the dishwasher?" },
+        ]);
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let results = body.as_array().unwrap();
+        assert_eq!(results[0]["id"], "abc");
+        assert!(!results[0]["predictions"].as_array().unwrap().is_empty());
+        // 失败的条目同样带回id
+        assert_eq!(results[1]["id"], "too-long");
+        assert_eq!(results[1]["predictions"][0]["label"], "error");
+        assert!(results[2].get("id").is_none());
+
+        let req = TestRequest::post().uri("/predict?on_error=skip").set_json(&data).to_request();
+        let body: serde_json::Value = read_body_json(call_service(&srv, req).await).await;
+        let ids: Vec<_> = body.as_array().unwrap().iter().map(|result| result["id"].clone()).collect();
+        assert_eq!(ids, vec![serde_json::json!("abc"), serde_json::Value::Null]);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_item_status() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let text = "Which baking dish is best to bake a banana bread?";
+        let data = serde_json::json!([
+            { "text": text },
+            { "text": text, "threshold": 1.0 },
+            { "text": "banana bread ".repeat(100) },
+        ]);
+        let req = TestRequest::post().uri("/predict?format=objects").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let statuses: Vec<ItemStatus> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| serde_json::from_value(item["status"].clone()).unwrap())
+            .collect();
+        assert_eq!(statuses, vec![ItemStatus::Ok, ItemStatus::Empty, ItemStatus::Error]);
+        // 空结果没有标签，失败的条目带错误标记
+        assert!(body[1]["predictions"].as_array().unwrap().is_empty());
+        assert_eq!(body[2]["predictions"][0]["label"], "error");
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_on_error() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 100,
+                ..Default::default()
+            },
+            on_error: crate::OnError::Fail,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?".to_string(),
+            "banana bread ".repeat(100),
+            "Why not put knives in the dishwasher?".to_string(),
+        ];
+        // 服务端默认fail，整批返回400
+        let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["error"], "input_error");
+        assert!(body["message"].as_str().unwrap().starts_with("Text 1 failed"));
+
+        let req = TestRequest::post().uri("/predict?on_error=mark").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_eq!(body.len(), 3);
+        assert_eq!(body[1].0, vec!["error".to_string()]);
+
+        let req = TestRequest::post().uri("/predict?on_error=skip").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_eq!(body.len(), 2);
+        assert!(body.iter().all(|(labels, _)| labels != &vec!["error".to_string()]));
+
+        let req = TestRequest::post().uri("/predict?on_error=ignore").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_stream_on_error() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_text_length: 100,
+                ..Default::default()
+            },
+            on_error: crate::OnError::Fail,
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/batch-predict").route(web::post().to(batch_predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?".to_string(),
+            "banana bread ".repeat(100),
+            "Why not put knives in the dishwasher?".to_string(),
+        ];
+        // 服务端默认fail，流式响应在开始前就拒绝
+        let req = TestRequest::post().uri("/predict?stream=true").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["error"], "input_error");
+        let req = TestRequest::post().uri("/batch-predict").set_payload(data.join("\n")).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+
+        let lines = |body: &[u8]| -> Vec<(Vec<String>, Vec<f32>)> {
+            let body = std::str::from_utf8(body).unwrap();
+            body.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        };
+        let req = TestRequest::post().uri("/predict?stream=true&on_error=skip").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let skipped = lines(&read_body(resp).await);
+        assert_eq!(skipped.len(), 2);
+        assert!(skipped.iter().all(|(labels, _)| labels != &vec!["error".to_string()]));
+        let req = TestRequest::post()
+            .uri("/batch-predict?on_error=skip")
+            .set_payload(data.join("\n"))
+            .to_request();
+        assert_eq!(lines(&read_body(call_service(&srv, req).await).await), skipped);
+
+        let req = TestRequest::post().uri("/predict?stream=true&on_error=mark").set_json(&data).to_request();
+        let marked = lines(&read_body(call_service(&srv, req).await).await);
+        assert_eq!(marked.len(), 3);
+        assert_eq!(marked[1].0, vec!["error".to_string()]);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_label_filter() {
+        let model = load_model();
+        let all_labels: Vec<String> = model
+            .get_labels()
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|label| label.trim_start_matches("__label__").to_string())
+            .collect();
+        let allowed = vec!["baking".to_string(), "bread".to_string(), "equipment".to_string()];
+        let config = ServerConfig {
+            label_filter: allowed.clone(),
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?",
+            "Why not put knives in the dishwasher?",
+            "How long should I boil an egg for a soft yolk?",
+        ];
+        let req = TestRequest::post()
+            .uri(&format!("/predict?k={}", all_labels.len()))
+            .set_json(&data)
+            .to_request();
+        let resp: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        for (labels, _) in &resp {
+            assert!(labels.iter().all(|label| allowed.contains(label)), "unexpected labels {:?}", labels);
+        }
+        // 过滤后仍能拿到被允许的标签
+        assert!(resp.iter().any(|(labels, _)| !labels.is_empty()));
+
+        // 查询参数覆盖全局配置，并可重新归一化
+        let req = TestRequest::post()
+            .uri(&format!("/predict?k={}&labels=baking,equipment&renormalize=true", all_labels.len()))
+            .set_json(&data)
+            .to_request();
+        let resp: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        for (labels, scores) in &resp {
+            assert!(labels.iter().all(|label| label == "baking" || label == "equipment"));
+            if labels.len() == 2 {
+                assert!((scores.iter().sum::<f32>() - 1.0).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_per_label_thresholds() {
+        let model = ServedModel::new(load_model());
+        let text = "Which baking dish is best to bake a banana bread?";
+        let label_count = model.get_labels().unwrap().0.len() as u32;
+        let full = PredictParams {
+            label_count: Some(label_count),
+            max_text_length: usize::MAX,
+            label_prefix: Some("__label__".to_string()),
+            ..Default::default()
+        };
+        let (labels, scores, _) = predict_text(&model, text, label_count, 0.0, &full).unwrap();
+        assert!(labels.contains(&"baking".to_string()) && labels.contains(&"bread".to_string()));
+
+        // baking要求极高的分数，bread几乎不设门槛，其余标签用全局阈值
+        let thresholds: HashMap<String, f32> =
+            vec![("baking".to_string(), 0.999), ("bread".to_string(), 0.0001)].into_iter().collect();
+        let params = PredictParams {
+            label_thresholds: Some(Arc::new(thresholds.clone())),
+            ..full
+        };
+        let global = 0.5;
+        let (kept, _, _) = predict_text(&model, text, label_count, global, &params).unwrap();
+        let expected: Vec<String> = labels
+            .into_iter()
+            .zip(scores)
+            .filter(|(label, score)| *score >= thresholds.get(label).copied().unwrap_or(global))
+            .map(|(label, _)| label)
+            .collect();
+        assert_eq!(kept, expected);
+        assert!(kept.contains(&"bread".to_string()));
+        assert!(!kept.contains(&"baking".to_string()));
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_all_labels() {
+        let model = load_model();
+        let label_count = model.get_labels().unwrap().0.len();
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict-all").route(web::post().to(predict_all))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        // 查询参数里的k和阈值不影响结果
+        let req = TestRequest::post()
+            .uri("/predict-all?k=1&threshold=0.9")
+            .set_json(&data)
+            .to_request();
+        let resp: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        let (labels, scores) = &resp[0];
+        assert_eq!(labels.len(), label_count);
+        assert_eq!(scores.len(), label_count);
+        assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[actix_rt::test]
+    async fn test_precision() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let rounded = |value: f32, digits: i32| {
+            let scale = 10f64.powi(digits);
+            ((value as f64 * scale).round() / scale) as f32 == value
+        };
+
+        let req = TestRequest::post().uri("/predict?k=3").set_json(&data).to_request();
+        let full: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert!(!full[0].1.iter().all(|score| rounded(*score, 2)));
+        let req = TestRequest::post().uri("/predict?k=3&precision=2").set_json(&data).to_request();
+        let short: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(short[0].0, full[0].0);
+        assert!(short[0].1.iter().all(|score| rounded(*score, 2)));
+        assert!(short[0].1.iter().zip(&full[0].1).all(|(short, full)| (short - full).abs() <= 0.005));
+
+        let req = TestRequest::post().uri("/sentence-vector?precision=3").set_json(&data).to_request();
+        let vectors: Vec<Vec<f32>> = read_body_json(call_service(&srv, req).await).await;
+        assert!(vectors[0].iter().all(|value| rounded(*value, 3)));
+
+        let req = TestRequest::post().uri("/predict?precision=20").set_json(&data).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_k_capped() {
+        let config = ServerConfig {
+            limits: Limits {
+                max_k: 5,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict?k=1000&threshold=0")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.headers().get(K_CAPPED).unwrap(), "5");
+        let resp: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_eq!(resp[0].0.len(), 5);
+
+        // 未超过上限时不带这个头
+        let req = TestRequest::post().uri("/predict?k=2").set_json(&data).to_request();
+        let resp = call_service(&srv, req).await;
+        assert!(resp.headers().get(K_CAPPED).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_object_format() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec![
+            "Which baking dish is best to bake a banana bread?",
+            "Why not put knives in the dishwasher?",
+        ];
+
+        let req = TestRequest::post()
+            .uri("/predict?k=3")
+            .set_json(&data)
+            .to_request();
+        let arrays: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+
+        let req = TestRequest::post()
+            .uri("/predict?k=3&format=objects")
+            .set_json(&data)
+            .to_request();
+        let objects: Vec<serde_json::Value> = read_body_json(call_service(&srv, req).await).await;
+
+        assert_eq!(arrays.len(), objects.len());
+        for ((labels, scores), object) in arrays.into_iter().zip(objects) {
+            let predictions: Vec<LabelScore> =
+                serde_json::from_value(object["predictions"].clone()).unwrap();
+            let expected: Vec<LabelScore> = labels
+                .into_iter()
+                .zip(scores)
+                .map(|(label, score)| LabelScore { label, score })
+                .collect();
+            assert_eq!(predictions, expected);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_nearest_neighbors() {
+        // 仓库里只有监督模型，其词向量同样可以查近邻
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(web::Data::new(WordIndex::default()))
+                .service(web::resource("/nearest-neighbors").route(web::post().to(nearest_neighbors))),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/nearest-neighbors")
+            .set_json(serde_json::json!({"word": "bread", "k": 5}))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let neighbors: Vec<serde_json::Value> = read_body_json(resp).await;
+        assert_eq!(neighbors.len(), 5);
+        assert!(neighbors.iter().all(|n| n["word"] != "bread" && n["similarity"].is_number()));
+
+        let req = TestRequest::post()
+            .uri("/nearest-neighbors")
+            .set_json(serde_json::json!({"word": "qwxzvbnm"}))
+            .to_request();
+        let neighbors: Vec<serde_json::Value> = read_body_json(call_service(&srv, req).await).await;
+        assert!(neighbors.is_empty());
+
+        let req = TestRequest::post()
+            .uri("/nearest-neighbors")
+            .set_json(serde_json::json!({"word": "bread", "k": 0}))
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_word_vector() {
+        let model = load_model();
+        let dim = model.get_dimension() as usize;
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/word-vector").route(web::post().to(word_vector))),
+        )
+        .await;
+        // 第二个词不在词表中，由子词合成
+        let data = vec!["bread", "breadification"];
+        let req = TestRequest::post()
+            .uri("/word-vector")
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let vectors: Vec<Vec<f32>> = read_body_json(resp).await;
+        assert_eq!(vectors.len(), 2);
+        assert!(vectors.iter().all(|v| v.len() == dim));
+    }
+
+    #[actix_rt::test]
+    async fn test_normalized_vectors() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector)))
+                .service(web::resource("/word-vector").route(web::post().to(word_vector))),
+        )
+        .await;
+        let norm = |vector: &Vec<f32>| vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        for (uri, data) in [("/sentence-vector", vec!["banana bread", ""]), ("/word-vector", vec!["bread", "flour"])] {
+            let req = TestRequest::post().uri(&format!("{}?normalize=true", uri)).set_json(&data).to_request();
+            let resp = call_service(&srv, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let vectors: Vec<Vec<f32>> = read_body_json(resp).await;
+            for vector in &vectors {
+                // 空文本得到零向量，归一化后仍是零
+                if vector.iter().all(|value| *value == 0.0) {
+                    continue;
+                }
+                assert!((norm(vector) - 1.0).abs() < 1e-4, "{} {}", uri, norm(vector));
+            }
+        }
+        let req = TestRequest::post().uri("/sentence-vector?normalize=true").set_json(vec![""]).to_request();
+        let vectors: Vec<Vec<f32>> = read_body_json(call_service(&srv, req).await).await;
+        assert!(vectors[0].iter().all(|value| *value == 0.0));
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_options_from_headers() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let request = |uri: &str, headers: &[(&'static str, &'static str)]| {
+            let mut req = TestRequest::post().uri(uri).set_json(&data);
+            for header in headers {
+                req = req.insert_header(*header);
+            }
+            req.to_request()
+        };
+        let req = request("/predict", &[("X-Predict-K", "3"), ("X-Predict-Threshold", "0")]);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body[0].0.len(), 3);
+
+        // 查询参数优先于请求头
+        let req = request("/predict?k=1", &[("X-Predict-K", "3")]);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(body[0].0.len(), 1);
+
+        // 阈值为1时没有标签能通过
+        let req = request("/predict", &[("X-Predict-K", "3"), ("X-Predict-Threshold", "1.0")]);
+        let body: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert!(body[0].0.is_empty());
+
+        for header in [("X-Predict-K", "many"), ("X-Predict-K", "0"), ("X-Predict-Threshold", "2")] {
+            let resp = call_service(&srv, request("/predict", &[header])).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", header);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_diff() {
+        let model = train_model(concat!(
+            "__label__fruit banana apple mango\n",
+            "__label__fruit apple banana pear\n",
+            "__label__tools knife hammer saw\n",
+            "__label__tools hammer saw drill\n",
+        ));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .service(web::resource("/diff").route(web::post().to(diff))),
+        )
+        .await;
+        let body = serde_json::json!({"a": "banana apple", "b": "hammer saw", "k": 1});
+        let req = TestRequest::post().uri("/diff").set_json(&body).to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let diff: DiffResponse = read_body_json(resp).await;
+        assert_eq!(diff.only_a.iter().map(|l| l.label.as_str()).collect::<Vec<_>>(), vec!["fruit"]);
+        assert_eq!(diff.only_b.iter().map(|l| l.label.as_str()).collect::<Vec<_>>(), vec!["tools"]);
+        assert!(diff.shared.is_empty());
+
+        // 两个标签都返回时全部共有，delta为b减a
+        let body = serde_json::json!({"a": "banana apple", "b": "hammer saw", "k": 2});
+        let req = TestRequest::post().uri("/diff").set_json(&body).to_request();
+        let diff: DiffResponse = read_body_json(call_service(&srv, req).await).await;
+        assert!(diff.only_a.is_empty() && diff.only_b.is_empty());
+        assert_eq!(diff.shared.len(), 2);
+        let fruit = diff.shared.iter().find(|shared| shared.label == "fruit").unwrap();
+        assert!(fruit.delta < 0.0);
+        assert!((fruit.delta - (fruit.score_b - fruit.score_a)).abs() < 1e-6);
+
+        let body = serde_json::json!({"a": "banana", "b": "", "k": 1});
+        let req = TestRequest::post().uri("/diff").set_json(&body).to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_diff_predictions() {
+        let a = (vec!["x".to_string(), "y".to_string()], vec![0.6, 0.3]);
+        let b = (vec!["z".to_string(), "x".to_string()], vec![0.5, 0.4]);
+        let diff = diff_predictions(a, b);
+        assert_eq!(diff.only_a, vec![LabelScore { label: "y".to_string(), score: 0.3 }]);
+        assert_eq!(diff.only_b, vec![LabelScore { label: "z".to_string(), score: 0.5 }]);
+        assert_eq!(diff.shared.len(), 1);
+        assert_eq!(diff.shared[0].label, "x");
+        assert!((diff.shared[0].delta + 0.2).abs() < 1e-6);
+    }
+
+    #[actix_rt::test]
+    async fn test_analogy() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(web::Data::new(WordIndex::default()))
+                .service(web::resource("/analogy").route(web::post().to(analogy))),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/analogy")
+            .set_json(serde_json::json!({"a": "bread", "b": "flour", "c": "rice", "k": 3}))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let results: Vec<serde_json::Value> = read_body_json(resp).await;
+        assert!(!results.is_empty());
+        assert!(results[0]["word"].is_string());
+
+        let req = TestRequest::post()
+            .uri("/analogy")
+            .set_json(serde_json::json!({"a": "bread", "b": "qwxzvbnm", "c": "rice"}))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert!(body["message"].as_str().unwrap().contains("qwxzvbnm"));
+    }
+
+    #[actix_rt::test]
+    async fn test_health_check_probes_model() {
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(web::Data::new(Readiness::new(true)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let req = TestRequest::get().uri("/health").to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["model_loaded"], true);
+    }
+
+    #[actix_rt::test]
+    async fn test_ready_after_prediction() {
+        // 无监督模型能正常加载，但不能用于分类预测
+        let dir = std::env::temp_dir().join(format!("fasttext-unsupervised-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("train.txt");
+        std::fs::write(&input, "banana bread dish\nknife steel edge\nbanana knife bread\n").unwrap();
+        let mut args = fasttext::Args::new();
+        args.set_input(input.to_str().unwrap()).unwrap();
+        args.set_model(fasttext::ModelName::SG);
+        args.set_min_count(1);
+        args.set_dim(8);
+        args.set_thread(1);
+        args.set_verbose(0);
+        let mut unsupervised = FastText::new();
+        unsupervised.train(&args).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(crate::warm_up(&unsupervised).is_err());
+
+        for (model, answers) in [(unsupervised, false), (load_model(), true)] {
+            let first = Arc::new(FirstPrediction::default());
+            let config = ServerConfig {
+                ready_after_prediction: true,
+                first_prediction: Some(first.clone()),
+                ..Default::default()
+            };
+            let readiness = web::Data::new(Readiness::new(true).after_prediction(first.clone()));
+            let srv = init_service(
+                App::new()
+                    .app_data(model_data(model))
+                    .app_data(config_data(config))
+                    .app_data(web::Data::new(Metrics::new()))
+                    .app_data(readiness.clone())
+                    .service(web::resource("/predict").route(web::post().to(predict)))
+                    .service(web::resource("/readyz").route(web::get().to(health_check))),
+            )
+            .await;
+            let resp = call_service(&srv, TestRequest::get().uri("/readyz").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let body: serde_json::Value = read_body_json(resp).await;
+            assert_eq!(body["status"], "awaiting_prediction");
+
+            for _ in 0..3 {
+                let data = vec!["Which baking dish is best to bake a banana bread?"];
+                let req = TestRequest::post().uri("/predict").set_json(&data).to_request();
+                let _ = call_service(&srv, req).await;
+            }
+            let resp = call_service(&srv, TestRequest::get().uri("/readyz").to_request()).await;
+            if answers {
+                assert_eq!(resp.status(), StatusCode::OK);
+            } else {
+                // 预测一直失败，就绪状态始终不会翻转
+                assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            }
+            assert_eq!(readiness.is_ready(), answers);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_not_ready_until_reload_warmed_up() {
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            ..Default::default()
+        };
+        assert!(config.warmup);
+        let readiness = web::Data::new(Readiness::new(true));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(readiness.clone())
+                .service(web::resource("/readyz").route(web::get().to(health_check)))
+                .service(web::resource("/reload").route(web::post().to(reload))),
+        )
+        .await;
+        // 单线程运行时：reload先被轮询并让出，/readyz在加载和预热期间执行
+        let reloading = srv.call(TestRequest::post().uri("/reload").to_request());
+        let probing = srv.call(TestRequest::get().uri("/readyz").to_request());
+        let (reloaded, probed) = future::join(reloading, probing).await;
+        assert_eq!(reloaded.unwrap().status(), StatusCode::OK);
+        assert_eq!(probed.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert!(readiness.is_ready());
+        let resp = srv.call(TestRequest::get().uri("/readyz").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_concurrent_reloads_rejected() {
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            ..Default::default()
+        };
+        let readiness = web::Data::new(Readiness::new(true));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(readiness.clone())
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/readyz").route(web::get().to(health_check)))
+                .service(web::resource("/reload").route(web::post().to(reload)))
+                .service(web::resource("/drain-reload").route(web::post().to(drain_reload))),
+        )
+        .await;
+        // 单线程运行时：第一个reload在加载时让出，其余请求在它完成前执行
+        let first = srv.call(TestRequest::post().uri("/reload").to_request());
+        let second = srv.call(TestRequest::post().uri("/reload").to_request());
+        let drain = srv.call(TestRequest::post().uri("/drain-reload").to_request());
+        let probing = srv.call(TestRequest::get().uri("/readyz").to_request());
+        let (first, second, drain, probed) = future::join4(first, second, drain, probing).await;
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        let second = second.unwrap();
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = read_body_json(second).await;
+        assert_eq!(body["error"], "reload_in_progress");
+        assert_eq!(drain.unwrap().status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = read_body_json(probed.unwrap()).await;
+        assert_eq!(body["reloading"], true);
+
+        // 完成后可以再次reload
+        assert!(!readiness.is_reloading());
+        let resp = srv.call(TestRequest::post().uri("/reload").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_drain_reload() {
+        let config = ServerConfig {
+            model_path: "models/cooking.model.bin".to_string(),
+            ..Default::default()
+        };
+        let readiness = web::Data::new(Readiness::new(true));
+        let metrics = web::Data::new(Metrics::new());
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(config))
+                .app_data(readiness.clone())
+                .app_data(metrics.clone())
+                .service(web::resource("/readyz").route(web::get().to(health_check)))
+                .service(web::resource("/drain-reload").route(web::post().to(drain_reload))),
+        )
+        .await;
+        // 模拟一个正在处理的请求，/readyz探测完成后才结束
+        let in_flight = metrics.clone().into_inner().enter();
+        let reloading = srv.call(TestRequest::post().uri("/drain-reload").to_request());
+        let probing = async {
+            let probed = srv.call(TestRequest::get().uri("/readyz").to_request()).await;
+            assert!(!readiness.is_ready());
+            drop(in_flight);
+            probed
+        };
+        let (reloaded, probed) = future::join(reloading, probing).await;
+        assert_eq!(probed.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+        let reloaded = reloaded.unwrap();
+        assert_eq!(reloaded.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(reloaded).await;
+        let timeline: Vec<ReloadStep> = serde_json::from_value(body["timeline"].clone()).unwrap();
+        let steps: Vec<&str> = timeline.iter().map(|step| step.step.as_str()).collect();
+        assert_eq!(steps, vec!["not_ready", "drained", "loaded_and_warmed_up", "swapped", "ready"]);
+        let ready: Vec<bool> = timeline.iter().map(|step| step.ready).collect();
+        assert_eq!(ready, vec![false, false, false, false, true]);
+
+        assert!(readiness.is_ready());
+        let resp = srv.call(TestRequest::get().uri("/readyz").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // 新模型加载失败时保留旧模型并恢复就绪
+        let req = TestRequest::post()
+            .uri("/drain-reload")
+            .set_json(serde_json::json!({ "path": "models/missing.bin" }))
+            .to_request();
+        let resp = srv.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(readiness.is_ready());
+    }
+
+    #[actix_rt::test]
+    async fn test_liveness_and_readiness() {
+        let readiness = web::Data::new(Readiness::new(false));
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(readiness.clone())
+                .service(web::resource("/livez").route(web::get().to(liveness)))
+                .service(web::resource("/readyz").route(web::get().to(health_check)))
+                .service(web::resource("/health").route(web::get().to(health_check))),
+        )
+        .await;
+        let status = |uri: &str| TestRequest::get().uri(uri).to_request();
+
+        assert_eq!(call_service(&srv, status("/livez")).await.status(), StatusCode::OK);
+        assert_eq!(call_service(&srv, status("/readyz")).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(call_service(&srv, status("/health")).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        readiness.set(true);
+        assert_eq!(call_service(&srv, status("/livez")).await.status(), StatusCode::OK);
+        assert_eq!(call_service(&srv, status("/readyz")).await.status(), StatusCode::OK);
+        assert_eq!(call_service(&srv, status("/health")).await.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_gzip_response() {
+        let srv = init_service(
+            App::new()
+                .wrap(actix_web::middleware::Compress::default())
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"; 100];
+        let req = TestRequest::post()
+            .uri("/predict?format=objects&k=5")
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[actix_rt::test]
+    async fn test_gzip_request_body() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let model = load_model();
+        let dim = model.get_dimension() as usize;
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(model))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .app_data(json_config(1))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+                .service(web::resource("/sentence-vector").route(web::post().to(sentence_vector))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"; 2];
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(&data).unwrap()).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(body.clone())
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let predictions: Vec<(Vec<String>, Vec<f32>)> = read_body_json(resp).await;
+        assert_eq!(predictions.len(), 2);
+        assert_ne!(predictions[0].0, vec!["error"]);
+
+        let req = TestRequest::post()
+            .uri("/sentence-vector")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(body)
+            .to_request();
+        let vectors: Vec<Vec<f32>> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(vectors.len(), 2);
+        assert!(vectors.iter().all(|v| v.len() == dim));
+
+        // 限制按解压后的大小计算
+        let data = vec!["banana ".repeat(200_000)];
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(&data).unwrap()).unwrap();
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(encoder.finish().unwrap())
+            .to_request();
+        assert_eq!(call_service(&srv, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_cors_headers() {
+        let srv = init_service(
+            App::new()
+                .wrap(cors(&["https://dashboard.internal".to_string()], &[], None))
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let data = vec!["Which baking dish is best to bake a banana bread?"];
+        let req = TestRequest::post()
+            .uri("/predict")
+            .insert_header((header::ORIGIN, "https://dashboard.internal"))
+            .set_json(&data)
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://dashboard.internal"
+        );
+
+        let req = TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri("/predict")
+            .insert_header((header::ORIGIN, "https://dashboard.internal"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+            .to_request();
+        let resp = call_service(&srv, req).await;
+        assert!(resp.status().is_success());
+        assert!(resp.headers().contains_key(header::ACCESS_CONTROL_ALLOW_METHODS));
+    }
+
+    #[actix_rt::test]
+    async fn test_cors_custom_headers() {
+        let origins = ["https://dashboard.internal".to_string()];
+        let preflight = || {
+            TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/predict")
+                .insert_header((header::ORIGIN, "https://dashboard.internal"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type, x-request-id"))
+                .to_request()
+        };
+        let app = |cors| {
+            App::new()
+                .wrap(cors)
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict)))
+        };
+
+        // 没有配置的自定义头预检不通过
+        let srv = init_service(app(cors(&origins, &[], None))).await;
+        let resp = call_service(&srv, preflight()).await;
+        assert!(!resp.status().is_success());
+
+        let srv = init_service(app(cors(&origins, &["x-request-id".to_string()], Some(600)))).await;
+        let resp = call_service(&srv, preflight()).await;
+        assert!(resp.status().is_success());
+        let allowed = resp.headers().get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap().to_str().unwrap();
+        assert!(allowed.split(',').any(|name| name.trim() == "x-request-id"));
+        assert!(allowed.split(',').any(|name| name.trim() == "x-api-key"));
+        assert_eq!(resp.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+    }
+
+    #[actix_rt::test]
+    async fn test_predict_per_item_options() {
+        let input: PredictInput = serde_json::from_str(r#"{"text": "banana bread", "k": 3}"#).unwrap();
+        assert_eq!(input.overrides(), (Some(3), None));
+
+        let srv = init_service(
+            App::new()
+                .app_data(model_data(load_model()))
+                .app_data(config_data(ServerConfig::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .service(web::resource("/predict").route(web::post().to(predict))),
+        )
+        .await;
+        let text = "Which baking dish is best to bake a banana bread?";
+
+        // 同构的字符串数组沿用查询参数
+        let req = TestRequest::post()
+            .uri("/predict?k=2")
+            .set_json(vec![text, text])
+            .to_request();
+        let homogeneous: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert!(homogeneous.iter().all(|(labels, _)| labels.len() == 2));
+
+        let data = serde_json::json!([
+            {"text": text, "k": 4},
+            text,
+            {"text": text, "threshold": 1.0},
+        ]);
+        let req = TestRequest::post()
+            .uri("/predict?k=2")
+            .set_json(&data)
+            .to_request();
+        let mixed: Vec<(Vec<String>, Vec<f32>)> = read_body_json(call_service(&srv, req).await).await;
+        assert_eq!(mixed[0].0.len(), 4);
+        assert_eq!(mixed[1].0.len(), 2);
+        assert!(mixed[2].0.is_empty());
     }
 }
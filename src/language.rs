@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use fasttext::FastText;
+
+/// Routes `/predict` texts to the model registered under their language for `--lang-detect-model`,
+/// using a fastText language identifier such as `lid.176.bin`
+pub(crate) struct LanguageRouter {
+    detector: FastText,
+    /// Models whose name is a language code the detector can return
+    models: HashMap<String, Arc<ArcSwap<crate::ServedModel>>>,
+}
+
+impl std::fmt::Debug for LanguageRouter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut languages: Vec<&String> = self.models.keys().collect();
+        languages.sort();
+        f.debug_struct("LanguageRouter").field("languages", &languages).finish_non_exhaustive()
+    }
+}
+
+impl LanguageRouter {
+    /// Keep the models named after one of the detector's labels, e.g. `en` for `__label__en`
+    pub(crate) fn new(detector: FastText, models: &HashMap<String, Arc<ArcSwap<crate::ServedModel>>>) -> Self {
+        let languages: Vec<String> = detector
+            .get_labels()
+            .map(|(labels, _)| {
+                labels
+                    .into_iter()
+                    .map(|label| crate::strip_label(label, Some(crate::DEFAULT_LABEL_PREFIX)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let models = models
+            .iter()
+            .filter(|(name, _)| languages.contains(name))
+            .map(|(name, model)| (name.clone(), model.clone()))
+            .collect();
+        LanguageRouter { detector, models }
+    }
+
+    pub(crate) fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<&str> = self.models.keys().map(String::as_str).collect();
+        languages.sort_unstable();
+        languages
+    }
+
+    /// Most likely language of `text`, without the label prefix
+    fn detect(&self, text: &str) -> Option<String> {
+        let prefix = Some(crate::DEFAULT_LABEL_PREFIX);
+        let (labels, _) = crate::predict_one_safe(&self.detector, text, 1, 0.0, usize::MAX, prefix, false).ok()?;
+        labels.into_iter().next()
+    }
+
+    /// Model registered for the detected language of `text`, `None` to use the default model
+    pub(crate) fn route(&self, text: &str) -> Option<Arc<crate::ServedModel>> {
+        let language = self.detect(text)?;
+        self.models.get(&language).map(|model| model.load_full())
+    }
+}
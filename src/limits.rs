@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Tracks in-flight requests per client IP and caps how many one client may hold
+#[derive(Debug)]
+pub(crate) struct ConnectionLimiter {
+    max_per_ip: usize,
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Held for the lifetime of a request; releases its slot on drop
+#[derive(Debug)]
+pub(crate) struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(max_per_ip: usize) -> Self {
+        ConnectionLimiter {
+            max_per_ip,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionPermit> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_insert(0);
+        if *count >= self.max_per_ip {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn active(&self, ip: IpAddr) -> usize {
+        self.active.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            // 计数归零后移除，避免表随客户端数量无限增长
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Caps how many predictions run at once across all clients
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimiter {
+    max: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimiter {
+    pub(crate) fn new(max: usize) -> Self {
+        ConcurrencyLimiter {
+            max,
+            semaphore: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    pub(crate) fn max(&self) -> usize {
+        self.max
+    }
+
+    /// A slot released when the permit drops, or `None` when all are taken
+    pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.semaphore.clone().try_acquire_owned().ok()
+    }
+}
+
+/// Token bucket per client IP: refills at `rate` tokens a second, holding at most one second's worth
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    rate: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Buckets kept before idle, fully refilled ones are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+impl RateLimiter {
+    pub(crate) fn new(rate: f64) -> Self {
+        RateLimiter {
+            rate,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        self.rate.max(1.0)
+    }
+
+    /// Take a token for `ip`, or return how long until one is available
+    pub(crate) fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = self.capacity();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&ip) {
+            let rate = self.rate;
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// Soft cap on the process's memory for `--max-memory-mb`, prediction routes are shed while it's exceeded
+#[derive(Debug)]
+pub(crate) struct MemoryGuard {
+    limit_bytes: u64,
+    shedding: AtomicBool,
+}
+
+impl MemoryGuard {
+    pub(crate) fn new(limit_mb: u64) -> Self {
+        MemoryGuard {
+            limit_bytes: limit_mb.saturating_mul(1024 * 1024),
+            shedding: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn limit_mb(&self) -> u64 {
+        self.limit_bytes / (1024 * 1024)
+    }
+
+    pub(crate) fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    /// Record a usage sample, logging when shedding starts or stops
+    pub(crate) fn update(&self, used_bytes: u64) {
+        let over = used_bytes > self.limit_bytes;
+        if self.shedding.swap(over, Ordering::Relaxed) == over {
+            return;
+        }
+        let used_mb = used_bytes / (1024 * 1024);
+        if over {
+            log::warn!("Memory usage {} MB above {} MB, shedding predictions", used_mb, self.limit_mb());
+        } else {
+            log::info!("Memory usage back to {} MB, accepting predictions again", used_mb);
+        }
+    }
+}
+
+/// Resident set size of this process, `None` where `/proc` isn't available
+pub(crate) fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    // 格式为 "VmRSS:     1234 kB"
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConnectionLimiter, MemoryGuard, RateLimiter};
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_connection_limit_per_ip() {
+        let limiter = Arc::new(ConnectionLimiter::new(2));
+        let noisy: IpAddr = "10.0.0.1".parse().unwrap();
+        let quiet: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.try_acquire(noisy).unwrap();
+        let _second = limiter.try_acquire(noisy).unwrap();
+        assert!(limiter.try_acquire(noisy).is_none());
+        assert!(limiter.try_acquire(quiet).is_some());
+
+        drop(first);
+        assert_eq!(limiter.active(noisy), 1);
+        assert!(limiter.try_acquire(noisy).is_some());
+    }
+
+    #[test]
+    fn test_rate_limit_refills() {
+        let limiter = RateLimiter::new(2.0);
+        let noisy: IpAddr = "10.0.0.1".parse().unwrap();
+        let quiet: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check_at(noisy, start).is_ok());
+        assert!(limiter.check_at(noisy, start).is_ok());
+        let retry_after = limiter.check_at(noisy, start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(500));
+        assert!(limiter.check_at(quiet, start).is_ok());
+
+        assert!(limiter.check_at(noisy, start + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check_at(noisy, start + Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn test_memory_guard_sheds_above_limit() {
+        let guard = MemoryGuard::new(1);
+        guard.update(1024 * 1024);
+        assert!(!guard.is_shedding());
+        guard.update(1024 * 1024 + 1);
+        assert!(guard.is_shedding());
+        guard.update(512 * 1024);
+        assert!(!guard.is_shedding());
+    }
+}
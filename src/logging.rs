@@ -0,0 +1,75 @@
+use std::io::Write;
+
+/// Initialize the global logger from `RUST_LOG`, as pretty text or one JSON object per line
+pub fn init(format: &str) {
+    match format {
+        "json" => env_logger::Builder::from_default_env()
+            .format(|buf, record| {
+                let timestamp = buf.timestamp_millis().to_string();
+                writeln!(buf, "{}", json_line(record, &timestamp))
+            })
+            .init(),
+        _ => pretty_env_logger::init(),
+    }
+}
+
+/// Render a log record as JSON; a leading `[request-id] ` in the message becomes a `request_id` field
+fn json_line(record: &log::Record, timestamp: &str) -> String {
+    let message = record.args().to_string();
+    let mut line = serde_json::json!({
+        "timestamp": timestamp,
+        "level": record.level().as_str(),
+        "target": record.target(),
+    });
+    match split_request_id(&message) {
+        Some((request_id, message)) => {
+            line["request_id"] = request_id.into();
+            line["message"] = message.into();
+        }
+        None => line["message"] = message.into(),
+    }
+    line.to_string()
+}
+
+fn split_request_id(message: &str) -> Option<(&str, &str)> {
+    let rest = message.strip_prefix('[')?;
+    let end = rest.find("] ")?;
+    let request_id = &rest[..end];
+    if request_id.is_empty() || request_id.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((request_id, &rest[end + 2..]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::json_line;
+
+    #[test]
+    fn test_json_line() {
+        // format_args!的临时值只活到语句结束，Record要在同一个表达式里用掉
+        let line = json_line(
+            &log::Record::builder()
+                .args(format_args!("[req-1] Processing {} texts with k={}", 3, 1))
+                .level(log::Level::Info)
+                .target("fasttext_serving::http")
+                .build(),
+            "2026-10-16T00:00:00.000Z",
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).expect("log line is not JSON");
+        assert_eq!(value["level"], "INFO");
+        assert_eq!(value["target"], "fasttext_serving::http");
+        assert_eq!(value["request_id"], "req-1");
+        assert_eq!(value["message"], "Processing 3 texts with k=1");
+        assert_eq!(value["timestamp"], "2026-10-16T00:00:00.000Z");
+
+        let record = log::Record::builder()
+            .args(format_args!("[not an id] Loading model"))
+            .level(log::Level::Warn)
+            .target("fasttext_serving")
+            .build();
+        let value: serde_json::Value = serde_json::from_str(&json_line(&record, "")).unwrap();
+        assert_eq!(value["message"], "[not an id] Loading model");
+        assert!(value.get("request_id").is_none());
+    }
+}
@@ -1,12 +1,39 @@
+use clap::parser::ValueSource;
 use clap::{Arg, ArgAction, Command};
-// use fasttext::FastText;  // 注释掉Rust FastText
+use fasttext::FastText;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
+#[cfg(any(feature = "http", feature = "grpc"))]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+mod config;
 #[cfg(feature = "grpc")]
 mod grpc;
 #[cfg(feature = "http")]
 mod http;
+#[cfg(feature = "http")]
+mod language;
+#[cfg(feature = "http")]
+mod limits;
+mod logging;
+#[cfg(feature = "http")]
+mod metrics;
+#[cfg(feature = "http")]
+mod neighbors;
+#[cfg(feature = "http")]
+mod prediction_cache;
+mod remote;
+#[cfg(feature = "http")]
+mod shadow;
+#[cfg(feature = "http")]
+mod telemetry;
+#[cfg(feature = "http")]
+mod vector_cache;
 
 #[cfg(all(unix, not(target_env = "musl"), not(target_arch = "aarch64")))]
 #[global_allocator]
@@ -20,6 +47,8 @@ static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
 pub enum PredictError {
     ModelError(String),
     InputError(String),
+    /// Prediction didn't finish within the configured number of milliseconds
+    Timeout(u64),
 }
 
 impl std::fmt::Display for PredictError {
@@ -27,158 +56,1014 @@ impl std::fmt::Display for PredictError {
         match self {
             PredictError::ModelError(msg) => write!(f, "Model error: {}", msg),
             PredictError::InputError(msg) => write!(f, "Input error: {}", msg),
+            PredictError::Timeout(ms) => write!(f, "Prediction timed out after {}ms", ms),
         }
     }
 }
 
 impl std::error::Error for PredictError {}
 
-#[derive(Clone, Debug)]
-pub struct ServerConfig {
+impl PredictError {
+    /// Machine-readable error code reported to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            PredictError::ModelError(_) => "model_error",
+            PredictError::InputError(_) => "input_error",
+            PredictError::Timeout(_) => "predict_timeout",
+        }
+    }
+}
+
+/// Name the model is registered under when serving a single model
+pub const DEFAULT_MODEL: &str = "default";
+
+/// Prefix fastText puts in front of every label unless trained with `-label`
+pub const DEFAULT_LABEL_PREFIX: &str = "__label__";
+
+/// What a batch does with inputs that fail to predict
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "http", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Drop failed items from the results
+    Skip,
+    /// Keep failed items as marker labels
+    #[default]
+    Mark,
+    /// Reject the whole batch on the first failed item
+    Fail,
+}
+
+impl std::str::FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnError::Skip),
+            "mark" => Ok(OnError::Mark),
+            "fail" => Ok(OnError::Fail),
+            other => Err(format!("unknown error behavior: {}", other)),
+        }
+    }
+}
+
+/// What startup does when one of several models fails to load
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnModelLoadError {
+    /// Exit on the first model that fails
+    Abort,
+    /// Serve the other models, logging the failure at info level
+    Skip,
+    /// Serve the other models, logging the failure as a warning
+    #[default]
+    Warn,
+}
+
+impl std::str::FromStr for OnModelLoadError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(OnModelLoadError::Abort),
+            "skip" => Ok(OnModelLoadError::Skip),
+            "warn" => Ok(OnModelLoadError::Warn),
+            other => Err(format!("unknown model load error behavior: {}", other)),
+        }
+    }
+}
+
+/// A model that failed to load at startup, reported in `/stats`
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct FailedModel {
+    pub name: String,
+    pub path: String,
+    pub error: String,
+}
+
+/// A loaded model with what is probed from it once, when it is loaded, so requests never probe it again.
+/// Swapped as one pointer on reload, so nobody sees the new model with the old model's dimension
+#[derive(Debug)]
+pub struct ServedModel {
+    model: FastText,
+    /// Length of the model's sentence vectors, see `probe_dimension`
+    pub dimension: usize,
+    /// Whether the model is a quantized `.ftz`
+    pub is_quantized: bool,
+    /// `http::model_checksum`, computed by the first request that needs it
+    #[cfg(feature = "http")]
+    checksum: std::sync::OnceLock<u64>,
+}
+
+impl ServedModel {
+    pub fn new(model: FastText) -> Self {
+        ServedModel {
+            dimension: probe_dimension(&model),
+            is_quantized: model.is_quant(),
+            #[cfg(feature = "http")]
+            checksum: std::sync::OnceLock::new(),
+            model,
+        }
+    }
+
+    /// Checksum versioning the model's `?fingerprint=`s and cached predictions, computed once per loaded model
+    #[cfg(feature = "http")]
+    pub fn checksum(&self) -> u64 {
+        *self.checksum.get_or_init(|| http::model_checksum(&self.model))
+    }
+
+    /// Error message for vector `operation`s a quantized model can't answer meaningfully
+    pub fn quantized_unsupported(&self, operation: &str) -> Option<String> {
+        if !self.is_quantized {
+            return None;
+        }
+        Some(format!(
+            "{} is not supported on quantized models: their vectors are compressed and pruned words \
+             come back as zeros, serve the full .bin model instead",
+            operation
+        ))
+    }
+}
+
+impl std::ops::Deref for ServedModel {
+    type Target = FastText;
+
+    fn deref(&self) -> &FastText {
+        &self.model
+    }
+}
+
+/// Per-model settings that take precedence over the global defaults
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelOverrides {
+    pub default_threshold: Option<f32>,
+    pub default_k: Option<u32>,
+    pub max_text_length: Option<usize>,
+    /// Prefix stripped from this model's labels, e.g. `__cat__`
+    pub label_prefix: Option<String>,
+}
+
+impl ModelOverrides {
+    /// Check the overridden values with the validators used for the global defaults
+    fn validate(&self) -> Result<(), String> {
+        if let Some(threshold) = self.default_threshold {
+            check_default_threshold(threshold)?;
+        }
+        if let Some(k) = self.default_k {
+            check_default_k(k)?;
+        }
+        Ok(())
+    }
+}
+
+/// Defaults of one model: the global values with its overrides applied, borrowed from the config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModelSettings<'a> {
+    pub default_threshold: f32,
+    pub default_k: u32,
     pub max_text_length: usize,
+    /// Prefix to strip from labels, `None` when labels are returned raw
+    pub label_prefix: Option<&'a str>,
+}
+
+/// Bounds on request and text size, k, time and concurrency, parsed once and applied by both servers
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Limits {
+    pub max_request_size_mb: u32,
+    /// Longest text in bytes, longer texts are rejected
+    pub max_text_length: usize,
+    /// Most texts accepted in one request or gRPC stream
+    pub max_batch_size: usize,
+    /// Total text bytes accepted across the messages of one gRPC stream, unbounded when unset
+    pub grpc_max_stream_bytes: Option<usize>,
+    /// Seconds between HTTP/2 pings on idle gRPC connections, no pings when unset
+    pub grpc_keepalive_interval_secs: Option<u64>,
+    /// Seconds to wait for a ping acknowledgement before closing the gRPC connection
+    pub grpc_keepalive_timeout_secs: Option<u64>,
+    /// TCP keepalive probe interval of accepted gRPC connections, off when unset
+    pub tcp_keepalive_secs: Option<u64>,
+    /// Largest k a client may ask for, larger values are clamped
+    pub max_k: u32,
+    /// Labels returned across all items of one `/predict` batch, later items are truncated, unbounded when unset
+    pub max_total_predictions: Option<usize>,
+    /// Upper bound on a single text's prediction, unbounded when unset
+    pub predict_timeout_ms: Option<u64>,
+    pub shutdown_timeout_secs: u64,
+    /// Idle time before a kept-alive connection is closed, 0 disables keep-alive
+    pub keep_alive_secs: u64,
+    /// Time a client has to send the request head, 0 disables the timeout
+    pub client_request_timeout_ms: u64,
+    /// Time a client has to acknowledge the connection shutdown, 0 disables the timeout
+    pub client_disconnect_timeout_ms: u64,
+    pub max_connections_per_ip: Option<usize>,
+    /// Predictions in flight across all clients, further requests get 503
+    pub max_concurrent_requests: Option<usize>,
+    /// Resident memory in MB above which prediction routes answer 503 until usage recovers
+    pub max_memory_mb: Option<u64>,
+    /// Requests per second each client IP may send to the prediction routes
+    pub rate_limit: Option<f64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_size_mb: 500,
+            max_text_length: 5_000_000,
+            max_batch_size: 10_000,
+            grpc_max_stream_bytes: None,
+            grpc_keepalive_interval_secs: None,
+            grpc_keepalive_timeout_secs: None,
+            tcp_keepalive_secs: None,
+            max_k: 100,
+            max_total_predictions: None,
+            predict_timeout_ms: None,
+            shutdown_timeout_secs: 30,
+            keep_alive_secs: 5,
+            client_request_timeout_ms: 5000,
+            client_disconnect_timeout_ms: 1000,
+            max_connections_per_ip: None,
+            max_concurrent_requests: None,
+            max_memory_mb: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Command line flags read by `from_matches`
+    fn args() -> Vec<Arg> {
+        vec![
+            Arg::new("max-request-size")
+                .long("max-request-size")
+                .default_value("500")
+                .num_args(1)
+                .help("Maximum request size in MB (default: 500MB)"),
+            Arg::new("max-batch-size")
+                .long("max-batch-size")
+                .default_value("10000")
+                .num_args(1)
+                .help("Maximum number of texts per request or gRPC stream"),
+            Arg::new("grpc-max-stream-bytes")
+                .long("grpc-max-stream-bytes")
+                .num_args(1)
+                .help("Maximum total text bytes across one gRPC stream's messages (default: unlimited)"),
+            Arg::new("grpc-keepalive-interval")
+                .long("grpc-keepalive-interval")
+                .num_args(1)
+                .help("Seconds between HTTP/2 keepalive pings on gRPC connections (default: off)"),
+            Arg::new("grpc-keepalive-timeout")
+                .long("grpc-keepalive-timeout")
+                .num_args(1)
+                .help("Seconds to wait for a keepalive ping to be acknowledged before closing the connection"),
+            Arg::new("tcp-keepalive")
+                .long("tcp-keepalive")
+                .num_args(1)
+                .help("Seconds between TCP keepalive probes on gRPC connections (default: off)"),
+            Arg::new("predict-timeout-ms")
+                .long("predict-timeout-ms")
+                .num_args(1)
+                .help("Milliseconds a single text may spend in the model before it is marked as timed out"),
+            Arg::new("max-text-length")
+                .long("max-text-length")
+                .default_value("5000000")
+                .num_args(1)
+                .help("Maximum text length in bytes (default: 5MB)"),
+            Arg::new("max-k")
+                .long("max-k")
+                .default_value("100")
+                .num_args(1)
+                .help("Largest number of labels a request may ask for, larger k is clamped (default: 100)"),
+            Arg::new("max-total-predictions")
+                .long("max-total-predictions")
+                .num_args(1)
+                .help("Maximum labels across one /predict batch, later items are truncated (default: unlimited)"),
+            Arg::new("shutdown-timeout")
+                .long("shutdown-timeout")
+                .default_value("30")
+                .num_args(1)
+                .help("Seconds to let in-flight requests finish after a shutdown signal (default: 30)"),
+            Arg::new("keep-alive")
+                .long("keep-alive")
+                .default_value("5")
+                .num_args(1)
+                .help("Seconds an idle connection is kept open, 0 disables keep-alive (default: 5)"),
+            Arg::new("client-request-timeout")
+                .long("client-request-timeout")
+                .default_value("5000")
+                .num_args(1)
+                .help("Milliseconds a client has to send the request head, 0 disables it (default: 5000)"),
+            Arg::new("client-disconnect-timeout")
+                .long("client-disconnect-timeout")
+                .default_value("1000")
+                .num_args(1)
+                .help("Milliseconds a client has to close the connection on shutdown, 0 disables it (default: 1000)"),
+            Arg::new("max-connections-per-ip")
+                .long("max-connections-per-ip")
+                .num_args(1)
+                .help("Maximum concurrent requests from a single client IP (default: unlimited)"),
+            Arg::new("max-concurrent-requests")
+                .long("max-concurrent-requests")
+                .num_args(1)
+                .help("Maximum predictions in flight across all clients, others get 503 (default: unlimited)"),
+            Arg::new("max-memory-mb")
+                .long("max-memory-mb")
+                .num_args(1)
+                .help("Answer 503 on prediction routes while resident memory is above this many MB (default: off)"),
+            Arg::new("rate-limit")
+                .long("rate-limit")
+                .num_args(1)
+                .help("Requests per second each client IP may send to the prediction routes"),
+        ]
+    }
+
+    /// Parse and validate the limit flags, naming the first invalid one
+    fn from_matches(matches: &clap::ArgMatches) -> Result<Limits, String> {
+        Ok(Limits {
+            max_request_size_mb: required_flag(matches, "max-request-size", |_| true)?,
+            max_text_length: required_flag(matches, "max-text-length", |_| true)?,
+            max_batch_size: required_flag(matches, "max-batch-size", |size: &usize| *size > 0)?,
+            grpc_max_stream_bytes: parse_flag(matches, "grpc-max-stream-bytes", |max: &usize| *max > 0)?,
+            grpc_keepalive_interval_secs: parse_flag(matches, "grpc-keepalive-interval", |secs: &u64| *secs > 0)?,
+            grpc_keepalive_timeout_secs: parse_flag(matches, "grpc-keepalive-timeout", |secs: &u64| *secs > 0)?,
+            tcp_keepalive_secs: parse_flag(matches, "tcp-keepalive", |secs: &u64| *secs > 0)?,
+            max_k: required_flag(matches, "max-k", |k: &u32| *k > 0)?,
+            max_total_predictions: parse_flag(matches, "max-total-predictions", |max: &usize| *max > 0)?,
+            predict_timeout_ms: parse_flag(matches, "predict-timeout-ms", |ms: &u64| *ms > 0)?,
+            shutdown_timeout_secs: required_flag(matches, "shutdown-timeout", |_| true)?,
+            keep_alive_secs: required_flag(matches, "keep-alive", |_| true)?,
+            client_request_timeout_ms: required_flag(matches, "client-request-timeout", |_| true)?,
+            client_disconnect_timeout_ms: required_flag(matches, "client-disconnect-timeout", |_| true)?,
+            max_connections_per_ip: parse_flag(matches, "max-connections-per-ip", |max: &usize| *max > 0)?,
+            max_concurrent_requests: parse_flag(matches, "max-concurrent-requests", |max: &usize| *max > 0)?,
+            max_memory_mb: parse_flag(matches, "max-memory-mb", |max: &u64| *max > 0)?,
+            rate_limit: parse_flag(matches, "rate-limit", |rate: &f64| rate.is_finite() && *rate > 0.0)?,
+        })
+    }
+}
+
+/// Value of flag `id` parsed as `T`, `None` when it wasn't given and has no default
+fn parse_flag<T: std::str::FromStr>(
+    matches: &clap::ArgMatches,
+    id: &str,
+    valid: impl Fn(&T) -> bool,
+) -> Result<Option<T>, String> {
+    match matches.get_one::<String>(id) {
+        None => Ok(None),
+        Some(value) => match value.parse::<T>() {
+            Ok(parsed) if valid(&parsed) => Ok(Some(parsed)),
+            _ => Err(format!("Invalid --{}: {}", id, value)),
+        },
+    }
+}
+
+/// Whether `name` is a valid HTTP header name (an RFC 7230 token)
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Validate a default k, the same for the flag, the config file and `PATCH /admin/config`
+pub fn check_default_k(k: u32) -> Result<u32, String> {
+    if k == 0 {
+        return Err("default_k must be at least 1, got 0".to_string());
+    }
+    Ok(k)
+}
+
+/// Validate a default threshold, the same for the flag, the config file and `PATCH /admin/config`
+pub fn check_default_threshold(threshold: f32) -> Result<f32, String> {
+    if !threshold.is_finite() || !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("default_threshold must be a finite number within [0, 1], got {}", threshold));
+    }
+    Ok(threshold)
+}
+
+/// `parse_flag` for flags with a default value
+fn required_flag<T: std::str::FromStr>(
+    matches: &clap::ArgMatches,
+    id: &str,
+    valid: impl Fn(&T) -> bool,
+) -> Result<T, String> {
+    parse_flag(matches, id, valid)?.ok_or_else(|| format!("missing --{}", id))
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ServerConfig {
+    pub model_path: String,
+    #[serde(flatten)]
+    pub limits: Limits,
     pub default_threshold: f32,
+    pub default_k: u32,
+    /// Normalized entropy above which a prediction is flagged uncertain
+    pub uncertainty_threshold: f32,
     pub default_vector_dim: usize,
-    pub max_request_size_mb: u32,
+    /// Disable Nagle's algorithm on TCP listeners so small responses go out immediately
+    pub tcp_nodelay: bool,
+    /// Pending connections each TCP listener queues before refusing more
+    pub backlog: u32,
+    /// Stack size in bytes for worker and blocking threads, the platform default when unset
+    pub worker_stack_size: Option<usize>,
+    /// Upper bound on each runtime's blocking thread pool, the runtime default when unset
+    pub blocking_threads: Option<usize>,
+    /// Threads of the pool predicting large HTTP batches, the rayon default when unset
+    pub predict_threads: Option<usize>,
+    /// Built from `predict_threads` at startup, separate from the actix workers and blocking threads
+    #[cfg(feature = "http")]
+    #[serde(skip)]
+    pub predict_pool: Option<std::sync::Arc<rayon::ThreadPool>>,
+    /// LRU of recent predictions shared by every config snapshot, cleared on model reload
+    #[cfg(feature = "http")]
+    #[serde(skip)]
+    pub(crate) prediction_cache: Option<std::sync::Arc<prediction_cache::PredictionCache>>,
+    /// Candidate model whose `/predict` answers are compared with the primary's, off the request path
+    #[cfg(feature = "http")]
+    #[serde(skip)]
+    pub(crate) shadow_model: Option<std::sync::Arc<shadow::ShadowModel>>,
+    /// Compress HTTP responses according to the client's `Accept-Encoding`
+    pub compression: bool,
+    /// Browser origins allowed by CORS, `*` for any; CORS is off when empty
+    pub cors_allow_origins: Vec<String>,
+    /// Request headers allowed by CORS on top of the built-in ones, e.g. `x-request-id`
+    pub cors_allow_headers: Vec<String>,
+    /// Seconds browsers may cache a preflight answer, sent as `Access-Control-Max-Age`
+    pub cors_max_age_secs: Option<usize>,
+    pub vector_cache_path: Option<String>,
+    /// Language identifier routing `/predict` texts to the model named after their language
+    pub lang_detect_model: Option<String>,
+    /// Built from `lang_detect_model` and the registered models when the HTTP server starts
+    #[cfg(feature = "http")]
+    #[serde(skip)]
+    pub(crate) language_router: Option<std::sync::Arc<language::LanguageRouter>>,
+    /// PEM certificate chain and private key, TLS is served when both are set
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// OTLP collector receiving handler spans, tracing is off when unset
+    pub otlp_endpoint: Option<String>,
+    /// Shared secret required on every route except /health, never echoed back
+    #[serde(skip_serializing)]
+    pub api_key: Option<String>,
+    pub replace_chars: Vec<char>,
+    /// Lowercase inputs before prediction
+    pub normalize_lowercase: bool,
+    /// Replace ASCII punctuation with spaces before prediction
+    pub normalize_strip_punct: bool,
+    /// Fail items whose scores or vectors contain NaN/infinity instead of zeroing those values
+    pub reject_nan: bool,
+    /// Predict each line of a multi-line text separately, returning a nested result
+    pub split_lines: bool,
+    /// How batches treat failed items unless a request overrides it
+    pub on_error: OnError,
+    /// Whether a model failing to load at startup stops the server
+    pub on_model_load_error: OnModelLoadError,
+    /// Names of the models served, in registration order
+    pub loaded_models: Vec<String>,
+    /// Models given at startup that couldn't be loaded
+    pub failed_models: Vec<FailedModel>,
+    pub field_separator: String,
+    /// Prefix stripped from predicted labels
+    pub label_prefix: String,
+    /// Return labels exactly as the model stores them
+    pub keep_label_prefix: bool,
+    /// Append the error message to the marker label of a failed prediction
+    pub marker_message: bool,
+    /// Count each label's top-1 predictions in `fasttext_label_predicted_total`
+    pub per_label_metrics: bool,
+    /// Only these labels are kept in `/predict` results, all when empty
+    pub label_filter: Vec<String>,
+    /// Separates the path segments of hierarchical labels for `?group_depth=`
+    pub label_separator: String,
+    /// Per-label score thresholds; other labels use the request's threshold
+    pub label_thresholds: HashMap<String, f32>,
+    /// Run a few predictions on each freshly loaded model before serving it
+    pub warmup: bool,
+    /// Report not-ready until a model has answered a real prediction or the default one its warmup
+    pub ready_after_prediction: bool,
+    /// Set by the first successful prediction under `ready_after_prediction`
+    #[cfg(feature = "http")]
+    #[serde(skip)]
+    pub(crate) first_prediction: Option<std::sync::Arc<http::FirstPrediction>>,
+    pub ensemble_weights: HashMap<String, f32>,
+    pub model_overrides: HashMap<String, ModelOverrides>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            model_path: String::new(),
+            limits: Limits::default(),
+            default_threshold: 0.0,
+            default_k: 1,
+            uncertainty_threshold: 0.8,
+            default_vector_dim: 100,
+            tcp_nodelay: true,
+            backlog: 1024,
+            worker_stack_size: None,
+            blocking_threads: None,
+            predict_threads: None,
+            #[cfg(feature = "http")]
+            predict_pool: None,
+            #[cfg(feature = "http")]
+            prediction_cache: None,
+            #[cfg(feature = "http")]
+            shadow_model: None,
+            compression: false,
+            cors_allow_origins: Vec::new(),
+            cors_allow_headers: Vec::new(),
+            cors_max_age_secs: None,
+            vector_cache_path: None,
+            lang_detect_model: None,
+            #[cfg(feature = "http")]
+            language_router: None,
+            tls_cert: None,
+            tls_key: None,
+            otlp_endpoint: None,
+            api_key: None,
+            replace_chars: Vec::new(),
+            normalize_lowercase: false,
+            normalize_strip_punct: false,
+            reject_nan: false,
+            split_lines: false,
+            on_error: OnError::Mark,
+            on_model_load_error: OnModelLoadError::Warn,
+            loaded_models: Vec::new(),
+            failed_models: Vec::new(),
+            field_separator: " ".to_string(),
+            label_prefix: DEFAULT_LABEL_PREFIX.to_string(),
+            keep_label_prefix: false,
+            marker_message: false,
+            per_label_metrics: false,
+            label_filter: Vec::new(),
+            label_separator: "/".to_string(),
+            label_thresholds: HashMap::new(),
+            warmup: true,
+            ready_after_prediction: false,
+            #[cfg(feature = "http")]
+            first_prediction: None,
+            ensemble_weights: HashMap::new(),
+            model_overrides: HashMap::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Warning for a `--default-vector-dim` that disagrees with the loaded model's `dimension`
+    pub fn vector_dim_mismatch(&self, dimension: usize) -> Option<String> {
+        if dimension == self.default_vector_dim {
+            return None;
+        }
+        Some(format!(
+            "--default-vector-dim {} does not match the model's vector dimension {}, using {}",
+            self.default_vector_dim, dimension, dimension
+        ))
+    }
+
+    /// Prefix to strip from labels, `None` when labels are returned raw
+    pub fn label_prefix(&self) -> Option<&str> {
+        if self.keep_label_prefix {
+            None
+        } else {
+            Some(&self.label_prefix)
+        }
+    }
+
+    /// Label reported in place of a failed prediction, e.g. `error` or `timeout`,
+    /// prefixed like real labels when those are returned raw
+    pub fn marker_label(&self, marker: &str) -> String {
+        match self.label_prefix() {
+            Some(_) => marker.to_string(),
+            None => format!("{}{}", self.label_prefix, marker),
+        }
+    }
+
+    /// Labels and scores standing in for a failed prediction so a batch keeps its order,
+    /// shared by both transports and `predict_one`
+    pub fn error_marker(&self, e: &PredictError) -> (Vec<String>, Vec<f32>) {
+        let marker = match e {
+            PredictError::Timeout(_) => "timeout",
+            _ => "error",
+        };
+        let label = if self.marker_message {
+            format!("{}: {}", self.marker_label(marker), e)
+        } else {
+            self.marker_label(marker)
+        };
+        (vec![label], vec![0.0])
+    }
+
+    /// Effective defaults for the named model, falling back to the global values
+    pub fn for_model(&self, name: &str) -> ModelSettings<'_> {
+        let overrides = self.model_overrides.get(name);
+        let label_prefix = match overrides.and_then(|overrides| overrides.label_prefix.as_deref()) {
+            Some(prefix) if !self.keep_label_prefix => Some(prefix),
+            _ => self.label_prefix(),
+        };
+        ModelSettings {
+            default_threshold: overrides.and_then(|o| o.default_threshold).unwrap_or(self.default_threshold),
+            default_k: overrides.and_then(|o| o.default_k).unwrap_or(self.default_k),
+            max_text_length: overrides.and_then(|o| o.max_text_length).unwrap_or(self.limits.max_text_length),
+            label_prefix,
+        }
+    }
+}
+
+/// Server-side preprocessing applied to every input before it reaches the model.
+/// Characters in `replace_chars`, and punctuation with `normalize_strip_punct`, become spaces,
+/// i.e. extra token boundaries for fastText; `normalize_lowercase` lowercases the rest.
+pub fn prepare_input<'a>(text: &'a str, config: &ServerConfig) -> Cow<'a, str> {
+    let is_separator =
+        |c: char| config.replace_chars.contains(&c) || (config.normalize_strip_punct && c.is_ascii_punctuation());
+    let lowercase = config.normalize_lowercase && text.chars().any(char::is_uppercase);
+    if !lowercase && !text.chars().any(is_separator) {
+        return Cow::Borrowed(text);
+    }
+    let text: String = text.chars().map(|c| if is_separator(c) { ' ' } else { c }).collect();
+    if lowercase {
+        Cow::Owned(text.to_lowercase())
+    } else {
+        Cow::Owned(text)
+    }
+}
+
+/// Load per-model overrides from a JSON file shaped like `{"name": {"default_k": 3}}`
+fn load_model_overrides(path: &str) -> Result<HashMap<String, ModelOverrides>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let overrides: HashMap<String, ModelOverrides> =
+        serde_json::from_str(&content).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+    for (name, model) in &overrides {
+        model.validate().map_err(|e| format!("model {}: {}", name, e))?;
+    }
+    Ok(overrides)
+}
+
+/// Load per-label thresholds from a JSON file shaped like `{"spam": 0.9, "promo": 0.3}`
+fn load_label_thresholds(path: &str) -> Result<HashMap<String, f32>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let thresholds: HashMap<String, f32> =
+        serde_json::from_str(&content).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+    match thresholds.iter().find(|(_, threshold)| !threshold.is_finite()) {
+        Some((label, threshold)) => Err(format!("threshold of {} must be a finite number, got {}", label, threshold)),
+        None => Ok(thresholds),
+    }
+}
+
+/// Drop `prefix` from `label`; labels without it are returned unchanged
+pub fn strip_label(label: String, prefix: Option<&str>) -> String {
+    match prefix.and_then(|prefix| label.strip_prefix(prefix)) {
+        Some(stripped) => stripped.to_string(),
+        None => label,
+    }
 }
 
 #[inline]
 pub fn predict_one_safe(
-    model_path: &str,
+    model: &FastText,
     text: &str,
     k: u32,
     threshold: f32,
     max_text_length: usize,
+    label_prefix: Option<&str>,
+    reject_nan: bool,
 ) -> Result<(Vec<String>, Vec<f32>), PredictError> {
-    // Validate input
+    validate_text(text, max_text_length)?;
+    
+    // Ensure k >= 1
+    let k = if k > 0 { k } else { 1 };
+    
+    // NOTE: text needs to end in a newline
+    // to exactly mimic the behavior of the cli
+    let preds = if text.ends_with('\n') {
+        model.predict(text, k as i32, threshold)
+    } else {
+        let mut text = text.to_string();
+        text.push('\n');
+        model.predict(&text, k as i32, threshold)
+    }
+    .map_err(|e| PredictError::ModelError(format!("Prediction failed: {}", e)))?;
+    
+    let mut labels = Vec::with_capacity(preds.len());
+    let mut probs = Vec::with_capacity(preds.len());
+    for pred in preds {
+        labels.push(strip_label(pred.label, label_prefix));
+        probs.push(pred.prob);
+    }
+    sanitize_scores(&mut probs, reject_nan)?;
+    Ok((labels, probs))
+}
+
+/// The input checks `predict_one_safe` runs before touching the model
+pub fn validate_text(text: &str, max_text_length: usize) -> Result<(), PredictError> {
     if text.is_empty() {
         return Err(PredictError::InputError("Empty text input".to_string()));
     }
-    
     if text.len() > max_text_length {
         return Err(PredictError::InputError(format!("Text too long: {} bytes", text.len())));
     }
-    
-    // Ensure k >= 1
-    let k = if k > 0 { k } else { 1 };
-    
-    // 调用官方Python FastText v0.9.2
-    call_python_fasttext(model_path, text, k, threshold)
+    Ok(())
 }
 
-fn call_python_fasttext(
-    model_path: &str,
-    text: &str,
-    k: u32,
-    threshold: f32,
-) -> Result<(Vec<String>, Vec<f32>), PredictError> {
-    use std::process::Command;
-    use std::io::Write;
-    
-    // 创建Python脚本调用FastText
-    let python_script = format!(
-        r#"
-import fasttext
-import json
-import sys
-
-try:
-    # 加载模型
-    model = fasttext.load_model('{}')
-    
-    # 读取输入文本
-    text = sys.stdin.read().strip()
-    
-    # 预测
-    labels, probs = model.predict(text, k={})
-    
-    # 转换为列表并输出JSON
-    result = {{
-        "labels": [label.replace("__label__", "") for label in labels],
-        "scores": probs.tolist()
-    }}
-    print(json.dumps(result))
-    
-except Exception as e:
-    print(json.dumps({{"error": str(e)}}), file=sys.stderr)
-    sys.exit(1)
-"#,
-        model_path, k
-    );
-    
-    // 执行Python脚本
-    let mut child = Command::new("python3")
-        .arg("-c")
-        .arg(&python_script)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()
-        .map_err(|e| PredictError::ModelError(format!("Failed to start Python: {}", e)))?;
-    
-    // 写入文本到stdin
-    if let Some(stdin) = child.stdin.as_mut() {
-        stdin.write_all(text.as_bytes())
-            .map_err(|e| PredictError::ModelError(format!("Failed to write to Python stdin: {}", e)))?;
+/// Sentence vector with the same non-finite handling as prediction scores
+pub fn sentence_vector_safe(model: &FastText, text: &str, reject_nan: bool) -> Result<Vec<f32>, PredictError> {
+    let mut vector = model.get_sentence_vector(text).map_err(PredictError::ModelError)?;
+    sanitize_scores(&mut vector, reject_nan)?;
+    Ok(vector)
+}
+
+/// Replace NaN and infinite values with 0.0, which JSON can represent, or fail when `reject` is set
+fn sanitize_scores(scores: &mut [f32], reject: bool) -> Result<(), PredictError> {
+    match scores.iter().position(|score| !score.is_finite()) {
+        None => Ok(()),
+        Some(index) if reject => Err(PredictError::InputError(format!(
+            "Model returned a non-finite value {} at position {}",
+            scores[index], index
+        ))),
+        Some(_) => {
+            scores.iter_mut().filter(|score| !score.is_finite()).for_each(|score| *score = 0.0);
+            Ok(())
+        }
     }
-    
-    // 等待执行完成
-    let output = child.wait_with_output()
-        .map_err(|e| PredictError::ModelError(format!("Failed to execute Python: {}", e)))?;
-    
-    if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
-        return Err(PredictError::ModelError(format!("Python FastText error: {}", error_msg)));
+}
+
+/// Dummy input whose sentence vector gives the model's real dimension
+const DIMENSION_PROBE: &str = "dimension probe";
+
+/// The length of a probe sentence vector, `get_dimension()` only when the probe fails
+fn probe_dimension(model: &FastText) -> usize {
+    let reported = model.get_dimension() as usize;
+    match model.get_sentence_vector(DIMENSION_PROBE) {
+        Ok(vector) => {
+            if vector.len() != reported {
+                log::warn!(
+                    "Model reports dimension {} but its sentence vectors have {} values, using {}",
+                    reported,
+                    vector.len(),
+                    vector.len()
+                );
+            }
+            vector.len()
+        }
+        Err(e) => {
+            log::warn!("Failed to probe the model's vector dimension, using {}: {}", reported, e);
+            reported
+        }
     }
-    
-    // 解析JSON结果
-    let result_str = String::from_utf8_lossy(&output.stdout);
-    let result: serde_json::Value = serde_json::from_str(&result_str)
-        .map_err(|e| PredictError::ModelError(format!("Failed to parse Python output: {}", e)))?;
-    
-    if let Some(error) = result.get("error") {
-        return Err(PredictError::ModelError(format!("FastText error: {}", error)));
+}
+
+/// Dummy inputs run through a freshly loaded model
+const WARMUP_TEXTS: &[&str] = &[
+    "warmup",
+    "the quick brown fox jumps over the lazy dog",
+    "how long should a model take to answer its first question",
+];
+
+/// Pay the model's lazy initialization cost up front with a few predictions and a sentence vector
+pub fn warm_up(model: &FastText) -> Result<Duration, PredictError> {
+    let started = Instant::now();
+    for text in WARMUP_TEXTS {
+        predict_one_safe(model, text, 1, 0.0, usize::MAX, None, false)?;
     }
-    
-    let labels: Vec<String> = result["labels"]
-        .as_array()
-        .ok_or_else(|| PredictError::ModelError("Invalid labels format".to_string()))?
-        .iter()
-        .map(|v| v.as_str().unwrap_or("").to_string())
-        .collect();
-    
-    let scores: Vec<f32> = result["scores"]
-        .as_array()
-        .ok_or_else(|| PredictError::ModelError("Invalid scores format".to_string()))?
-        .iter()
-        .map(|v| v.as_f64().unwrap_or(0.0) as f32)
-        .collect();
-    
-    Ok((labels, scores))
+    model
+        .get_sentence_vector(WARMUP_TEXTS[0])
+        .map_err(|e| PredictError::ModelError(format!("Sentence vector failed: {}", e)))?;
+    Ok(started.elapsed())
 }
 
 // 保留原始的predict_one函数以保持向后兼容，但内部使用安全版本
 #[inline]
 pub fn predict_one(
-    model_path: &str,
+    model: &FastText,
     text: &str,
     k: u32,
     threshold: f32,
     max_text_length: usize,
 ) -> (Vec<String>, Vec<f32>) {
-    match predict_one_safe(model_path, text, k, threshold, max_text_length) {
+    match predict_one_safe(model, text, k, threshold, max_text_length, Some(DEFAULT_LABEL_PREFIX), false) {
         Ok(result) => result,
         Err(e) => {
             log::error!("Prediction failed, returning default result: {}", e);
-            (vec!["error".to_string()], vec![0.0])
+            ServerConfig::default().error_marker(&e)
+        }
+    }
+}
+
+/// Split a `--model` value into a name and a path; a bare path serves as the default model
+fn parse_model_spec(spec: &str) -> (String, String) {
+    match spec.split_once('=') {
+        // URL里的查询参数也带=，不能当作名称分隔符
+        Some((name, path)) if !name.is_empty() && !path.is_empty() && !remote::is_remote(spec) => {
+            (name.to_string(), path.to_string())
+        }
+        _ => (DEFAULT_MODEL.to_string(), spec.to_string()),
+    }
+}
+
+/// `(name, path)` of each `--model` spec; local files must exist, remote models are only checked when fetched
+fn resolve_model_specs(specs: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut model_specs: Vec<(String, String)> = Vec::new();
+    for spec in specs {
+        let (name, path) = parse_model_spec(spec);
+        if model_specs.iter().any(|(existing, _)| *existing == name) {
+            return Err(format!("Model name registered twice: {}", name));
+        }
+        if !remote::is_remote(&path) && !Path::new(&path).exists() {
+            return Err(format!("Model file does not exist: {}", path));
+        }
+        model_specs.push((name, path));
+    }
+    Ok(model_specs)
+}
+
+/// Every `*.bin` and `*.ftz` in `dir` as `(name, path)`, named by file stem and sorted by name
+fn discover_models(dir: &Path) -> std::io::Result<Vec<(String, String)>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| matches!(path.extension().and_then(|ext| ext.to_str()), Some("bin") | Some("ftz")))
+        .collect();
+    paths.sort();
+    let mut models: Vec<(String, String)> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let path = path.to_string_lossy().into_owned();
+        // 同名的.bin和.ftz只保留先出现的一个
+        if models.iter().any(|(existing, _)| *existing == name) {
+            log::warn!("Skipping {}: a model named {} was already found", path, name);
+            continue;
+        }
+        models.push((name, path));
+    }
+    Ok(models)
+}
+
+/// A model loaded at startup, with its name and the path it was loaded from
+type LoadedModel = (String, String, FastText);
+
+/// Load `(name, path)` pairs in order, handling failures according to `policy`.
+/// Returns the loaded models and the ones that failed, or the first failure under `abort`
+fn load_models(
+    specs: &[(String, String)],
+    policy: OnModelLoadError,
+) -> Result<(Vec<LoadedModel>, Vec<FailedModel>), String> {
+    let mut models = Vec::with_capacity(specs.len());
+    let mut failed = Vec::new();
+    for (name, path) in specs {
+        log::info!("Loading FastText model {} from: {}", name, path);
+        match remote::load_model(path) {
+            Ok(model) => models.push((name.clone(), path.clone(), model)),
+            Err(e) => {
+                let msg = format!("Failed to load model {} from {}: {}", name, path, e);
+                match policy {
+                    OnModelLoadError::Abort => return Err(msg),
+                    OnModelLoadError::Skip => log::info!("{}, skipped", msg),
+                    OnModelLoadError::Warn => log::warn!("{}, skipped", msg),
+                }
+                failed.push(FailedModel {
+                    name: name.clone(),
+                    path: path.clone(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok((models, failed))
+}
+
+/// What the `check` subcommand found out about a model
+#[derive(Debug)]
+struct CheckReport {
+    dimension: usize,
+    label_count: usize,
+    labels: Vec<String>,
+    probs: Vec<f32>,
+}
+
+/// Load the model at `path` and exercise prediction and sentence vectors once, without serving it
+fn check_model(path: &str) -> Result<CheckReport, String> {
+    let model = remote::load_model(path)?;
+    let dimension = model.get_dimension() as usize;
+    let label_count = model.get_labels().map(|(labels, _)| labels.len())?;
+    let text = WARMUP_TEXTS[1];
+    let (labels, probs) = predict_one_safe(&model, text, 1, 0.0, usize::MAX, None, false).map_err(|e| e.to_string())?;
+    let vector = sentence_vector_safe(&model, text, true).map_err(|e| e.to_string())?;
+    if vector.len() != dimension {
+        return Err(format!("sentence vector has {} values, expected {}", vector.len(), dimension));
+    }
+    Ok(CheckReport {
+        dimension,
+        label_count,
+        labels,
+        probs,
+    })
+}
+
+/// Run `check` on `path`, print the outcome and return the exit code
+fn run_check(path: &str) -> i32 {
+    match check_model(path) {
+        Ok(report) => {
+            println!("Model {} OK", path);
+            println!("Dimension: {}", report.dimension);
+            println!("Labels: {}", report.label_count);
+            println!("Sample prediction: {:?} {:?}", report.labels, report.probs);
+            0
+        }
+        Err(e) => {
+            eprintln!("Model {} failed the check: {}", path, e);
+            1
+        }
+    }
+}
+
+/// Lines between the progress messages of the `predict` subcommand
+const PROGRESS_INTERVAL: usize = 10_000;
+
+/// Predict every line of `input` with the model at `model_path`, writing one `[labels, scores]`
+/// NDJSON line per input line to `output` as `/batch-predict` does; returns the number of lines
+fn predict_file(model_path: &str, input: &str, output: &str, k: u32, threshold: f32) -> Result<usize, String> {
+    let (k, threshold) = (check_default_k(k)?, check_default_threshold(threshold)?);
+    let model = remote::load_model(model_path)?;
+    let config = ServerConfig::default();
+    let reader = fs::File::open(input).map_err(|e| format!("failed to open {}: {}", input, e))?;
+    let writer = fs::File::create(output).map_err(|e| format!("failed to create {}: {}", output, e))?;
+    let mut writer = io::BufWriter::new(writer);
+    let write_failed = |e: io::Error| format!("failed to write {}: {}", output, e);
+    let started = Instant::now();
+    let mut count = 0;
+    for (index, line) in io::BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| format!("failed to read line {} of {}: {}", index + 1, input, e))?;
+        let prediction = predict_one_safe(
+            &model,
+            &line,
+            k,
+            threshold,
+            config.limits.max_text_length,
+            config.label_prefix(),
+            config.reject_nan,
+        );
+        // 与服务默认的--on-error mark一致，失败的行写入错误标记，输出与输入逐行对应
+        let (labels, probs) = prediction.unwrap_or_else(|e| {
+            log::warn!("Prediction failed for line {}: {}", index + 1, e);
+            config.error_marker(&e)
+        });
+        serde_json::to_writer(&mut writer, &(labels, probs)).map_err(|e| write_failed(e.into()))?;
+        writer.write_all(b"\n").map_err(write_failed)?;
+        count += 1;
+        if count % PROGRESS_INTERVAL == 0 {
+            log::info!("Predicted {} lines in {}s", count, started.elapsed().as_secs());
+        }
+    }
+    writer.flush().map_err(write_failed)?;
+    log::info!("Predicted {} lines from {} into {} in {}ms", count, input, output, started.elapsed().as_millis());
+    Ok(count)
+}
+
+/// Run `predict` on the subcommand's arguments and return the exit code
+fn run_predict(args: &clap::ArgMatches) -> i32 {
+    let arg = |id: &str| args.get_one::<String>(id).map(String::as_str).expect("missing argument");
+    let k = *args.get_one::<u32>("k").expect("missing k");
+    let threshold = *args.get_one::<f32>("threshold").expect("missing threshold");
+    match predict_file(arg("model"), arg("input"), arg("output"), k, threshold) {
+        Ok(_) => 0,
+        Err(e) => {
+            log::error!("Offline prediction failed: {}", e);
+            1
         }
     }
 }
 
+/// `--workers 0` means one worker per CPU rather than letting the server pick its own default
+fn resolve_workers(workers: usize) -> usize {
+    if workers > 0 {
+        return workers;
+    }
+    let cpus = num_cpus::get();
+    log::info!("--workers 0 requested, using one worker per CPU: {}", cpus);
+    cpus
+}
+
+/// Make sure the requested transport was compiled in before doing any expensive startup work
+fn check_transport(grpc: bool, grpc_port: bool) -> Result<(), &'static str> {
+    if cfg!(not(any(feature = "http", feature = "grpc"))) {
+        return Err("No transport available: rebuild with the `http` and/or `grpc` feature enabled");
+    }
+    if grpc && cfg!(not(feature = "grpc")) {
+        return Err("gRPC support is not enabled!");
+    }
+    if !grpc && cfg!(not(feature = "http")) {
+        return Err("HTTP support is not enabled!");
+    }
+    // --grpc时--grpc-port会被忽略
+    if !grpc && grpc_port && cfg!(not(all(feature = "http", feature = "grpc"))) {
+        return Err("--grpc-port needs both HTTP and gRPC support enabled!");
+    }
+    Ok(())
+}
+
 fn main() {
     if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "fasttext_serving=info");
     }
-    pretty_env_logger::init();
 
     let num_threads = num_cpus::get().to_string();
     let matches = Command::new("fasttext-serving")
@@ -187,12 +1072,19 @@ fn main() {
         .author("Messense Lv <messense@icloud.com>")
         .arg(
             Arg::new("model")
-                .required(true)
                 .short('m')
                 .long("model")
                 .value_name("model")
                 .num_args(1)
-                .help("Model path"),
+                .action(ArgAction::Append)
+                .help("Model path, http(s):// URL or s3://bucket/key; name=path serves several models (repeatable)"),
+        )
+        .arg(
+            Arg::new("model-dir")
+                .long("model-dir")
+                .value_name("dir")
+                .num_args(1)
+                .help("Also serve every *.bin/*.ftz in this directory, named by file stem"),
         )
         .arg(
             Arg::new("address")
@@ -200,7 +1092,8 @@ fn main() {
                 .long("address")
                 .default_value("127.0.0.1")
                 .num_args(1)
-                .help("Listen address"),
+                .action(ArgAction::Append)
+                .help("Listen address, e.g. 0.0.0.0, [::] or unix:/path; repeat or comma-separate to bind several"),
         )
         .arg(
             Arg::new("port")
@@ -218,7 +1111,7 @@ fn main() {
                 .alias("threads")
                 .default_value(&num_threads)
                 .num_args(1)
-                .help("Worker thread count, defaults to CPU count"),
+                .help("Worker thread count, defaults to CPU count; 0 also means CPU count"),
         )
         .arg(
             Arg::new("grpc")
@@ -227,19 +1120,12 @@ fn main() {
                 .help("Serving gRPC API instead of HTTP API"),
         )
         .arg(
-            Arg::new("max-request-size")
-                .long("max-request-size")
-                .default_value("500")
+            Arg::new("grpc-port")
+                .long("grpc-port")
                 .num_args(1)
-                .help("Maximum request size in MB (default: 500MB)"),
-        )
-        .arg(
-            Arg::new("max-text-length")
-                .long("max-text-length")
-                .default_value("5000000")
-                .num_args(1)
-                .help("Maximum text length in bytes (default: 5MB)"),
+                .help("Also serve the gRPC API on this port, next to the HTTP API on --port"),
         )
+        .args(Limits::args())
         .arg(
             Arg::new("default-threshold")
                 .long("default-threshold")
@@ -247,6 +1133,20 @@ fn main() {
                 .num_args(1)
                 .help("Default prediction threshold (default: 0.0)"),
         )
+        .arg(
+            Arg::new("default-k")
+                .long("default-k")
+                .default_value("1")
+                .num_args(1)
+                .help("Default number of labels to return (default: 1)"),
+        )
+        .arg(
+            Arg::new("uncertainty-threshold")
+                .long("uncertainty-threshold")
+                .default_value("0.8")
+                .num_args(1)
+                .help("Normalized entropy (0-1) above which predictions are flagged uncertain (default: 0.8)"),
+        )
         .arg(
             Arg::new("default-vector-dim")
                 .long("default-vector-dim")
@@ -254,93 +1154,788 @@ fn main() {
                 .num_args(1)
                 .help("Default sentence vector dimension for errors (default: 100)"),
         )
+        .arg(
+            Arg::new("tcp-nodelay")
+                .long("tcp-nodelay")
+                .value_parser(clap::value_parser!(bool))
+                .default_value("true")
+                .help("Set TCP_NODELAY on accepted connections to avoid Nagle delays on small responses"),
+        )
+        .arg(
+            Arg::new("backlog")
+                .long("backlog")
+                .default_value("1024")
+                .num_args(1)
+                .help("Pending connections each TCP listener queues (default: 1024)"),
+        )
+        .arg(
+            Arg::new("otlp-endpoint")
+                .long("otlp-endpoint")
+                .num_args(1)
+                .help("Export prediction spans to this OTLP gRPC collector, e.g. http://localhost:4317"),
+        )
+        .arg(
+            Arg::new("worker-stack-size")
+                .long("worker-stack-size")
+                .num_args(1)
+                .help("Stack size in bytes for worker and blocking threads"),
+        )
+        .arg(
+            Arg::new("blocking-threads")
+                .long("blocking-threads")
+                .num_args(1)
+                .help("Maximum blocking threads per runtime, used by batch prediction and vectors"),
+        )
+        .arg(
+            Arg::new("predict-threads")
+                .long("predict-threads")
+                .num_args(1)
+                .help("Threads predicting large batches, independent of --workers (HTTP only, default: one per CPU)"),
+        )
+        .arg(
+            Arg::new("cache-size")
+                .long("cache-size")
+                .num_args(1)
+                .help("Cache this many recent (text, k, threshold) predictions, cleared on reload (default: off)"),
+        )
+        .arg(
+            Arg::new("shadow-model")
+                .long("shadow-model")
+                .num_args(1)
+                .help("Also predict each /predict batch with this model and count top-label disagreements"),
+        )
+        .arg(
+            Arg::new("lang-detect-model")
+                .long("lang-detect-model")
+                .num_args(1)
+                .help("Language identifier like lid.176.bin, routing /predict texts to the model named by language"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(ArgAction::SetTrue)
+                .help("Refuse to start when --default-vector-dim doesn't match the model"),
+        )
+        .arg(
+            Arg::new("warmup")
+                .long("warmup")
+                .value_parser(clap::value_parser!(bool))
+                .default_value("true")
+                .help("Run a few predictions on each model before accepting traffic"),
+        )
+        .arg(
+            Arg::new("ready-after-prediction")
+                .long("ready-after-prediction")
+                .action(ArgAction::SetTrue)
+                .help("Stay not-ready until the model has answered a prediction or the warmup, not just loaded"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_parser(["pretty", "json"])
+                .default_value("pretty")
+                .help("Log line format, json emits one object per line"),
+        )
+        .arg(
+            Arg::new("compression")
+                .long("compression")
+                .action(ArgAction::SetTrue)
+                .help("Compress HTTP responses with gzip or brotli when the client accepts it"),
+        )
+        .arg(
+            Arg::new("cors-allow-origin")
+                .long("cors-allow-origin")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .help("Origin allowed to call the HTTP API from a browser, or `*` for any (repeatable)"),
+        )
+        .arg(
+            Arg::new("cors-allow-headers")
+                .long("cors-allow-headers")
+                .num_args(1)
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Extra request headers browsers may send cross-origin, e.g. x-request-id (repeatable)"),
+        )
+        .arg(
+            Arg::new("cors-max-age")
+                .long("cors-max-age")
+                .num_args(1)
+                .help("Seconds browsers may cache a CORS preflight answer"),
+        )
+        .arg(
+            Arg::new("replace-chars")
+                .long("replace-chars")
+                .num_args(1)
+                .help("Characters replaced with a space before prediction, e.g. \",.!?\""),
+        )
+        .arg(
+            Arg::new("normalize-lowercase")
+                .long("normalize-lowercase")
+                .action(ArgAction::SetTrue)
+                .help("Lowercase texts before prediction"),
+        )
+        .arg(
+            Arg::new("normalize-strip-punct")
+                .long("normalize-strip-punct")
+                .action(ArgAction::SetTrue)
+                .help("Replace ASCII punctuation with spaces before prediction"),
+        )
+        .arg(
+            Arg::new("split-lines")
+                .long("split-lines")
+                .action(ArgAction::SetTrue)
+                .help("Predict each line of a multi-line text separately like the fastText CLI (HTTP only)"),
+        )
+        .arg(
+            Arg::new("on-error")
+                .long("on-error")
+                .value_parser(["skip", "mark", "fail"])
+                .default_value("mark")
+                .help("Failed batch items are dropped, returned as marker labels, or reject the batch with 400"),
+        )
+        .arg(
+            Arg::new("on-model-load-error")
+                .long("on-model-load-error")
+                .value_parser(["abort", "skip", "warn"])
+                .default_value("warn")
+                .help("A model failing to load at startup stops the server, or is left out quietly or with a warning"),
+        )
+        .arg(
+            Arg::new("reject-nan")
+                .long("reject-nan")
+                .action(ArgAction::SetTrue)
+                .help("Fail texts whose scores or vectors come out NaN/infinite instead of zeroing those values"),
+        )
+        .arg(
+            Arg::new("field-separator")
+                .long("field-separator")
+                .default_value(" ")
+                .num_args(1)
+                .help("Separator used to join multi-field inputs like {\"fields\": [title, body]}"),
+        )
+        .arg(
+            Arg::new("label-prefix")
+                .long("label-prefix")
+                .default_value(DEFAULT_LABEL_PREFIX)
+                .num_args(1)
+                .help("Prefix stripped from predicted labels"),
+        )
+        .arg(
+            Arg::new("label-filter")
+                .long("label-filter")
+                .value_delimiter(',')
+                .num_args(1)
+                .help("Comma-separated labels to keep in /predict results, e.g. spam,promo"),
+        )
+        .arg(
+            Arg::new("label-separator")
+                .long("label-separator")
+                .default_value("/")
+                .num_args(1)
+                .help("Separator of hierarchical label paths, e.g. food/dessert/cake, grouped by ?group_depth="),
+        )
+        .arg(
+            Arg::new("label-thresholds")
+                .long("label-thresholds")
+                .num_args(1)
+                .help("JSON file mapping labels to their own score threshold"),
+        )
+        .arg(
+            Arg::new("keep-label-prefix")
+                .long("keep-label-prefix")
+                .action(ArgAction::SetTrue)
+                .help("Return labels with their prefix, as stored in the model"),
+        )
+        .arg(
+            Arg::new("marker-message")
+                .long("marker-message")
+                .action(ArgAction::SetTrue)
+                .help("Append the error message to the error/timeout label of a failed prediction"),
+        )
+        .arg(
+            Arg::new("per-label-metrics")
+                .long("per-label-metrics")
+                .action(ArgAction::SetTrue)
+                .help("Export a top-1 prediction counter per label; avoid on models with very many labels"),
+        )
+        .arg(
+            Arg::new("ensemble-weight")
+                .long("ensemble-weight")
+                .value_name("name=weight")
+                .action(ArgAction::Append)
+                .help("Weight of a named model in /ensemble predictions, repeatable"),
+        )
+        .arg(
+            Arg::new("vector-cache")
+                .long("vector-cache")
+                .num_args(1)
+                .help("Precomputed `id v1 v2 ...` vector file served from /cached-vector/{id}"),
+        )
+        .arg(
+            Arg::new("tls-cert")
+                .long("tls-cert")
+                .num_args(1)
+                .help("PEM certificate chain for serving HTTPS or gRPC over TLS, requires --tls-key"),
+        )
+        .arg(
+            Arg::new("tls-key")
+                .long("tls-key")
+                .num_args(1)
+                .help("PEM private key for serving HTTPS or gRPC over TLS, requires --tls-cert"),
+        )
+        .arg(
+            Arg::new("api-key")
+                .long("api-key")
+                .env("FASTTEXT_API_KEY")
+                .num_args(1)
+                .hide_env_values(true)
+                .help("Require `Authorization: Bearer <key>` or `X-API-Key: <key>` on all routes but /health"),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .num_args(1)
+                .help("TOML file with listen address, models, limits and thresholds; flags override it"),
+        )
+        .arg(
+            Arg::new("model-config")
+                .long("model-config")
+                .num_args(1)
+                .help("JSON file with per-model overrides of the default settings"),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Load a model, run a sample prediction and sentence vector, then exit 0 or 1")
+                .arg(
+                    Arg::new("model")
+                        .short('m')
+                        .long("model")
+                        .required(true)
+                        .num_args(1)
+                        .help("Model path, http(s):// URL or s3://bucket/key"),
+                ),
+        )
+        .subcommand(
+            Command::new("predict")
+                .about("Predict each line of a text file into an NDJSON file without starting a server")
+                .arg(
+                    Arg::new("model")
+                        .short('m')
+                        .long("model")
+                        .required(true)
+                        .num_args(1)
+                        .help("Model path, http(s):// URL or s3://bucket/key"),
+                )
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .required(true)
+                        .num_args(1)
+                        .help("Text file with one text per line"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .required(true)
+                        .num_args(1)
+                        .help("NDJSON file receiving one [labels, scores] line per input line"),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .long("k")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("1")
+                        .help("Number of labels per line"),
+                )
+                .arg(
+                    Arg::new("threshold")
+                        .short('t')
+                        .long("threshold")
+                        .value_parser(clap::value_parser!(f32))
+                        .default_value("0.0")
+                        .help("Minimum label probability"),
+                ),
+        )
         .get_matches();
+    logging::init(matches.get_one::<String>("log-format").expect("missing log-format"));
+    match matches.subcommand() {
+        Some(("check", check)) => {
+            std::process::exit(run_check(check.get_one::<String>("model").expect("missing model")));
+        }
+        Some(("predict", predict)) => std::process::exit(run_predict(predict)),
+        _ => {}
+    }
         
-    let model_path = matches.get_one::<String>("model").unwrap();
-    if !Path::new(model_path).exists() {
-        log::error!("Model file does not exist: {}", model_path);
+    if let Err(msg) = check_transport(matches.get_flag("grpc"), matches.contains_id("grpc-port")) {
+        log::error!("{}", msg);
         std::process::exit(1);
     }
     
-    let address = matches
-        .get_one::<String>("address")
-        .expect("missing address");
+    let file_config = match matches.get_one::<String>("config") {
+        Some(path) => config::Config::load(path).unwrap_or_else(|e| {
+            log::error!("Invalid config file: {}", e);
+            std::process::exit(1);
+        }),
+        None => config::Config::default(),
+    };
+    // 命令行和环境变量中给出的参数优先于配置文件
+    let explicit = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+        )
+    };
+    
+    let model_args: Vec<String> = match matches.get_many::<String>("model") {
+        Some(models) => models.cloned().collect(),
+        None => file_config.models.clone(),
+    };
+    let model_dir = matches.get_one::<String>("model-dir");
+    if model_args.is_empty() && model_dir.is_none() {
+        log::error!("No model given, pass --model, --model-dir or list models in the config file");
+        std::process::exit(1);
+    }
+    let mut model_specs = resolve_model_specs(&model_args).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
+    // 单模型路由使用名为default的模型，没有的话使用第一个
+    if let Some(index) = model_specs.iter().position(|(name, _)| name == DEFAULT_MODEL) {
+        let default = model_specs.remove(index);
+        model_specs.insert(0, default);
+    }
+    let mut discovered = match model_dir {
+        Some(dir) => discover_models(Path::new(dir)).unwrap_or_else(|e| {
+            log::error!("Failed to read model directory {}: {}", dir, e);
+            std::process::exit(1);
+        }),
+        None => Vec::new(),
+    };
+    discovered.retain(|(name, path)| {
+        let taken = model_specs.iter().any(|(existing, _)| existing == name);
+        if taken {
+            log::warn!("Skipping {}: --model already registers a model named {}", path, name);
+        }
+        !taken
+    });
+    if model_specs.is_empty() && discovered.is_empty() {
+        log::error!("No model found in {}", model_dir.map(String::as_str).unwrap_or_default());
+        std::process::exit(1);
+    }
+    model_specs.extend(discovered);
+    
+    // 多个地址统一拼成逗号分隔的列表
+    let address = match &file_config.address {
+        Some(address) if !explicit("address") => address.clone(),
+        _ => matches
+            .get_many::<String>("address")
+            .expect("missing address")
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(","),
+    };
     let port = matches.get_one::<String>("port").expect("missing port");
     let workers = matches
         .get_one::<String>("workers")
         .expect("missing workers");
-    let max_request_size = matches
-        .get_one::<String>("max-request-size")
-        .expect("missing max-request-size");
-    let max_text_length = matches
-        .get_one::<String>("max-text-length")
-        .expect("missing max-text-length");
     let default_threshold = matches
         .get_one::<String>("default-threshold")
         .expect("missing default-threshold");
+    let default_k = matches
+        .get_one::<String>("default-k")
+        .expect("missing default-k");
     let default_vector_dim = matches
         .get_one::<String>("default-vector-dim")
         .expect("missing default-vector-dim");
         
-    log::info!("Using FastText model from: {}", model_path);
-    // 验证模型文件存在（实际加载在Python中进行）
-    if !Path::new(model_path).exists() {
-        log::error!("Model file does not exist: {}", model_path);
-        std::process::exit(1);
-    }
-    log::info!("Model path verified, will use Python FastText v0.9.2");
+    let port: u16 = match file_config.port {
+        Some(port) if !explicit("port") => port,
+        _ => port.parse().unwrap_or_else(|_| {
+            log::error!("Invalid port number: {}", port);
+            std::process::exit(1);
+        }),
+    };
     
-    let port: u16 = port.parse().unwrap_or_else(|_| {
-        log::error!("Invalid port number: {}", port);
+    let workers: usize = match file_config.workers {
+        Some(workers) if !explicit("workers") => workers,
+        _ => workers.parse().unwrap_or_else(|_| {
+            log::error!("Invalid worker count: {}", workers);
+            std::process::exit(1);
+        }),
+    };
+    let workers = resolve_workers(workers);
+    let limits = Limits::from_matches(&matches).unwrap_or_else(|e| {
+        log::error!("{}", e);
         std::process::exit(1);
     });
     
-    let workers: usize = workers.parse().unwrap_or_else(|_| {
-        log::error!("Invalid worker count: {}", workers);
+    let _default_threshold: f32 = match default_threshold.parse().map(check_default_threshold) {
+        Ok(Ok(threshold)) => threshold,
+        _ => {
+            log::error!("Invalid default threshold: {}", default_threshold);
+            std::process::exit(1);
+        }
+    };
+    let _default_k: u32 = match default_k.parse().map(check_default_k) {
+        Ok(Ok(k)) => k,
+        _ => {
+            log::error!("Invalid default k: {}", default_k);
+            std::process::exit(1);
+        }
+    };
+    let uncertainty_threshold = matches
+        .get_one::<String>("uncertainty-threshold")
+        .expect("missing uncertainty-threshold");
+    let uncertainty_threshold: f32 = match uncertainty_threshold.parse::<f32>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => value,
+        _ => {
+            log::error!("Invalid uncertainty threshold: {}", uncertainty_threshold);
+            std::process::exit(1);
+        }
+    };
+    let _default_vector_dim: usize = default_vector_dim.parse().unwrap_or_else(|_| {
+        log::error!("Invalid default vector dim: {}", default_vector_dim);
         std::process::exit(1);
     });
     
-    let _max_request_size_mb: u32 = max_request_size.parse().unwrap_or_else(|_| {
-        log::error!("Invalid max request size: {}", max_request_size);
-        std::process::exit(1);
+    let backlog = matches.get_one::<String>("backlog").expect("missing backlog");
+    let backlog: u32 = match backlog.parse::<u32>() {
+        Ok(backlog) if backlog > 0 && backlog <= i32::MAX as u32 => backlog,
+        _ => {
+            log::error!("Invalid backlog: {}", backlog);
+            std::process::exit(1);
+        }
+    };
+    let worker_stack_size = matches.get_one::<String>("worker-stack-size").map(|value| {
+        match value.parse::<usize>() {
+            Ok(size) if size > 0 => size,
+            _ => {
+                log::error!("Invalid worker stack size: {}", value);
+                std::process::exit(1);
+            }
+        }
     });
-    let _max_text_length_bytes: usize = max_text_length.parse().unwrap_or_else(|_| {
-        log::error!("Invalid max text length: {}", max_text_length);
-        std::process::exit(1);
+    let blocking_threads = matches.get_one::<String>("blocking-threads").map(|value| {
+        match value.parse::<usize>() {
+            Ok(threads) if threads > 0 => threads,
+            _ => {
+                log::error!("Invalid blocking thread count: {}", value);
+                std::process::exit(1);
+            }
+        }
     });
-    let _default_threshold: f32 = default_threshold.parse().unwrap_or_else(|_| {
-        log::error!("Invalid default threshold: {}", default_threshold);
-        std::process::exit(1);
+    let predict_threads = matches.get_one::<String>("predict-threads").map(|value| {
+        match value.parse::<usize>() {
+            Ok(threads) if threads > 0 => threads,
+            _ => {
+                log::error!("Invalid predict thread count: {}", value);
+                std::process::exit(1);
+            }
+        }
     });
-    let _default_vector_dim: usize = default_vector_dim.parse().unwrap_or_else(|_| {
-        log::error!("Invalid default vector dim: {}", default_vector_dim);
+    if let Some(size) = worker_stack_size {
+        // actix的worker线程和rayon线程池不能单独设置栈大小，在创建线程前通过RUST_MIN_STACK统一设置
+        std::env::set_var("RUST_MIN_STACK", size.to_string());
+    }
+    let cors_allow_headers: Vec<String> = matches
+        .get_many::<String>("cors-allow-headers")
+        .into_iter()
+        .flatten()
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    if let Some(name) = cors_allow_headers.iter().find(|name| !is_header_name(name)) {
+        log::error!("Invalid CORS header name: {:?}", name);
         std::process::exit(1);
+    }
+    let cors_max_age_secs = matches.get_one::<String>("cors-max-age").map(|value| match value.parse::<usize>() {
+        Ok(secs) => secs,
+        Err(_) => {
+            log::error!("Invalid CORS max age: {}", value);
+            std::process::exit(1);
+        }
     });
+    let cache_size = matches.get_one::<String>("cache-size").map(|value| match value.parse::<usize>() {
+        Ok(size) if size > 0 => size,
+        _ => {
+            log::error!("Invalid cache size: {}", value);
+            std::process::exit(1);
+        }
+    });
+    let mut ensemble_weights = HashMap::new();
+    for spec in matches.get_many::<String>("ensemble-weight").into_iter().flatten() {
+        let weight = spec
+            .split_once('=')
+            .and_then(|(name, weight)| Some((name.to_string(), weight.parse::<f32>().ok()?)))
+            .filter(|(_, weight)| weight.is_finite() && *weight > 0.0);
+        match weight {
+            Some((name, weight)) => {
+                ensemble_weights.insert(name, weight);
+            }
+            None => {
+                log::error!("Invalid ensemble weight: {}", spec);
+                std::process::exit(1);
+            }
+        }
+    }
+    let tls_cert = matches.get_one::<String>("tls-cert").cloned();
+    let tls_key = matches.get_one::<String>("tls-key").cloned();
+    if tls_cert.is_some() != tls_key.is_some() {
+        log::error!("--tls-cert and --tls-key must be given together");
+        std::process::exit(1);
+    }
+    let model_overrides = match matches.get_one::<String>("model-config") {
+        Some(path) => load_model_overrides(path).unwrap_or_else(|e| {
+            log::error!("Invalid model config: {}", e);
+            std::process::exit(1);
+        }),
+        None => HashMap::new(),
+    };
+    let label_thresholds = match matches.get_one::<String>("label-thresholds") {
+        Some(path) => load_label_thresholds(path).unwrap_or_else(|e| {
+            log::error!("Invalid label thresholds: {}", e);
+            std::process::exit(1);
+        }),
+        None => HashMap::new(),
+    };
     
-    log::info!("Starting server with {} workers on {}:{}", workers, address, port);
-    log::info!("Maximum request size: {}MB", _max_request_size_mb);
-    log::info!("Maximum text length: {} bytes", _max_text_length_bytes);
-    log::info!("Default threshold: {}", _default_threshold);
-    log::info!("Default vector dimension: {}", _default_vector_dim);
-    
-    let config = ServerConfig {
-        max_text_length: _max_text_length_bytes,
+    let mut config = ServerConfig {
+        model_path: model_specs[0].1.clone(),
+        limits,
         default_threshold: _default_threshold,
+        default_k: _default_k,
+        uncertainty_threshold,
         default_vector_dim: _default_vector_dim,
-        max_request_size_mb: _max_request_size_mb,
+        // 加载模型后再检测
+        tcp_nodelay: *matches.get_one::<bool>("tcp-nodelay").expect("missing tcp-nodelay"),
+        backlog,
+        worker_stack_size,
+        blocking_threads,
+        predict_threads,
+        #[cfg(feature = "http")]
+        predict_pool: predict_threads.map(|threads| Arc::new(http::predict_pool(threads))),
+        #[cfg(feature = "http")]
+        prediction_cache: cache_size.map(|size| std::sync::Arc::new(prediction_cache::PredictionCache::new(size))),
+        #[cfg(feature = "http")]
+        shadow_model: None,
+        compression: matches.get_flag("compression"),
+        cors_allow_origins: matches
+            .get_many::<String>("cors-allow-origin")
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+        cors_allow_headers,
+        cors_max_age_secs,
+        vector_cache_path: matches.get_one::<String>("vector-cache").cloned(),
+        lang_detect_model: matches.get_one::<String>("lang-detect-model").cloned(),
+        #[cfg(feature = "http")]
+        language_router: None,
+        tls_cert,
+        tls_key,
+        otlp_endpoint: matches.get_one::<String>("otlp-endpoint").cloned(),
+        api_key: matches.get_one::<String>("api-key").filter(|key| !key.is_empty()).cloned(),
+        replace_chars: matches
+            .get_one::<String>("replace-chars")
+            .map(|chars| chars.chars().collect())
+            .unwrap_or_default(),
+        normalize_lowercase: matches.get_flag("normalize-lowercase"),
+        normalize_strip_punct: matches.get_flag("normalize-strip-punct"),
+        reject_nan: matches.get_flag("reject-nan"),
+        split_lines: matches.get_flag("split-lines"),
+        on_error: matches
+            .get_one::<String>("on-error")
+            .expect("missing on-error")
+            .parse()
+            .expect("validated by clap"),
+        on_model_load_error: matches
+            .get_one::<String>("on-model-load-error")
+            .expect("missing on-model-load-error")
+            .parse()
+            .expect("validated by clap"),
+        loaded_models: Vec::new(),
+        failed_models: Vec::new(),
+        field_separator: matches
+            .get_one::<String>("field-separator")
+            .expect("missing field-separator")
+            .to_string(),
+        label_prefix: matches
+            .get_one::<String>("label-prefix")
+            .expect("missing label-prefix")
+            .to_string(),
+        keep_label_prefix: matches.get_flag("keep-label-prefix"),
+        marker_message: matches.get_flag("marker-message"),
+        per_label_metrics: matches.get_flag("per-label-metrics"),
+        label_filter: matches
+            .get_many::<String>("label-filter")
+            .map(|labels| labels.cloned().collect())
+            .unwrap_or_default(),
+        label_separator: matches
+            .get_one::<String>("label-separator")
+            .expect("missing label-separator")
+            .to_string(),
+        label_thresholds,
+        warmup: *matches.get_one::<bool>("warmup").expect("missing warmup"),
+        ready_after_prediction: matches.get_flag("ready-after-prediction"),
+        #[cfg(feature = "http")]
+        first_prediction: None,
+        ensemble_weights,
+        model_overrides,
     };
+    file_config.apply(&mut config, explicit);
+    
+    log::info!("Starting server with {} workers on {}:{}", workers, address, port);
+    log::info!("Maximum request size: {}MB", config.limits.max_request_size_mb);
+    log::info!("Maximum batch size: {}", config.limits.max_batch_size);
+    log::info!("Maximum text length: {} bytes", config.limits.max_text_length);
+    log::info!("Default threshold: {}", config.default_threshold);
+    log::info!("Default k: {}", config.default_k);
+    log::info!("Maximum k: {}", config.limits.max_k);
+    if let Some(size) = cache_size {
+        log::info!("Prediction cache: {} entries", size);
+    }
+    log::info!("Default vector dimension: {}", config.default_vector_dim);
+    log::info!("Shutdown timeout: {}s", config.limits.shutdown_timeout_secs);
+    let limits = &config.limits;
+    log::info!(
+        "Keep-alive: {}s, client request timeout: {}ms, client disconnect timeout: {}ms",
+        limits.keep_alive_secs, limits.client_request_timeout_ms, limits.client_disconnect_timeout_ms
+    );
+    log::info!("TCP_NODELAY: {}, listen backlog: {}", config.tcp_nodelay, config.backlog);
+    if let Some(size) = config.worker_stack_size {
+        log::info!("Worker stack size: {} bytes", size);
+    }
+    if let Some(threads) = config.blocking_threads {
+        log::info!("Blocking threads: {}", threads);
+    }
+    if let Some(threads) = config.predict_threads {
+        log::info!("Predict threads: {}", threads);
+    }
+    for (name, overrides) in &config.model_overrides {
+        log::info!("Overrides for model {}: {:?}", name, overrides);
+    }
+
+    let (loaded, failed) = load_models(&model_specs, config.on_model_load_error).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
+    if loaded.is_empty() {
+        log::error!("None of the {} models could be loaded", model_specs.len());
+        std::process::exit(1);
+    }
+    log::info!(
+        "Loaded {} of {} models, failed: {:?}",
+        loaded.len(),
+        model_specs.len(),
+        failed.iter().map(|model| model.name.as_str()).collect::<Vec<_>>()
+    );
+    // 默认模型加载失败时由第一个加载成功的模型代替
+    config.model_path = loaded[0].1.clone();
+    config.loaded_models = loaded.iter().map(|(name, _, _)| name.clone()).collect();
+    config.failed_models = failed;
+    let models: Vec<(String, ServedModel)> =
+        loaded.into_iter().map(|(name, _, model)| (name, ServedModel::new(model))).collect();
+    if config.ready_after_prediction {
+        #[cfg(feature = "http")]
+        {
+            config.first_prediction = Some(Arc::new(http::FirstPrediction::default()));
+        }
+        #[cfg(not(feature = "http"))]
+        log::warn!("--ready-after-prediction only gates HTTP readiness, ignored for gRPC");
+    }
+    for (name, model) in &models {
+        log::info!("Model {} loaded, vector dimension: {}", name, model.dimension);
+        if config.warmup {
+            match warm_up(model) {
+                Ok(elapsed) => {
+                    log::info!("Model {} warmed up in {}ms", name, elapsed.as_millis());
+                    // 第一个模型服务不带模型名的路由，它预热成功就算作一次成功预测
+                    #[cfg(feature = "http")]
+                    if let (true, Some(first)) = (name == &models[0].0, &config.first_prediction) {
+                        first.record();
+                    }
+                }
+                Err(e) => log::warn!("Warmup of model {} failed: {}", name, e),
+            }
+        }
+    }
+    if let Some(path) = matches.get_one::<String>("shadow-model") {
+        #[cfg(feature = "http")]
+        {
+            log::info!("Loading shadow model from: {}", path);
+            let shadow = remote::load_model(path).unwrap_or_else(|e| {
+                log::error!("Failed to load shadow model {}: {}", path, e);
+                std::process::exit(1);
+            });
+            config.shadow_model = Some(std::sync::Arc::new(shadow::ShadowModel::new(path.clone(), shadow)));
+        }
+        #[cfg(not(feature = "http"))]
+        log::warn!("--shadow-model {} needs the HTTP API, ignored", path);
+    }
+    if explicit("default-vector-dim") {
+        if let Some(msg) = config.vector_dim_mismatch(models[0].1.dimension) {
+            if matches.get_flag("strict") {
+                log::error!("{}", msg);
+                std::process::exit(1);
+            }
+            log::warn!("{}", msg);
+        }
+    }
+
+    let grpc_port = matches.get_one::<String>("grpc-port").map(|grpc_port| {
+        match grpc_port.parse::<u16>() {
+            Ok(grpc_port) if grpc_port != port || address.starts_with("unix:") => grpc_port,
+            _ => {
+                log::error!("Invalid --grpc-port {}, it needs a port other than --port {}", grpc_port, port);
+                std::process::exit(1);
+            }
+        }
+    });
+    #[cfg(any(feature = "http", feature = "grpc"))]
+    let models: Vec<(String, Arc<ServedModel>)> =
+        models.into_iter().map(|(name, model)| (name, Arc::new(model))).collect();
 
     if matches.get_flag("grpc") {
         #[cfg(feature = "grpc")]
-        crate::grpc::runserver(model_path.to_string(), address, port, workers, config);
+        {
+            if grpc_port.is_some() {
+                log::warn!("--grpc-port is ignored with --grpc, serving gRPC on --port {}", port);
+            }
+            if models.len() > 1 {
+                log::warn!("gRPC serves a single model, only {} will be available", models[0].0);
+            }
+            if config.otlp_endpoint.is_some() {
+                log::warn!("--otlp-endpoint only traces the HTTP API, ignored for gRPC");
+            }
+            if matches.get_one::<String>("shadow-model").is_some() {
+                log::warn!("--shadow-model only mirrors the HTTP API, ignored for gRPC");
+            }
+            if config.lang_detect_model.is_some() {
+                log::warn!("--lang-detect-model only routes the HTTP API, ignored for gRPC");
+            }
+            if config.limits.max_memory_mb.is_some() {
+                log::warn!("--max-memory-mb only sheds the HTTP API, ignored for gRPC");
+            }
+            let (_, model) = models.into_iter().next().expect("no model loaded");
+            crate::grpc::runserver(model, &address, port, workers, config);
+        }
         #[cfg(not(feature = "grpc"))]
         {
             log::error!("gRPC support is not enabled!");
             std::process::exit(1);
         }
+    } else if let Some(grpc_port) = grpc_port {
+        #[cfg(all(feature = "http", feature = "grpc"))]
+        serve_both(models, &address, port, grpc_port, workers, config);
+        #[cfg(not(all(feature = "http", feature = "grpc")))]
+        unreachable!("check_transport rejects --grpc-port {} without both transports", grpc_port);
     } else {
         #[cfg(feature = "http")]
-        crate::http::runserver(model_path.to_string(), address, port, workers, config);
+        crate::http::runserver(models, &address, port, workers, config);
         #[cfg(not(feature = "http"))]
         {
             log::error!("HTTP support is not enabled!");
@@ -348,3 +1943,425 @@ fn main() {
         }
     }
 }
+
+/// `--grpc-port`: the HTTP API on `port` and the gRPC API on `grpc_port` in one process, each on
+/// its own runtime and sharing the loaded models. Both stop on the shutdown signal; returns once they have
+#[cfg(all(feature = "http", feature = "grpc"))]
+fn serve_both(
+    models: Vec<(String, Arc<ServedModel>)>,
+    address: &str,
+    port: u16,
+    grpc_port: u16,
+    workers: usize,
+    config: ServerConfig,
+) {
+    if models.len() > 1 {
+        log::info!("gRPC serves the default model {}", models[0].0);
+    }
+    // gRPC只监听一个地址，取第一个
+    let grpc_address = address.split(',').next().unwrap_or(address).to_string();
+    let (model, grpc_config) = (models[0].1.clone(), config.clone());
+    let grpc = std::thread::Builder::new()
+        .name("grpc-server".to_string())
+        .spawn(move || crate::grpc::runserver(model, &grpc_address, grpc_port, workers, grpc_config))
+        .expect("failed to spawn the gRPC server thread");
+    crate::http::runserver(models, address, port, workers, config);
+    if grpc.join().is_err() {
+        log::error!("gRPC server stopped with a panic");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        check_model, check_transport, discover_models, load_model_overrides, load_models, parse_model_spec,
+        predict_file, predict_one, predict_one_safe, prepare_input, resolve_model_specs, resolve_workers, run_check,
+        sanitize_scores, Limits, ModelOverrides, OnModelLoadError, PredictError, ServedModel, ServerConfig, DEFAULT_LABEL_PREFIX,
+        DEFAULT_MODEL,
+    };
+    use clap::Command;
+    use fasttext::FastText;
+
+    #[test]
+    fn test_limits_from_args() {
+        let command = Command::new("fasttext-serving").args(Limits::args());
+        let matches = command.clone().get_matches_from(["fasttext-serving"]);
+        assert_eq!(Limits::from_matches(&matches).unwrap(), Limits::default());
+
+        let matches = command.clone().get_matches_from([
+            "fasttext-serving",
+            "--max-text-length",
+            "4096",
+            "--max-k",
+            "5",
+            "--predict-timeout-ms",
+            "250",
+            "--rate-limit",
+            "2.5",
+            "--grpc-keepalive-interval",
+            "30",
+            "--max-total-predictions",
+            "50",
+        ]);
+        let limits = Limits::from_matches(&matches).unwrap();
+        assert_eq!(limits.max_text_length, 4096);
+        assert_eq!(limits.max_k, 5);
+        assert_eq!(limits.predict_timeout_ms, Some(250));
+        assert_eq!(limits.rate_limit, Some(2.5));
+        assert_eq!(limits.grpc_keepalive_interval_secs, Some(30));
+        assert_eq!(limits.max_total_predictions, Some(50));
+        assert_eq!(limits.max_batch_size, Limits::default().max_batch_size);
+
+        let matches = command.get_matches_from(["fasttext-serving", "--max-batch-size", "0"]);
+        assert_eq!(Limits::from_matches(&matches).unwrap_err(), "Invalid --max-batch-size: 0");
+    }
+
+    #[test]
+    fn test_for_model_overrides() {
+        let mut config = ServerConfig::default();
+        config.model_overrides.insert(
+            "spam".to_string(),
+            ModelOverrides {
+                default_threshold: Some(0.9),
+                ..Default::default()
+            },
+        );
+        config.model_overrides.insert(
+            "promo".to_string(),
+            ModelOverrides {
+                default_threshold: Some(0.3),
+                default_k: Some(3),
+                label_prefix: Some("__cat__".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let spam = config.for_model("spam");
+        assert_eq!(spam.default_threshold, 0.9);
+        assert_eq!(spam.default_k, 1);
+        let promo = config.for_model("promo");
+        assert_eq!(promo.default_threshold, 0.3);
+        assert_eq!(promo.default_k, 3);
+        assert_eq!(promo.label_prefix, Some("__cat__"));
+        let other = config.for_model("other");
+        assert_eq!(other.default_threshold, 0.0);
+        assert_eq!(other.max_text_length, config.limits.max_text_length);
+        assert_eq!(other.label_prefix, Some(DEFAULT_LABEL_PREFIX));
+    }
+
+    #[test]
+    fn test_model_overrides_validated() {
+        let path = std::env::temp_dir().join(format!("fasttext-model-config-{}.json", std::process::id()));
+        let load = |json: &str| {
+            std::fs::write(&path, json).unwrap();
+            load_model_overrides(path.to_str().unwrap())
+        };
+        let overrides = load(r#"{"spam": {"default_k": 2, "label_prefix": "__cat__"}}"#).unwrap();
+        assert_eq!(overrides["spam"].label_prefix.as_deref(), Some("__cat__"));
+        assert!(load(r#"{"spam": {"default_k": 0}}"#).unwrap_err().contains("default_k"));
+        assert!(load(r#"{"spam": {"default_threshold": 1.5}}"#).unwrap_err().contains("default_threshold"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn test_check_transport_http() {
+        assert!(check_transport(false, false).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "grpc")]
+    fn test_check_transport_grpc() {
+        assert!(check_transport(true, true).is_ok());
+    }
+
+    #[test]
+    #[cfg(all(feature = "http", not(feature = "grpc")))]
+    fn test_check_transport_grpc_port_needs_both() {
+        let err = check_transport(false, true).unwrap_err();
+        assert!(err.contains("--grpc-port"));
+        // --grpc优先于--grpc-port，报告的是缺少gRPC
+        assert_eq!(check_transport(true, true).unwrap_err(), "gRPC support is not enabled!");
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "http", feature = "grpc")))]
+    fn test_check_transport_none() {
+        let err = check_transport(false, false).unwrap_err();
+        assert!(err.contains("No transport available"));
+        assert_eq!(check_transport(true, false).unwrap_err(), err);
+        assert_eq!(check_transport(false, true).unwrap_err(), err);
+    }
+
+    #[test]
+    fn test_resolve_workers() {
+        assert_eq!(resolve_workers(3), 3);
+        // 0表示按CPU数，至少一个
+        assert_eq!(resolve_workers(0), num_cpus::get());
+        assert!(resolve_workers(0) >= 1);
+    }
+
+    #[test]
+    fn test_prepare_input_replace_chars() {
+        let config = ServerConfig {
+            replace_chars: vec![',', '!'],
+            ..Default::default()
+        };
+        assert_eq!(prepare_input("banana,bread!", &config), "banana bread ");
+        assert!(matches!(prepare_input("banana bread", &config), std::borrow::Cow::Borrowed(_)));
+        assert_eq!(prepare_input("banana,bread", &ServerConfig::default()), "banana,bread");
+    }
+
+    #[test]
+    fn test_prepare_input_normalization() {
+        let config = ServerConfig {
+            normalize_lowercase: true,
+            normalize_strip_punct: true,
+            ..Default::default()
+        };
+        assert_eq!(prepare_input("Banana, Bread!", &config), "banana  bread ");
+        assert!(matches!(prepare_input("banana bread", &config), std::borrow::Cow::Borrowed(_)));
+
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        let config = ServerConfig {
+            normalize_lowercase: true,
+            ..Default::default()
+        };
+        let predict = |text: &str| {
+            predict_one_safe(&model, &prepare_input(text, &config), 3, 0.0, usize::MAX, None, false).unwrap()
+        };
+        assert_eq!(predict("Banana"), predict("banana"));
+        assert_eq!(predict("How do I BAKE Banana Bread?"), predict("how do i bake banana bread?"));
+    }
+
+    #[test]
+    fn test_predict_one_safe_max_text_length() {
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        let text = "Which baking dish is best to bake a banana bread?";
+        assert!(predict_one_safe(&model, text, 1, 0.0, text.len(), None, false).is_ok());
+        match predict_one_safe(&model, text, 1, 0.0, text.len() - 1, None, false) {
+            Err(PredictError::InputError(msg)) => assert!(msg.contains("Text too long")),
+            other => panic!("expected input error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_vector_dim_mismatch() {
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        let dim = model.get_dimension() as usize;
+
+        let mut config = ServerConfig {
+            default_vector_dim: dim + 1,
+            ..Default::default()
+        };
+        let served = ServedModel::new(model);
+        let warning = config.vector_dim_mismatch(served.dimension).expect("mismatch not reported");
+        assert!(warning.contains(&dim.to_string()));
+
+        config.default_vector_dim = dim;
+        assert_eq!(config.vector_dim_mismatch(served.dimension), None);
+    }
+
+    #[test]
+    fn test_probed_dimension_matches_sentence_vector() {
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        let vector = model.get_sentence_vector("Which baking dish is best to bake a banana bread?").unwrap();
+        let served = ServedModel::new(model);
+        assert_eq!(served.dimension, vector.len());
+        assert!(!served.is_quantized);
+    }
+
+    #[test]
+    fn test_error_marker() {
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        // 兼容接口与默认配置的标记一致
+        let e = predict_one_safe(&model, "banana bread", 1, 0.0, 1, None, false).unwrap_err();
+        let marker = ServerConfig::default().error_marker(&e);
+        assert_eq!(predict_one(&model, "banana bread", 1, 0.0, 1), marker);
+        assert_eq!(marker, (vec!["error".to_string()], vec![0.0]));
+
+        let config = ServerConfig {
+            keep_label_prefix: true,
+            marker_message: true,
+            ..Default::default()
+        };
+        let (labels, _) = config.error_marker(&PredictError::Timeout(5));
+        assert_eq!(labels, vec!["__label__timeout: Prediction timed out after 5ms"]);
+    }
+
+    #[test]
+    fn test_label_prefix_stripping() {
+        let mut model = FastText::new();
+        model
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        let text = "Which baking dish is best to bake a banana bread?";
+        let predict = |prefix| predict_one_safe(&model, text, 3, 0.0, usize::MAX, prefix, false).unwrap().0;
+
+        let stripped = predict(Some("__label__"));
+        let raw = predict(None);
+        assert!(raw.iter().all(|label| label.starts_with("__label__")));
+        let custom = predict(Some("__label"));
+        for ((stripped, raw), custom) in stripped.iter().zip(&raw).zip(&custom) {
+            assert_eq!(*raw, format!("__label__{}", stripped));
+            assert_eq!(*custom, format!("__{}", stripped));
+        }
+        // 不带该前缀的标签保持原样
+        assert_eq!(predict(Some("__cat__")), raw);
+
+        let config = ServerConfig {
+            keep_label_prefix: true,
+            ..Default::default()
+        };
+        assert_eq!(config.label_prefix(), None);
+        assert_eq!(config.marker_label("error"), "__label__error");
+        assert_eq!(ServerConfig::default().marker_label("error"), "error");
+    }
+
+    #[test]
+    fn test_parse_model_spec() {
+        assert_eq!(
+            parse_model_spec("cooking=models/cooking.model.bin"),
+            ("cooking".to_string(), "models/cooking.model.bin".to_string())
+        );
+        assert_eq!(
+            parse_model_spec("models/cooking.model.bin"),
+            (DEFAULT_MODEL.to_string(), "models/cooking.model.bin".to_string())
+        );
+    }
+
+    #[test]
+    fn test_resolve_model_specs_remote() {
+        // 远程模型启动时不在本地，不能按文件是否存在来拒绝
+        let specs = vec![
+            "https://models.example.com/cooking.bin?X-Amz-Signature=abc".to_string(),
+            "spam=s3://bucket/spam.bin".to_string(),
+            "local=models/cooking.model.bin".to_string(),
+        ];
+        let resolved = resolve_model_specs(&specs).unwrap();
+        assert_eq!(resolved[0], (DEFAULT_MODEL.to_string(), specs[0].clone()));
+        assert_eq!(resolved[1], ("spam".to_string(), "s3://bucket/spam.bin".to_string()));
+
+        let missing = resolve_model_specs(&["models/missing.bin".to_string()]).unwrap_err();
+        assert!(missing.contains("does not exist"), "{}", missing);
+        let twice = vec!["a=models/cooking.model.bin".to_string(), "a=s3://bucket/a.bin".to_string()];
+        assert!(resolve_model_specs(&twice).unwrap_err().contains("registered twice"));
+    }
+
+    #[test]
+    fn test_sanitize_scores() {
+        let crafted = vec![f32::NAN, 0.5, f32::INFINITY, f32::NEG_INFINITY];
+
+        let mut scores = crafted.clone();
+        sanitize_scores(&mut scores, false).unwrap();
+        assert_eq!(scores, vec![0.0, 0.5, 0.0, 0.0]);
+        let json = serde_json::to_string(&(vec!["baking"; 4], &scores)).unwrap();
+        let parsed: (Vec<String>, Vec<f32>) = serde_json::from_str(&json).expect("output is not valid JSON");
+        assert_eq!(parsed.1, scores);
+
+        let mut scores = crafted;
+        match sanitize_scores(&mut scores, true) {
+            Err(PredictError::InputError(msg)) => assert!(msg.contains("position 0"), "{}", msg),
+            other => panic!("expected an input error, got {:?}", other),
+        }
+        let mut scores = vec![0.7, 0.3];
+        assert!(sanitize_scores(&mut scores, true).is_ok());
+        assert_eq!(scores, vec![0.7, 0.3]);
+    }
+
+
+    #[test]
+    fn test_check_subcommand() {
+        let report = check_model("models/cooking.model.bin").unwrap();
+        assert!(report.dimension > 0);
+        assert!(report.label_count > 0);
+        assert_eq!(report.labels.len(), 1);
+        assert_eq!(run_check("models/cooking.model.bin"), 0);
+
+        assert!(check_model("models/missing.bin").is_err());
+        assert_eq!(run_check("models/missing.bin"), 1);
+    }
+
+    #[test]
+    fn test_predict_subcommand() {
+        let dir = std::env::temp_dir().join(format!("fasttext-serving-predict-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("texts.txt"), dir.join("predictions.ndjson"));
+        let texts = ["Which baking dish is best to bake a banana bread?", "", "Why not put knives in the dishwasher?"];
+        std::fs::write(&input, texts.join("\n")).unwrap();
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+        assert_eq!(predict_file("models/cooking.model.bin", input, output, 2, 0.0), Ok(3));
+        let written = std::fs::read_to_string(output).unwrap();
+
+        let mut model = FastText::new();
+        model.load_model("models/cooking.model.bin").unwrap();
+        let lines: Vec<(Vec<String>, Vec<f32>)> =
+            written.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 3);
+        let expected = predict_one_safe(&model, texts[0], 2, 0.0, usize::MAX, Some(DEFAULT_LABEL_PREFIX), false);
+        assert_eq!(lines[0], expected.unwrap());
+        assert_eq!(lines[2].0.len(), 2);
+        // 空行预测失败，写入错误标记，后面的行仍然对齐
+        assert_eq!(lines[1], (vec!["error".to_string()], vec![0.0]));
+
+        assert!(predict_file("models/missing.bin", input, output, 2, 0.0).is_err());
+        assert!(predict_file("models/cooking.model.bin", input, output, 0, 0.0).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+
+    #[test]
+    fn test_discover_models() {
+        let dir = std::env::temp_dir().join(format!("fasttext-serving-models-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("models/cooking.model.bin", dir.join("alpha.bin")).unwrap();
+        std::fs::copy("models/cooking.model.bin", dir.join("beta.ftz")).unwrap();
+        // 其他扩展名的文件被跳过，能否加载到启动时才知道
+        std::fs::write(dir.join("broken.bin"), b"not a model").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"readme").unwrap();
+        let models = discover_models(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<&str> = models.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["alpha", "beta", "broken"]);
+        assert!(discover_models(&dir).is_err());
+    }
+
+    #[test]
+    fn test_load_models_on_error() {
+        let dir = std::env::temp_dir().join(format!("fasttext-serving-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let broken = dir.join("broken.bin");
+        std::fs::write(&broken, b"not a model").unwrap();
+        let specs = vec![
+            ("good".to_string(), "models/cooking.model.bin".to_string()),
+            ("broken".to_string(), broken.to_string_lossy().into_owned()),
+        ];
+        for policy in [OnModelLoadError::Skip, OnModelLoadError::Warn] {
+            let (loaded, failed) = load_models(&specs, policy).unwrap();
+            let names: Vec<&str> = loaded.iter().map(|(name, _, _)| name.as_str()).collect();
+            assert_eq!(names, vec!["good"]);
+            assert_eq!(failed.len(), 1);
+            assert_eq!(failed[0].name, "broken");
+            assert_eq!(failed[0].path, specs[1].1);
+            assert!(!failed[0].error.is_empty());
+        }
+        let err = load_models(&specs, OnModelLoadError::Abort).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.contains("broken"), "{}", err);
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+/// Prometheus collectors shared by all HTTP workers, labelled by endpoint
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub(crate) requests: IntCounterVec,
+    pub(crate) errors: IntCounterVec,
+    pub(crate) duration: HistogramVec,
+    /// Byte length of each text sent for prediction, before normalization
+    pub(crate) input_length: Histogram,
+    /// Requests currently inside a handler, copied into `in_flight_gauge` on render
+    in_flight: AtomicUsize,
+    in_flight_gauge: IntGauge,
+    /// Requests that have left the handler since startup
+    served: AtomicU64,
+    /// Texts predicted by both the primary and the `--shadow-model`, and those whose top labels differed
+    pub(crate) shadow_comparisons: IntCounter,
+    pub(crate) shadow_disagreements: IntCounter,
+    /// Texts whose top-1 prediction was each label, only counted under `--per-label-metrics`
+    pub(crate) label_predicted: IntCounterVec,
+}
+
+/// Counts one request as in flight until dropped
+pub(crate) struct InFlight(Arc<Metrics>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.0.served.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("fasttext_predict_requests_total", "Requests received per endpoint"),
+            &["endpoint"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new("fasttext_predict_errors_total", "Texts that failed per endpoint"),
+            &["endpoint"],
+        )
+        .unwrap();
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "fasttext_predict_duration_seconds",
+                "Time spent in the model per text",
+            )
+            .buckets(vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+            &["endpoint"],
+        )
+        .unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        let in_flight_gauge =
+            IntGauge::new("fasttext_in_flight_requests", "Requests currently being handled").unwrap();
+        registry.register(Box::new(duration.clone())).unwrap();
+        // 16B到1MB，每档乘4
+        let input_length = Histogram::with_opts(
+            HistogramOpts::new("fasttext_input_length_bytes", "Byte length of texts sent for prediction")
+                .buckets(exponential_buckets(16.0, 4.0, 9).unwrap()),
+        )
+        .unwrap();
+        registry.register(Box::new(input_length.clone())).unwrap();
+        registry.register(Box::new(in_flight_gauge.clone())).unwrap();
+        let shadow_comparisons = IntCounter::new(
+            "fasttext_shadow_comparisons_total",
+            "Texts predicted by both the primary and the shadow model",
+        )
+        .unwrap();
+        let shadow_disagreements = IntCounter::new(
+            "fasttext_shadow_disagreements_total",
+            "Texts whose top label differs between the primary and the shadow model",
+        )
+        .unwrap();
+        registry.register(Box::new(shadow_comparisons.clone())).unwrap();
+        registry.register(Box::new(shadow_disagreements.clone())).unwrap();
+        // 标签数量可能很大，只有开启--per-label-metrics时才会产生序列
+        let label_predicted = IntCounterVec::new(
+            Opts::new("fasttext_label_predicted_total", "Texts predicted with each label as their top label"),
+            &["label"],
+        )
+        .unwrap();
+        registry.register(Box::new(label_predicted.clone())).unwrap();
+        Metrics {
+            registry,
+            requests,
+            errors,
+            duration,
+            input_length,
+            in_flight: AtomicUsize::new(0),
+            in_flight_gauge,
+            served: AtomicU64::new(0),
+            shadow_comparisons,
+            shadow_disagreements,
+            label_predicted,
+        }
+    }
+
+    /// Mark a request as in flight for the lifetime of the returned guard
+    pub(crate) fn enter(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        InFlight(self.clone())
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn served(&self) -> u64 {
+        self.served.load(Ordering::Relaxed)
+    }
+
+    /// Render every registered collector in the Prometheus text exposition format
+    pub(crate) fn render(&self) -> String {
+        self.in_flight_gauge.set(self.in_flight() as i64);
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("failed to encode metrics");
+        String::from_utf8(buffer).expect("metrics are not valid UTF-8")
+    }
+}
@@ -0,0 +1,128 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex, Weak};
+
+use fasttext::FastText;
+
+/// Unit-length vectors of every word in a model's vocabulary, one row per word,
+/// so the cosine similarity of each word to a query is a single dot product
+#[derive(Debug)]
+pub(crate) struct WordMatrix {
+    words: Vec<String>,
+    dim: usize,
+    rows: Vec<f32>,
+}
+
+impl WordMatrix {
+    pub(crate) fn build(model: &FastText) -> Result<Self, String> {
+        let (words, _) = model.get_vocab()?;
+        let dim = model.get_dimension() as usize;
+        let mut rows = Vec::with_capacity(words.len() * dim);
+        for word in &words {
+            let mut vector = model.get_word_vector(word)?;
+            if vector.len() != dim {
+                return Err(format!("vector of {} has {} values, expected {}", word, vector.len(), dim));
+            }
+            normalize(&mut vector);
+            rows.extend_from_slice(&vector);
+        }
+        Ok(WordMatrix { words, dim, rows })
+    }
+
+    /// The `k` words most similar to `word`, excluding the word itself
+    pub(crate) fn nearest(&self, model: &FastText, word: &str, k: usize) -> Result<Vec<(f32, String)>, String> {
+        let query = unit_vector(model, word)?;
+        Ok(self.closest(&query, k, &[word]))
+    }
+
+    /// The `k` words closest to `a - b + c`, excluding the three words themselves
+    pub(crate) fn analogies(
+        &self,
+        model: &FastText,
+        (a, b, c): (&str, &str, &str),
+        k: usize,
+    ) -> Result<Vec<(f32, String)>, String> {
+        let mut query = unit_vector(model, a)?;
+        let (b_vector, c_vector) = (unit_vector(model, b)?, unit_vector(model, c)?);
+        for (value, (b, c)) in query.iter_mut().zip(b_vector.iter().zip(&c_vector)) {
+            *value += c - b;
+        }
+        Ok(self.closest(&query, k, &[a, b, c]))
+    }
+
+    /// Rows ranked by cosine similarity to `query`, highest first
+    fn closest(&self, query: &[f32], k: usize, exclude: &[&str]) -> Vec<(f32, String)> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        let mut scored: Vec<(f32, usize)> = self
+            .rows
+            .chunks_exact(self.dim.max(1))
+            .enumerate()
+            .filter(|(index, _)| !exclude.contains(&self.words[*index].as_str()))
+            .map(|(index, row)| (row.iter().zip(&query).map(|(x, y)| x * y).sum(), index))
+            .collect();
+        let by_score = |a: &(f32, usize), b: &(f32, usize)| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal);
+        // 词表可能很大，先选出前k个再排序
+        if k < scored.len() {
+            scored.select_nth_unstable_by(k, by_score);
+            scored.truncate(k);
+        }
+        scored.sort_by(by_score);
+        scored.into_iter().map(|(score, index)| (score, self.words[index].clone())).collect()
+    }
+}
+
+/// `WordMatrix` of the served model, built on first use after each model load;
+/// a model swapped in by `/reload` gets its own matrix
+#[derive(Debug, Default)]
+pub(crate) struct WordIndex {
+    built: Mutex<Option<(Weak<crate::ServedModel>, Arc<WordMatrix>)>>,
+}
+
+impl WordIndex {
+    pub(crate) fn matrix(&self, model: &Arc<crate::ServedModel>) -> Result<Arc<WordMatrix>, String> {
+        let mut built = self.built.lock().unwrap();
+        if let Some((owner, matrix)) = built.as_ref() {
+            // Weak让旧模型的地址不会被新模型复用
+            if Weak::as_ptr(owner) == Arc::as_ptr(model) {
+                return Ok(matrix.clone());
+            }
+        }
+        let matrix = Arc::new(WordMatrix::build(model)?);
+        *built = Some((Arc::downgrade(model), matrix.clone()));
+        Ok(matrix)
+    }
+}
+
+fn unit_vector(model: &FastText, word: &str) -> Result<Vec<f32>, String> {
+    let mut vector = model.get_word_vector(word)?;
+    normalize(&mut vector);
+    Ok(vector)
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WordMatrix;
+
+    #[test]
+    fn test_closest_ranks_by_cosine() {
+        let matrix = WordMatrix {
+            words: vec!["east".to_string(), "north".to_string(), "west".to_string()],
+            dim: 2,
+            rows: vec![1.0, 0.0, 0.0, 1.0, -1.0, 0.0],
+        };
+        let closest = matrix.closest(&[3.0, 1.0], 2, &[]);
+        assert_eq!(closest[0].1, "east");
+        assert!((closest[0].0 - 3.0 / 10f32.sqrt()).abs() < 1e-6);
+        assert_eq!(closest[1].1, "north");
+        // 排除的词不出现在结果里
+        let closest = matrix.closest(&[3.0, 1.0], 3, &["east"]);
+        assert_eq!(closest.iter().map(|(_, word)| word.as_str()).collect::<Vec<_>>(), vec!["north", "west"]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Raw model output for one text, before filtering and per-label thresholds
+pub(crate) type Prediction = (Vec<String>, Vec<f32>);
+
+/// Model version, text, k and threshold bits
+type Key = (u64, String, u32, u32);
+
+/// Bounded LRU of model predictions keyed by `(model version, text, k, threshold)`.
+/// The version is the model's checksum, so models sharing the cache never see each other's results.
+/// Only successful predictions are stored; `clear` drops everything on model reload
+#[derive(Debug)]
+pub(crate) struct PredictionCache {
+    capacity: usize,
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<Key, (Prediction, u64)>,
+    /// Last use tick -> key, the smallest tick is evicted first
+    order: BTreeMap<u64, Key>,
+    tick: u64,
+    /// Bumped by `clear` so predictions started before a reload aren't stored after it
+    generation: u64,
+}
+
+impl PredictionCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        PredictionCache {
+            capacity,
+            state: Mutex::new(State::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Cached prediction for the arguments, or the result of `predict` which is stored when it succeeds.
+    /// The model runs outside the lock so concurrent misses don't serialize
+    pub(crate) fn get_or_predict<E>(
+        &self,
+        version: u64,
+        text: &str,
+        k: u32,
+        threshold: f32,
+        predict: impl FnOnce() -> Result<Prediction, E>,
+    ) -> Result<Prediction, E> {
+        let key = (version, text.to_string(), k, threshold.to_bits());
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(prediction) = state.touch(&key) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(prediction);
+            }
+            state.generation
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let prediction = predict()?;
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            state.insert(key, prediction.clone(), self.capacity);
+        }
+        Ok(prediction)
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.order.clear();
+        state.generation += 1;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of lookups that had to run the model
+    #[cfg(test)]
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl State {
+    fn touch(&mut self, key: &Key) -> Option<Prediction> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (prediction, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        *used = tick;
+        self.order.insert(tick, key.clone());
+        Some(prediction.clone())
+    }
+
+    fn insert(&mut self, key: Key, prediction: Prediction, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (prediction, self.tick)) {
+            // 并发的两次未命中可能先后写入同一个key
+            self.order.remove(&used);
+        }
+        self.order.insert(self.tick, key);
+        while self.entries.len() > capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PredictionCache;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let version = 1;
+        let cache = PredictionCache::new(2);
+        let predict = |label: &str| -> Result<_, ()> { Ok((vec![label.to_string()], vec![1.0])) };
+        cache.get_or_predict(version, "a", 1, 0.0, || predict("a")).unwrap();
+        cache.get_or_predict(version, "b", 1, 0.0, || predict("b")).unwrap();
+        // a刚被用过，容量满时淘汰b
+        cache.get_or_predict(version, "a", 1, 0.0, || predict("stale")).unwrap();
+        cache.get_or_predict(version, "c", 1, 0.0, || predict("c")).unwrap();
+        assert_eq!(cache.len(), 2);
+        let (labels, _) = cache.get_or_predict(version, "a", 1, 0.0, || predict("stale")).unwrap();
+        assert_eq!(labels, vec!["a"]);
+        let (labels, _) = cache.get_or_predict(version, "b", 1, 0.0, || predict("new")).unwrap();
+        assert_eq!(labels, vec!["new"]);
+        // k不同是不同的key，失败的预测不缓存
+        assert!(cache.get_or_predict(version, "a", 2, 0.0, || Err(())).is_err());
+        assert_eq!((cache.hits(), cache.misses()), (2, 5));
+
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_keyed_by_model_version() {
+        let cache = PredictionCache::new(8);
+        let predict = |label: &str| -> Result<_, ()> { Ok((vec![label.to_string()], vec![1.0])) };
+        cache.get_or_predict(1, "a", 1, 0.0, || predict("old")).unwrap();
+        // 另一个模型对同一文本不会拿到前一个模型的结果
+        let (labels, _) = cache.get_or_predict(2, "a", 1, 0.0, || predict("new")).unwrap();
+        assert_eq!(labels, vec!["new"]);
+        let (labels, _) = cache.get_or_predict(1, "a", 1, 0.0, || predict("stale")).unwrap();
+        assert_eq!(labels, vec!["old"]);
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+}
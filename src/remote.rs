@@ -0,0 +1,135 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use fasttext::FastText;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+
+/// How long the presigned S3 URL stays valid, enough for a large model over a slow link
+const PRESIGN_TTL: Duration = Duration::from_secs(3600);
+
+/// Whether `location` names a model to download rather than a local file
+pub fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://") || location.starts_with("s3://")
+}
+
+/// Load a model from a local path, an `http(s)://` URL or `s3://bucket/key`.
+/// Remote models are downloaded to a temporary file that is removed once loaded
+pub fn load_model(location: &str) -> Result<FastText, String> {
+    let url = if location.starts_with("http://") || location.starts_with("https://") {
+        location.to_string()
+    } else if let Some(object) = location.strip_prefix("s3://") {
+        s3_url(object)?
+    } else {
+        let mut model = FastText::new();
+        model.load_model(location)?;
+        return Ok(model);
+    };
+    let path = download(&url, location)?;
+    let mut model = FastText::new();
+    let loaded = model.load_model(&path.to_string_lossy());
+    let _ = fs::remove_file(&path);
+    loaded.map_err(|e| format!("downloaded {} is not a valid model: {}", location, e))?;
+    Ok(model)
+}
+
+/// Presigned GET URL for `bucket/key`, using the standard `AWS_*` environment variables.
+/// Without credentials the request is anonymous, which works for public buckets
+fn s3_url(object: &str) -> Result<String, String> {
+    let (bucket, key) = match object.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => (bucket, key),
+        _ => return Err(format!("expected s3://bucket/key, got s3://{}", object)),
+    };
+    let region = env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| "us-east-1".to_string());
+    // 自定义endpoint（如MinIO）通常只支持path style
+    let (endpoint, style) = match env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => (endpoint, UrlStyle::Path),
+        Err(_) => (format!("https://s3.{}.amazonaws.com", region), UrlStyle::VirtualHost),
+    };
+    let endpoint = endpoint.parse().map_err(|e| format!("invalid S3 endpoint {}: {}", endpoint, e))?;
+    let bucket = Bucket::new(endpoint, style, bucket.to_string(), region)
+        .map_err(|e| format!("invalid S3 bucket {}: {}", bucket, e))?;
+    let credentials = Credentials::from_env();
+    Ok(bucket.get_object(credentials.as_ref(), key).sign(PRESIGN_TTL).to_string())
+}
+
+/// Fetch `url` into a temporary file, checking the status and that the whole body arrived
+fn download(url: &str, location: &str) -> Result<PathBuf, String> {
+    let response = ureq::get(url).call().map_err(|e| match e {
+        ureq::Error::Status(status, _) => format!("failed to fetch {}: HTTP {}", location, status),
+        e => format!("failed to fetch {}: {}", location, e),
+    })?;
+    let expected: Option<u64> = response.header("Content-Length").and_then(|len| len.parse().ok());
+    let file_name = location.rsplit('/').next().unwrap_or("model.bin");
+    let path = env::temp_dir().join(format!("fasttext-serving-{}-{}", std::process::id(), file_name));
+    let written = File::create(&path).and_then(|mut file| io::copy(&mut response.into_reader(), &mut file));
+    let error = match written {
+        Ok(0) => format!("downloaded {} is empty", location),
+        Ok(written) if expected.is_some_and(|expected| expected != written) => format!(
+            "download of {} is incomplete: got {} of {} bytes",
+            location,
+            written,
+            expected.unwrap_or_default()
+        ),
+        Ok(written) => {
+            log::info!("Downloaded {} bytes from {}", written, location);
+            return Ok(path);
+        }
+        Err(e) => format!("failed to download {}: {}", location, e),
+    };
+    let _ = fs::remove_file(&path);
+    Err(error)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_model, s3_url};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serve the cooking model for `/cooking.model.bin` and 404 for anything else
+    fn spawn_fixture_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let model = std::fs::read("models/cooking.model.bin").unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0; 1024];
+                let len = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..len]);
+                if request.starts_with("GET /cooking.model.bin ") {
+                    write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", model.len())
+                        .unwrap();
+                    stream.write_all(&model).unwrap();
+                } else {
+                    stream
+                        .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                        .unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_load_model_over_http() {
+        let addr = spawn_fixture_server();
+        let model = load_model(&format!("http://{}/cooking.model.bin", addr)).unwrap();
+        let local = load_model("models/cooking.model.bin").unwrap();
+        assert_eq!(model.get_dimension(), local.get_dimension());
+        let text = "Which baking dish is best to bake a banana bread?\n";
+        let labels = |model: &fasttext::FastText| -> Vec<String> {
+            model.predict(text, 2, 0.0).unwrap().into_iter().map(|pred| pred.label).collect()
+        };
+        assert_eq!(labels(&model), labels(&local));
+
+        let err = load_model(&format!("http://{}/missing.bin", addr)).unwrap_err();
+        assert!(err.contains("HTTP 404"), "{}", err);
+        assert!(s3_url("bucket-only").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use fasttext::FastText;
+
+use crate::metrics::Metrics;
+
+/// Batches waiting for the shadow model; once full, new batches are dropped instead of queued
+const SHADOW_QUEUE_SIZE: usize = 64;
+
+/// One prepared text with the `k` and threshold it was predicted with and the primary's top label,
+/// `None` when no label passed the threshold
+pub(crate) type ShadowItem = (String, u32, f32, Option<String>);
+
+/// A `/predict` batch the primary has answered, for the shadow worker to compare
+pub(crate) struct ShadowBatch {
+    pub(crate) items: Vec<ShadowItem>,
+    pub(crate) max_text_length: usize,
+    pub(crate) label_prefix: Option<String>,
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+/// Candidate model fed a copy of each `/predict` batch for `--shadow-model`.
+/// Its predictions are only compared with the primary's, never returned to clients.
+/// A single worker thread owns the model, so shadow traffic never takes more than one core
+pub(crate) struct ShadowModel {
+    path: String,
+    queue: SyncSender<ShadowBatch>,
+}
+
+impl std::fmt::Debug for ShadowModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ShadowModel").field("path", &self.path).finish_non_exhaustive()
+    }
+}
+
+impl ShadowModel {
+    /// Start the worker comparing batches with `model`; it stops once the `ShadowModel` is dropped
+    pub(crate) fn new(path: String, model: FastText) -> Self {
+        let (queue, batches) = mpsc::sync_channel(SHADOW_QUEUE_SIZE);
+        let worker_path = path.clone();
+        std::thread::Builder::new()
+            .name("shadow-model".to_string())
+            .spawn(move || run_worker(&worker_path, &model, batches))
+            .expect("Failed to start the shadow model worker");
+        ShadowModel { path, queue }
+    }
+
+    /// Queue a batch for comparison without waiting on it; dropped when the worker is behind
+    pub(crate) fn submit(&self, batch: ShadowBatch) {
+        match self.queue.try_send(batch) {
+            Ok(()) => {}
+            Err(TrySendError::Full(batch)) => {
+                log::debug!("Shadow model {} is behind, dropped a batch of {} texts", self.path, batch.items.len());
+            }
+            Err(TrySendError::Disconnected(_)) => log::warn!("Shadow model {} worker has stopped", self.path),
+        }
+    }
+}
+
+fn run_worker(path: &str, model: &FastText, batches: Receiver<ShadowBatch>) {
+    for batch in batches {
+        compare(path, model, &batch);
+    }
+}
+
+/// Predict each text of `batch` with the shadow and count the texts whose top label differs from the primary's.
+/// Texts the shadow fails on aren't compared
+fn compare(path: &str, model: &FastText, batch: &ShadowBatch) {
+    let label_prefix = batch.label_prefix.as_deref();
+    let mut disagreements = 0;
+    for (text, k, threshold, expected) in &batch.items {
+        let actual = match crate::predict_one_safe(model, text, *k, *threshold, batch.max_text_length, label_prefix, false)
+        {
+            Ok((labels, _)) => labels.into_iter().next(),
+            Err(_) => continue,
+        };
+        batch.metrics.shadow_comparisons.inc();
+        if *expected != actual {
+            disagreements += 1;
+            batch.metrics.shadow_disagreements.inc();
+        }
+    }
+    if disagreements > 0 {
+        log::info!("Shadow model {} disagreed on {} of {} texts", path, disagreements, batch.items.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compare, ShadowBatch, ShadowItem, ShadowModel};
+    use crate::metrics::Metrics;
+    use fasttext::{Args, FastText, LossName, ModelName};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn load_model() -> FastText {
+        let mut fasttext = FastText::new();
+        fasttext
+            .load_model("models/cooking.model.bin")
+            .expect("Failed to load fastText model");
+        fasttext
+    }
+
+    /// The texts with the primary's own top labels, as `/predict` submits them
+    fn primary_items() -> Vec<ShadowItem> {
+        let primary = load_model();
+        ["Which baking dish is best to bake a banana bread?", "Why not put knives in the dishwasher?"]
+            .iter()
+            .map(|text| {
+                let (labels, _) = crate::predict_one_safe(&primary, text, 1, 0.0, 1000, None, false).unwrap();
+                (text.to_string(), 1, 0.0, labels.into_iter().next())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_disagreements() {
+        let metrics = Arc::new(Metrics::new());
+        let batch = ShadowBatch {
+            items: primary_items(),
+            max_text_length: 1000,
+            label_prefix: None,
+            metrics: metrics.clone(),
+        };
+        // 同一个模型作为影子，不会有分歧
+        compare("same", &load_model(), &batch);
+        assert_eq!(metrics.shadow_comparisons.get(), 2);
+        assert_eq!(metrics.shadow_disagreements.get(), 0);
+
+        // 只有一个标签的小模型，对每条文本的预测都与主模型不同
+        let dir = std::env::temp_dir().join(format!("fasttext-shadow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("train.txt");
+        std::fs::write(&input, "__label__shadow banana bread\n__label__shadow knives dishwasher\n").unwrap();
+        let mut args = Args::new();
+        args.set_input(input.to_str().unwrap()).unwrap();
+        args.set_model(ModelName::SUP);
+        args.set_loss(LossName::SOFTMAX);
+        args.set_min_count(1);
+        args.set_epoch(1);
+        args.set_thread(1);
+        args.set_verbose(0);
+        let mut candidate = FastText::new();
+        candidate.train(&args).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        compare("candidate", &candidate, &batch);
+        assert_eq!(metrics.shadow_comparisons.get(), 4);
+        assert_eq!(metrics.shadow_disagreements.get(), 2);
+    }
+
+    #[test]
+    fn test_worker_compares_submitted_batches() {
+        let metrics = Arc::new(Metrics::new());
+        let shadow = ShadowModel::new("same".to_string(), load_model());
+        shadow.submit(ShadowBatch {
+            items: primary_items(),
+            max_text_length: 1000,
+            label_prefix: None,
+            metrics: metrics.clone(),
+        });
+        // 比较在后台线程上进行
+        let started = Instant::now();
+        while metrics.shadow_comparisons.get() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(10), "shadow worker never compared the batch");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(metrics.shadow_disagreements.get(), 0);
+    }
+}
@@ -0,0 +1,57 @@
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpRequest;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Export handler spans to the OTLP collector at `endpoint`, must be called inside a tokio runtime
+pub fn init(endpoint: &str) -> Result<(), String> {
+    let resource = Resource::new(vec![KeyValue::new("service.name", env!("CARGO_PKG_NAME"))]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(trace::config().with_resource(resource))
+        // actix的System是单线程运行时，批量导出放在独立线程里
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)
+        .map_err(|e| e.to_string())?;
+    let subscriber = tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| e.to_string())
+}
+
+/// Flush pending spans; blocks until the exporter finishes
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Span for one request, continuing the caller's trace when it sent a `traceparent` header
+pub fn request_span(name: &'static str, req: &HttpRequest, request_id: &str) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        otel.name = name,
+        request_id = request_id,
+        batch_size = tracing::field::Empty,
+        k = tracing::field::Empty,
+    );
+    span.set_parent(parent_context(req.headers()));
+    span
+}
+
+fn parent_context(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
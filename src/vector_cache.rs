@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// Precomputed `id -> vector` table loaded from a `.vec`-style text file:
+/// one `id v1 v2 ...` entry per line, with an optional `count dim` header line.
+/// Every vector must have the served model's dimension.
+#[derive(Debug, Default)]
+pub(crate) struct VectorCache {
+    vectors: HashMap<String, Vec<f32>>,
+}
+
+impl VectorCache {
+    pub(crate) fn load(path: &str, dim: usize) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?), dim)
+    }
+
+    pub(crate) fn from_reader<R: BufRead>(reader: R, dim: usize) -> io::Result<Self> {
+        let mut vectors = HashMap::new();
+        for (lineno, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let id = match parts.next() {
+                Some(id) => id,
+                None => continue,
+            };
+            let values = parts
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| invalid_data(format!("line {}: {}", lineno + 1, e)))?;
+            // fastText导出的.vec文件首行是 "数量 维度"
+            if lineno == 0 && values.len() == 1 && id.parse::<usize>().is_ok() {
+                continue;
+            }
+            // 维度不同的向量无法与模型实时计算的结果混用
+            if values.len() != dim {
+                return Err(invalid_data(format!(
+                    "line {}: expected {} values, the model's dimension, got {}",
+                    lineno + 1,
+                    dim,
+                    values.len()
+                )));
+            }
+            vectors.insert(id.to_string(), values);
+        }
+        Ok(VectorCache { vectors })
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&Vec<f32>> {
+        self.vectors.get(id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.vectors.len()
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}